@@ -1,12 +1,54 @@
-use super::job::Job;
+use super::job::{ Job, JobProgress };
 use serde_json::Value;
 
 pub trait ExecutionContext {
     fn result(&mut self, job: &Job, is_async: bool) -> Option<Result<Option<Value>, Value>>;
+    // stops a job that has not produced a result yet, returning the errors to record for it, or None if it is still
+    // stopping, in which case `result` returns them once it has
+    fn cancel(&mut self, job: &Job) -> Option<Value>;
+    // output written by the job since the last call, which is appended to its logs while it runs
+    fn take_logs(&mut self) -> Option<String> {
+        return None;
+    }
+    // progress reported by the job since the last call, which is set on it while it runs
+    fn take_progress(&mut self) -> Option<JobProgress> {
+        return None;
+    }
 }
 
 pub trait Executor {
     type Context: ExecutionContext;
 
     fn execute(&mut self, job: &Job) -> Self::Context;
-}
\ No newline at end of file
+}
+
+pub type ExecutionContextRef = Box<dyn ExecutionContext>;
+
+// an `Executor` with its context boxed, so executors with different contexts can be registered together
+pub trait DynExecutor {
+    fn execute_boxed(&mut self, job: &Job) -> ExecutionContextRef;
+}
+
+impl<E: Executor> DynExecutor for E where E::Context: 'static {
+    fn execute_boxed(&mut self, job: &Job) -> ExecutionContextRef {
+        return Box::new(self.execute(job));
+    }
+}
+
+impl ExecutionContext for ExecutionContextRef {
+    fn result(&mut self, job: &Job, is_async: bool) -> Option<Result<Option<Value>, Value>> {
+        return (**self).result(job, is_async);
+    }
+
+    fn cancel(&mut self, job: &Job) -> Option<Value> {
+        return (**self).cancel(job);
+    }
+
+    fn take_logs(&mut self) -> Option<String> {
+        return (**self).take_logs();
+    }
+
+    fn take_progress(&mut self) -> Option<JobProgress> {
+        return (**self).take_progress();
+    }
+}
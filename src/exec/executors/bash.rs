@@ -1,37 +1,308 @@
 use std::process::*;
 use crate::exec::executor::*;
 use std::env;
-use crate::exec::job::Job;
+use crate::exec::job::{ Job, JobProgress };
 use log::*;
 use serde_json::{Value, json};
 use regex::Regex;
 use serde_json::map::Map;
+use std::os::unix::process::CommandExt;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::{ Duration, Instant };
+use std::io::{ ErrorKind, Read };
+use std::fs;
+use std::path::PathBuf;
 
 pub struct BashExecutor {
 
 }
 
+// output read from a process's pipes as it is written
+#[derive(Default)]
+pub struct CapturedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub unlogged: Vec<u8>, // stdout and stderr, interleaved, not yet taken for the job's logs
+}
+
 pub struct BashExecutorContext {
     pub internal_failure: bool,
     pub handle: Option<Child>,
+    pub pgid: Option<i32>, // the process group, kept after the process exits, as anything it spawned may still be running
+    pub exit_status: Option<ExitStatus>, // set once the process exits, while its pipes may still be open
+    pub termination: Option<GroupTermination>, // set once the process group is being stopped
+    // non-blocking, read whenever the context is polled, until closed
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    pub output: CapturedOutput,
     pub timeout: Option<u64>,
+    pub started_at: Instant,
+    pub result_file: Option<PathBuf>,
+    pub progress_file: Option<PathBuf>,
+    pub reported_progress: String, // the progress file's contents when last read
+    pub final_progress: Option<JobProgress>, // read from the progress file as the job finished, until taken
+}
+
+// how long a timed out or cancelled process group has to exit after SIGTERM before being sent SIGKILL
+const TIMEOUT_GRACE_PERIOD_MS: u64 = 5000;
+// how often a process is checked for completion and output when waited for
+const TIMEOUT_POLL_MS: u64 = 10;
+// the environment variable holding the path a job may write its structured output to, as a JSON document
+pub const RESULT_FILE_ENV: &str = "SYNCHRONY_RESULT_FILE";
+// prefixes a JSON document on the final line of a job's stdout, used as its structured output if it wrote no result file
+pub const RESULT_LINE_MARKER: &str = "SYNCHRONY_RESULT: ";
+// the environment variable holding the path a job may write its progress to, replacing the file's contents each time
+pub const PROGRESS_FILE_ENV: &str = "SYNCHRONY_PROGRESS_FILE";
+
+// reads at most one byte past the limit, so an oversized result file is never read into memory whole
+fn read_result_file(path: &PathBuf, limit: Option<usize>) -> std::io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let mut written = vec![];
+    match limit {
+        Some(limit) => {
+            file.take(limit as u64 + 1).read_to_end(&mut written)?;
+            if written.len() > limit {
+                let bytes = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(written.len() as u64);
+                return Err(std::io::Error::other(format!("{} bytes is over the output limit of {} bytes", bytes, limit)));
+            }
+        },
+        None => {
+            file.read_to_end(&mut written)?;
+        },
+    }
+    return Ok(written);
+}
+
+// the result file of a job, unique to it on this node
+pub fn result_file_path(job: &Job) -> PathBuf {
+    return env::temp_dir().join(format!("synchrony_result_{}.json", job.uuid.hyphenated()));
+}
+
+pub fn progress_file_path(job: &Job) -> PathBuf {
+    return env::temp_dir().join(format!("synchrony_progress_{}", job.uuid.hyphenated()));
+}
+
+// points the command at the job's result and progress files, removing any left by an earlier attempt
+pub fn set_job_files(builder: &mut Command, job: &Job) {
+    for (name, path) in [(RESULT_FILE_ENV, result_file_path(job)), (PROGRESS_FILE_ENV, progress_file_path(job))] {
+        let _ = fs::remove_file(&path);
+        builder.env(name, path);
+    }
+}
+
+// either a JSON object like `{"percent": 42, "message": "processed 420/1000 rows"}`, or plain text used as the message, with any leading percentage as the percent
+pub fn parse_progress(reported: &str) -> JobProgress {
+    if let Ok(progress) = serde_json::from_str::<JobProgress>(reported) {
+        return progress;
+    }
+    let percent = reported.find('%').and_then(|end| reported[..end].trim().parse::<f64>().ok());
+    return JobProgress { percent: percent, message: Some(reported.to_string()), updated_at: 0 };
+}
+
+impl BashExecutorContext {
+    pub fn failed(timeout: Option<u64>) -> BashExecutorContext {
+        BashExecutorContext { internal_failure: true, handle: None, pgid: None, exit_status: None, termination: None, stdout: None, stderr: None, output: CapturedOutput::default(), timeout: timeout, started_at: Instant::now(), result_file: None, progress_file: None, reported_progress: String::new(), final_progress: None }
+    }
+
+    // the job's files must have been set on the command with `set_job_files`
+    pub fn started(mut handle: Child, timeout: Option<u64>, job: &Job) -> BashExecutorContext {
+        let stdout = handle.stdout.take().filter(set_nonblocking);
+        let stderr = handle.stderr.take().filter(set_nonblocking);
+        let pgid = handle.id() as i32;
+        BashExecutorContext { internal_failure: false, handle: Some(handle), pgid: Some(pgid), exit_status: None, termination: None, stdout: stdout, stderr: stderr, output: CapturedOutput::default(), timeout: timeout, started_at: Instant::now(), result_file: Some(result_file_path(job)), progress_file: Some(progress_file_path(job)), reported_progress: String::new(), final_progress: None }
+    }
+
+    // reads whatever output the process has written since the last call, without blocking
+    fn drain(&mut self) {
+        if drain_pipe(&mut self.stdout, &mut self.output.stdout, &mut self.output.unlogged) {
+            self.stdout = None;
+        }
+        if drain_pipe(&mut self.stderr, &mut self.output.stderr, &mut self.output.unlogged) {
+            self.stderr = None;
+        }
+    }
+
+    // the progress in the progress file, if it has changed since last read
+    fn read_progress(&mut self) -> Option<JobProgress> {
+        let reported = fs::read_to_string(self.progress_file.as_ref()?).ok()?;
+        let reported = reported.trim();
+        if reported.is_empty() || reported == self.reported_progress {
+            return None;
+        }
+        self.reported_progress = reported.to_string();
+        return Some(parse_progress(reported));
+    }
+
+    // keeps the last progress to be taken, then removes the progress file
+    fn close_progress(&mut self) {
+        self.final_progress = self.read_progress().or(self.final_progress.take());
+        if let Some(progress_file) = self.progress_file.take() {
+            let _ = fs::remove_file(progress_file);
+        }
+    }
+
+    // the JSON document the job wrote to its result file, or else on its final stdout line, removing the result file
+    // documents over the job type's output limit are rejected unparsed, as truncating them would leave invalid JSON
+    fn structured_output(&mut self, stdout: &str, job: &Job) -> Option<Result<Value, String>> {
+        let limit = job.job_type.as_ref().unwrap().output_limit.as_ref().map(|limit| limit.head_bytes + limit.tail_bytes);
+        if let Some(result_file) = self.result_file.take() {
+            let written = read_result_file(&result_file, limit);
+            let _ = fs::remove_file(&result_file);
+            match written {
+                Ok(written) => return Some(serde_json::from_slice(&written).map_err(|e| format!("invalid JSON in result file: {}", e))),
+                Err(e) if e.kind() == ErrorKind::NotFound => {},
+                Err(e) => return Some(Err(format!("failed to read result file: {}", e))),
+            }
+        }
+        let line = stdout.lines().rev().find(|line| !line.trim().is_empty())?;
+        let document = line.strip_prefix(RESULT_LINE_MARKER)?;
+        if let Some(limit) = limit.filter(|limit| document.len() > *limit) {
+            return Some(Err(format!("result line of {} bytes is over the output limit of {} bytes", document.len(), limit)));
+        }
+        return Some(serde_json::from_str(document).map_err(|e| format!("invalid JSON in result line: {}", e)));
+    }
+
+    fn timed_out(&self) -> bool {
+        return self.timeout.map(|timeout| self.started_at.elapsed() >= Duration::from_millis(timeout)).unwrap_or(false);
+    }
+
+    fn kill(&mut self, job: &Job) {
+        warn!("Job '{}', job type '{}' / '{}' timed out after {} ms, terminating", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), self.started_at.elapsed().as_millis());
+        self.terminate("timeout");
+    }
+
+    // sends the process group SIGTERM, its result is reported once it has exited, see `terminated`
+    fn terminate(&mut self, kind: &str) {
+        let after_ms = self.started_at.elapsed().as_millis() as u64;
+        self.termination = Some(GroupTermination::start(self.pgid.unwrap(), kind, after_ms));
+    }
+
+    // the errors to record for a job whose process group has been stopped, with the output written before it was, or None while it is still stopping
+    fn terminated(&mut self) -> Option<Value> {
+        if !self.termination.as_mut().unwrap().poll(&mut self.handle) {
+            return None;
+        }
+        self.drain();
+        if let Some(result_file) = self.result_file.take() {
+            let _ = fs::remove_file(result_file);
+        }
+        self.close_progress();
+        let termination = self.termination.as_ref().unwrap();
+        return Some(json!({
+            "kind": termination.kind,
+            "after_ms": termination.after_ms,
+            "stdout": String::from_utf8_lossy(self.output.stdout.as_slice()),
+            "stderr": String::from_utf8_lossy(self.output.stderr.as_slice()),
+        }));
+    }
+}
+
+// a process group being stopped, which is sent SIGTERM, then SIGKILL once the grace period has passed.
+// it is polled rather than waited for, so a node keeps running its other jobs meanwhile
+pub struct GroupTermination {
+    pub kind: String,
+    pub after_ms: u64,
+    pgid: i32,
+    term_sent_at: Instant,
+    kill_sent: bool,
+}
+
+impl GroupTermination {
+    pub fn start(pgid: i32, kind: &str, after_ms: u64) -> GroupTermination {
+        signal_group(pgid, libc::SIGTERM);
+        return GroupTermination { kind: kind.to_string(), after_ms: after_ms, pgid: pgid, term_sent_at: Instant::now(), kill_sent: false };
+    }
+
+    // reaps the group's leader once it exits, returning true once the whole group has exited, or has been sent SIGKILL
+    pub fn poll(&mut self, leader: &mut Option<Child>) -> bool {
+        if leader.as_mut().map(|handle| !matches!(handle.try_wait(), Ok(None))).unwrap_or(false) {
+            *leader = None;
+        }
+        if leader.is_none() && !group_exists(self.pgid) {
+            return true;
+        }
+        if self.term_sent_at.elapsed() < Duration::from_millis(TIMEOUT_GRACE_PERIOD_MS) {
+            return false;
+        }
+        if !self.kill_sent {
+            signal_group(self.pgid, libc::SIGKILL);
+            self.kill_sent = true;
+        }
+        // SIGKILL cannot be ignored, so only the leader is waited for, as the others are reaped by init
+        return leader.is_none();
+    }
+}
+
+// the child must be spawned as the leader of its own process group, so this also reaches anything it spawned
+pub fn signal_group(pgid: i32, signal: i32) {
+    unsafe {
+        libc::kill(-pgid, signal);
+    }
+}
+
+fn group_exists(pgid: i32) -> bool {
+    unsafe {
+        return libc::kill(-pgid, 0) == 0;
+    }
+}
+
+// sends SIGTERM to the child's process group, then SIGKILL after the grace period, and waits for the child to exit
+pub fn terminate_process_group(handle: &mut Child) {
+    let pgid = handle.id() as i32;
+    signal_group(pgid, libc::SIGTERM);
+    let grace_started_at = Instant::now();
+    while grace_started_at.elapsed() < Duration::from_millis(TIMEOUT_GRACE_PERIOD_MS) {
+        match handle.try_wait() {
+            Ok(None) => thread::sleep(Duration::from_millis(TIMEOUT_POLL_MS)),
+            _ => break,
+        }
+    }
+    signal_group(pgid, libc::SIGKILL);
+    let _ = handle.wait();
 }
 
 impl ExecutionContext for BashExecutorContext {
     fn result(&mut self, job: &Job, is_async: bool) -> Option<Result<Option<Value>, Value>> {
-        //TODO: handle timeouts, async?
         if self.internal_failure {
             return Some(Err(Value::Null));
         }
-        let handle = self.handle.as_mut().unwrap();
-        let status = if is_async {
-            let waited = handle.try_wait();
-            if waited.is_ok() && waited.as_ref().unwrap().is_none() {
+        // the pipes are drained while waiting, so a process writing more than a pipe buffer never blocks on them
+        let status = loop {
+            self.drain();
+            if self.termination.is_some() {
+                if let Some(errors) = self.terminated() {
+                    return Some(Err(errors));
+                }
+            } else if self.handle.is_none() {
+                // the process exited on an earlier poll, its pipes are read until closed, which may be after it exits
+                if self.stdout.is_none() && self.stderr.is_none() {
+                    break Ok(self.exit_status.unwrap());
+                }
+                // anything left running in its process group is still subject to the timeout
+                if self.timed_out() {
+                    self.kill(job);
+                    continue;
+                }
+            } else {
+                let waited = self.handle.as_mut().unwrap().try_wait();
+                if waited.is_err() {
+                    break waited.map(|i| i.unwrap());
+                }
+                if let Some(status) = waited.unwrap() {
+                    self.handle = None;
+                    self.exit_status = Some(status);
+                    continue;
+                }
+                if self.timed_out() {
+                    self.kill(job);
+                    continue;
+                }
+            }
+            if is_async {
                 return None;
             }
-            waited.map(|i| i.unwrap())
-        } else {
-            handle.wait()
+            thread::sleep(Duration::from_millis(TIMEOUT_POLL_MS));
         };
         return match status {
             Err(e) => {
@@ -39,15 +310,93 @@ impl ExecutionContext for BashExecutorContext {
                 Some(Err(Value::Null))
             }
             Ok(status) => {
+                self.handle = None;
+                let stdout = String::from_utf8_lossy(self.output.stdout.as_slice()).to_string();
+                let stderr = String::from_utf8_lossy(self.output.stderr.as_slice()).to_string();
+                let structured_output = self.structured_output(&stdout, job);
+                self.close_progress();
                 let mut output = Map::new();
-                let process_out = self.handle.take().unwrap().wait_with_output().expect("wait_with_output failed after wait succeeded");
-                output.insert("stdout".to_string(), Value::String(String::from_utf8_lossy(process_out.stdout.as_slice()).to_string()));
-                output.insert("stderr".to_string(), Value::String(String::from_utf8_lossy(process_out.stderr.as_slice()).to_string()));
+                output.insert("stdout".to_string(), Value::String(stdout));
+                output.insert("stderr".to_string(), Value::String(stderr));
                 output.insert("exit_code".to_string(), status.code().map(|code| json!(code)).unwrap_or(Value::Null));
+                match structured_output {
+                    Some(Ok(value)) => {
+                        output.insert("output".to_string(), value);
+                    },
+                    Some(Err(message)) => {
+                        warn!("Invalid structured output in job '{}', job type '{}' / '{}': {}", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), message);
+                        output.insert("kind".to_string(), Value::String("invalid_result".to_string()));
+                        output.insert("message".to_string(), Value::String(message));
+                        return Some(Err(Value::Object(output)));
+                    },
+                    None => {},
+                }
                 Some(Ok(Some(Value::Object(output))))
             }
         }
     }
+
+    fn cancel(&mut self, job: &Job) -> Option<Value> {
+        if self.pgid.is_none() {
+            return Some(json!({ "kind": "cancelled" }));
+        }
+        if self.termination.is_none() {
+            info!("Job '{}', job type '{}' / '{}' cancelled after {} ms, terminating", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), self.started_at.elapsed().as_millis());
+            self.terminate("cancelled");
+        }
+        return None;
+    }
+
+    fn take_logs(&mut self) -> Option<String> {
+        self.drain();
+        return take_utf8(&mut self.output.unlogged);
+    }
+
+    fn take_progress(&mut self) -> Option<JobProgress> {
+        return self.final_progress.take().or_else(|| self.read_progress());
+    }
+}
+
+fn set_nonblocking<P: AsRawFd>(pipe: &P) -> bool {
+    let fd = pipe.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        return flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) >= 0;
+    }
+}
+
+// reads everything available from a non-blocking pipe into `captured` and `unlogged`, returning true once it is closed
+fn drain_pipe<R: Read>(pipe: &mut Option<R>, captured: &mut Vec<u8>, unlogged: &mut Vec<u8>) -> bool {
+    let pipe = match pipe.as_mut() {
+        Some(pipe) => pipe,
+        None => return false,
+    };
+    let mut buf = [0; 8192];
+    loop {
+        match pipe.read(&mut buf) {
+            Ok(0) => return true,
+            Ok(read) => {
+                captured.extend_from_slice(&buf[..read]);
+                unlogged.extend_from_slice(&buf[..read]);
+            },
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+// takes the bytes as a string, leaving a character split across reads to be taken once the rest of it is read
+pub fn take_utf8(bytes: &mut Vec<u8>) -> Option<String> {
+    let valid = match std::str::from_utf8(bytes) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => bytes.len(),
+    };
+    if valid == 0 {
+        return None;
+    }
+    let taken: Vec<u8> = bytes.drain(..valid).collect();
+    return Some(String::from_utf8_lossy(&taken).to_string());
 }
 
 impl Executor for BashExecutor {
@@ -63,13 +412,13 @@ impl Executor for BashExecutor {
         let timeout = job.job_type.as_ref().unwrap().timeout;
         if meta_command.is_none() || !(meta_command.unwrap().is_string() || meta_command.unwrap().is_array()) {
             error!("No command found in job type in bash execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated());
-            return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };
+            return BashExecutorContext::failed(timeout);
         }
         let command = job.arguments.get("command");
         let environment = job.arguments.get("environment");
         if command.is_some() && !(command.unwrap().is_string() || command.unwrap().is_array()) {
             error!("Invalid command arguments found in job in bash execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated());
-            return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };
+            return BashExecutorContext::failed(timeout);
         }
         let mut environment = match environment {
             None => Map::new(),
@@ -97,7 +446,8 @@ impl Executor for BashExecutor {
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
             .arg("-c")
-            .stdin(Stdio::null());
+            .stdin(Stdio::null())
+            .process_group(0);
         environment.iter().for_each(|(key, value)| {
             match value {
                 Value::String(s) => {
@@ -113,9 +463,9 @@ impl Executor for BashExecutor {
             let arg = match unwrapped {
                 Value::String(s) => s.clone(),
                 Value::Array(arr) => {
-                    if arr.iter().any({ |arg| !arg.is_string()}) {
+                    if arr.iter().any(|arg| !arg.is_string()) {
                         error!("Invalid non-string argument in arguments for bash command for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated());
-                        return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };
+                        return BashExecutorContext::failed(timeout);
                     }
                     arr.iter().map({ |arg| match arg {
                         Value::String(s) => format!("\"{}\"", &SAFE_ARG_REGEX.replace_all(s, "\\$0")),
@@ -127,33 +477,25 @@ impl Executor for BashExecutor {
             total_command.push(arg);
         }
         builder.arg(total_command.join(" "));
+        set_job_files(&mut builder, job);
         let handle = builder.spawn();
         if handle.is_err() {
             error!("Failed to spawn bash in bash execution for job '{}', job type '{}' / '{}': {:?}", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), handle.unwrap_err());
-            return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };
+            return BashExecutorContext::failed(timeout);
         }
-        return BashExecutorContext { internal_failure: false, handle: handle.ok(), timeout: timeout }
+        return BashExecutorContext::started(handle.unwrap(), timeout, job)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use uuid::Uuid;
-    use crate::exec::job_type::JobType;
-    use std::collections::HashMap;
+    use crate::exec::executors::tests as executor_tests;
+    use crate::exec::job_type::{ JobType, OutputLimit };
     use serde_json::Number;
 
     fn make_job_type(command: Value, environment: Option<Map<String, Value>>) -> JobType {
-        let mut job_type = JobType {
-            executor: "bash".to_string(),
-            name: "test".to_string(),
-            node_type: "default".to_string(),
-            timeout: None,
-            unique: false,
-            uuid: Uuid::new_v4(),
-            metadata: HashMap::new(),
-        };
+        let mut job_type = executor_tests::make_job_type("bash");
         job_type.metadata.insert("command".to_string(), command);
         if environment.is_some() {
             job_type.metadata.insert("environment".to_string(), Value::Object(environment.unwrap()));
@@ -162,18 +504,7 @@ mod tests {
     }
 
     fn make_job(job_type: &JobType, command: Option<Value>, environment: Option<Map<String, Value>>) -> Job {
-        let mut job = Job {
-            uuid: Uuid::new_v4(),
-            job_type_uuid: job_type.uuid,
-            job_type: Some(job_type.clone()),
-            arguments: HashMap::new(),
-            executing_node: None,
-            enqueued_at: None,
-            started_at: None,
-            ended_at: None,
-            results: None,
-            errors: None,
-        };
+        let mut job = executor_tests::make_job(job_type);
         if command.is_some() {
             job.arguments.insert("command".to_string(), command.unwrap());
         }
@@ -332,4 +663,260 @@ mod tests {
         assert_eq!(result, Some(Ok(Some(Value::Object(output)))));
     }
 
+    #[test]
+    fn can_finish_before_timeout() {
+        let mut executor = BashExecutor {};
+        let mut job_type = make_job_type(Value::String("echo 'test'".to_string()), None);
+        job_type.timeout = Some(5000);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let result = context.result(&job, false);
+        let mut output = Map::new();
+        output.insert("stdout".to_string(), Value::String("test\n".to_string()));
+        output.insert("stderr".to_string(), Value::String("".to_string()));
+        output.insert("exit_code".to_string(), Value::Number(Number::from(0)));
+        assert_eq!(result, Some(Ok(Some(Value::Object(output)))));
+    }
+
+    #[test]
+    fn can_time_out_command() {
+        let mut executor = BashExecutor {};
+        let mut job_type = make_job_type(Value::String("sleep 10 & sleep 10; echo 'test'".to_string()), None);
+        job_type.timeout = Some(200);
+        let job = make_job(&job_type, None, None);
+        let started_at = Instant::now();
+        let mut context = executor.execute(&job);
+        let result = context.result(&job, false);
+        assert!(started_at.elapsed() < Duration::from_millis(5000));
+        let errors = match result {
+            Some(Err(errors)) => errors,
+            _ => panic!("expected timeout error, got {:?}", result),
+        };
+        assert_eq!(errors["kind"], Value::String("timeout".to_string()));
+        assert!(errors["after_ms"].as_u64().unwrap() >= 200);
+    }
+
+    #[test]
+    fn can_keep_output_of_timed_out_command() {
+        let mut executor = BashExecutor {};
+        // more than a pipe buffer, which must not block the job before it times out
+        let mut job_type = make_job_type(Value::String("echo 'partial'; echo 'err' 1>&2; head -c 100000 /dev/zero | tr '\\0' a; sleep 10".to_string()), None);
+        job_type.timeout = Some(500);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert_eq!(errors["kind"], Value::String("timeout".to_string()));
+        assert_eq!(errors["stdout"], Value::String(format!("partial\n{}", "a".repeat(100000))));
+        assert_eq!(errors["stderr"], Value::String("err\n".to_string()));
+    }
+
+    #[test]
+    fn can_time_out_async_command() {
+        let mut executor = BashExecutor {};
+        let mut job_type = make_job_type(Value::String("sleep 10".to_string()), None);
+        job_type.timeout = Some(100);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        assert_eq!(context.result(&job, true), None);
+        thread::sleep(Duration::from_millis(200));
+        // the first poll after the timeout sends SIGTERM, a later one finds the process exited
+        let polled_at = Instant::now();
+        let result = loop {
+            if let Some(result) = context.result(&job, true) {
+                break result;
+            }
+            assert!(polled_at.elapsed() < Duration::from_millis(1000), "job was not terminated");
+            thread::sleep(Duration::from_millis(10));
+        };
+        match result {
+            Err(errors) => assert_eq!(errors["kind"], Value::String("timeout".to_string())),
+            result => panic!("expected timeout error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn can_capture_large_async_output() {
+        let mut executor = BashExecutor {};
+        // more than a pipe buffer on both pipes, which would block the process if they were only read once it exits
+        let job_type = make_job_type(Value::String("head -c 1000000 /dev/zero | tr '\\0' a; head -c 200000 /dev/zero | tr '\\0' b 1>&2".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let started_at = Instant::now();
+        let result = loop {
+            if let Some(result) = context.result(&job, true) {
+                break result;
+            }
+            assert!(started_at.elapsed() < Duration::from_secs(5), "job blocked writing its output");
+            thread::sleep(Duration::from_millis(10));
+        };
+        let results = result.unwrap().unwrap();
+        assert_eq!(results["stdout"], Value::String("a".repeat(1000000)));
+        assert_eq!(results["stderr"], Value::String("b".repeat(200000)));
+        assert_eq!(results["exit_code"], Value::Number(Number::from(0)));
+    }
+
+    #[test]
+    fn can_cancel_command() {
+        let mut executor = BashExecutor {};
+        let job_type = make_job_type(Value::String("sleep 10 & sleep 10".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let started_at = Instant::now();
+        let mut context = executor.execute(&job);
+        assert_eq!(context.result(&job, true), None);
+        assert_eq!(context.cancel(&job), None);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert!(started_at.elapsed() < Duration::from_millis(5000));
+        assert_eq!(errors["kind"], Value::String("cancelled".to_string()));
+    }
+
+    #[test]
+    fn can_time_out_command_after_it_exits() {
+        let mut executor = BashExecutor {};
+        // the background process keeps the pipes open after the shell exits
+        let mut job_type = make_job_type(Value::String("sleep 999 & echo 'test'".to_string()), None);
+        job_type.timeout = Some(200);
+        let job = make_job(&job_type, None, None);
+        let started_at = Instant::now();
+        let mut context = executor.execute(&job);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert!(started_at.elapsed() < Duration::from_millis(5000));
+        assert_eq!(errors["kind"], Value::String("timeout".to_string()));
+        assert_eq!(errors["stdout"], Value::String("test\n".to_string()));
+    }
+
+    #[test]
+    fn can_cancel_command_after_it_exits() {
+        let mut executor = BashExecutor {};
+        let job_type = make_job_type(Value::String("sleep 999 & echo 'test'".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let started_at = Instant::now();
+        let mut context = executor.execute(&job);
+        while context.handle.is_some() {
+            assert_eq!(context.result(&job, true), None);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(context.cancel(&job), None);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert!(started_at.elapsed() < Duration::from_millis(5000));
+        assert_eq!(errors["kind"], Value::String("cancelled".to_string()));
+    }
+
+    #[test]
+    fn can_kill_command_ignoring_sigterm() {
+        let mut executor = BashExecutor {};
+        let mut job_type = make_job_type(Value::String("trap '' TERM; sleep 999".to_string()), None);
+        job_type.timeout = Some(100);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        thread::sleep(Duration::from_millis(200));
+        // SIGTERM is sent without waiting for the process to exit, SIGKILL once the grace period passes
+        let polled_at = Instant::now();
+        assert_eq!(context.result(&job, true), None);
+        assert!(polled_at.elapsed() < Duration::from_millis(1000));
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert!(polled_at.elapsed() >= Duration::from_millis(TIMEOUT_GRACE_PERIOD_MS));
+        assert_eq!(errors["kind"], Value::String("timeout".to_string()));
+    }
+
+    #[test]
+    fn can_return_structured_output() {
+        let mut executor = BashExecutor {};
+        let job_type = make_job_type(Value::String("echo '{\"count\": 2}' > \"$SYNCHRONY_RESULT_FILE\"; echo 'SYNCHRONY_RESULT: [1]'".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let results = context.result(&job, false).unwrap().unwrap().unwrap();
+        assert_eq!(results["output"], json!({ "count": 2 }));
+        assert!(!result_file_path(&job).exists());
+
+        let job_type = make_job_type(Value::String("echo 'SYNCHRONY_RESULT: [1]'; echo test".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        assert_eq!(context.result(&job, false).unwrap().unwrap().unwrap().get("output"), None);
+
+        let job_type = make_job_type(Value::String("echo test; echo 'SYNCHRONY_RESULT: [1]'; echo".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let results = context.result(&job, false).unwrap().unwrap().unwrap();
+        assert_eq!(results["output"], json!([1]));
+        assert_eq!(results["stdout"], Value::String("test\nSYNCHRONY_RESULT: [1]\n\n".to_string()));
+    }
+
+    #[test]
+    fn can_report_invalid_structured_output() {
+        let mut executor = BashExecutor {};
+        let job_type = make_job_type(Value::String("echo '{' > \"$SYNCHRONY_RESULT_FILE\"; echo test".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert_eq!(errors["kind"], Value::String("invalid_result".to_string()));
+        assert!(errors["message"].as_str().unwrap().starts_with("invalid JSON in result file"));
+        assert_eq!(errors["stdout"], Value::String("test\n".to_string()));
+        assert_eq!(errors["exit_code"], Value::Number(Number::from(0)));
+    }
+
+    #[test]
+    fn can_reject_structured_output_over_limit() {
+        let mut executor = BashExecutor {};
+        let mut job_type = make_job_type(Value::String("printf '\"%02000000d\"' 0 > \"$SYNCHRONY_RESULT_FILE\"; echo test".to_string()), None);
+        job_type.output_limit = Some(OutputLimit { head_bytes: 1000, tail_bytes: 24 });
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert_eq!(errors["kind"], Value::String("invalid_result".to_string()));
+        assert_eq!(errors["message"], Value::String("failed to read result file: 2000002 bytes is over the output limit of 1024 bytes".to_string()));
+        assert_eq!(errors["stdout"], Value::String("test\n".to_string()));
+        assert!(!result_file_path(&job).exists());
+
+        let mut job_type = make_job_type(Value::String("echo \"SYNCHRONY_RESULT: [$(seq -s , 1 1000)]\"".to_string()), None);
+        job_type.output_limit = Some(OutputLimit { head_bytes: 1000, tail_bytes: 24 });
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert_eq!(errors["message"], Value::String("result line of 3894 bytes is over the output limit of 1024 bytes".to_string()));
+    }
+
+    #[test]
+    fn can_report_progress() {
+        let mut executor = BashExecutor {};
+        let job_type = make_job_type(Value::String("echo '42% - processed 420/1000 rows' > \"$SYNCHRONY_PROGRESS_FILE\"; sleep 0.5; echo '{\"percent\": 100}' > \"$SYNCHRONY_PROGRESS_FILE\"".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        let started_at = Instant::now();
+        let mut progress = None;
+        while progress.is_none() && started_at.elapsed() < Duration::from_millis(400) {
+            thread::sleep(Duration::from_millis(10));
+            progress = context.take_progress();
+        }
+        assert_eq!(progress, Some(JobProgress { percent: Some(42.0), message: Some("42% - processed 420/1000 rows".to_string()), updated_at: 0 }));
+        assert_eq!(context.take_progress(), None);
+        assert!(context.result(&job, false).unwrap().is_ok());
+        assert_eq!(context.take_progress(), Some(JobProgress { percent: Some(100.0), message: None, updated_at: 0 }));
+        assert_eq!(context.take_progress(), None);
+        assert!(!progress_file_path(&job).exists());
+    }
+
+    #[test]
+    fn can_stream_output() {
+        let mut executor = BashExecutor {};
+        let job_type = make_job_type(Value::String("echo first; sleep 0.5; echo second 1>&2".to_string()), None);
+        let job = make_job(&job_type, None, None);
+        let mut context = executor.execute(&job);
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(context.result(&job, true), None);
+        assert_eq!(context.take_logs(), Some("first\n".to_string()));
+        assert_eq!(context.take_logs(), None);
+        let result = context.result(&job, false).unwrap().unwrap().unwrap();
+        assert_eq!(context.take_logs(), Some("second\n".to_string()));
+        assert_eq!(result["stdout"], Value::String("first\n".to_string()));
+        assert_eq!(result["stderr"], Value::String("second\n".to_string()));
+    }
+
+    #[test]
+    fn can_take_split_characters() {
+        let mut bytes = "é".as_bytes()[..1].to_vec();
+        assert_eq!(take_utf8(&mut bytes), None);
+        bytes.extend_from_slice(&"é!".as_bytes()[1..]);
+        assert_eq!(take_utf8(&mut bytes), Some("é!".to_string()));
+        assert!(bytes.is_empty());
+    }
 }
@@ -0,0 +1,350 @@
+use crate::exec::executor::*;
+use crate::exec::job::Job;
+use log::*;
+use serde_json::{Value, json};
+use serde_json::map::Map;
+use std::collections::HashMap;
+use std::sync::mpsc::{ channel, Receiver, TryRecvError };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+pub struct HttpExecutor {
+
+}
+
+pub struct HttpExecutorContext {
+    pub internal_failure: bool,
+    pub receiver: Option<Receiver<Result<Value, Value>>>,
+    pub timeout: Option<u64>,
+    pub started_at: Instant,
+}
+
+// how often a request with a timeout is checked for completion
+const TIMEOUT_POLL_MS: u64 = 10;
+
+impl HttpExecutorContext {
+    pub fn failed(timeout: Option<u64>) -> HttpExecutorContext {
+        HttpExecutorContext { internal_failure: true, receiver: None, timeout: timeout, started_at: Instant::now() }
+    }
+
+    pub fn started(receiver: Receiver<Result<Value, Value>>, timeout: Option<u64>) -> HttpExecutorContext {
+        HttpExecutorContext { internal_failure: false, receiver: Some(receiver), timeout: timeout, started_at: Instant::now() }
+    }
+
+    fn timed_out(&self) -> bool {
+        return self.timeout.map(|timeout| self.started_at.elapsed() >= Duration::from_millis(timeout)).unwrap_or(false);
+    }
+
+    // the request thread is abandoned, the agent's own timeout ends it
+    fn abandon(&mut self, job: &Job) -> Value {
+        warn!("Job '{}', job type '{}' / '{}' timed out after {} ms, abandoning request", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), self.started_at.elapsed().as_millis());
+        self.receiver = None;
+        return json!({ "kind": "timeout", "after_ms": self.started_at.elapsed().as_millis() as u64 });
+    }
+}
+
+impl ExecutionContext for HttpExecutorContext {
+    fn result(&mut self, job: &Job, is_async: bool) -> Option<Result<Option<Value>, Value>> {
+        if self.internal_failure || self.receiver.is_none() {
+            return Some(Err(Value::Null));
+        }
+        let received = loop {
+            match self.receiver.as_ref().unwrap().try_recv() {
+                Ok(received) => break received,
+                Err(TryRecvError::Disconnected) => return Some(Err(Value::Null)),
+                Err(TryRecvError::Empty) => (),
+            }
+            if self.timed_out() {
+                return Some(Err(self.abandon(job)));
+            }
+            if is_async {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(TIMEOUT_POLL_MS));
+        };
+        self.receiver = None;
+        return match received {
+            // the agent's timeout can fire just before ours does
+            Err(_) if self.timed_out() => Some(Err(self.abandon(job))),
+            Err(e) => Some(Err(e)),
+            Ok(output) => Some(Ok(Some(output))),
+        }
+    }
+
+    fn cancel(&mut self, job: &Job) -> Option<Value> {
+        if self.receiver.is_some() {
+            info!("Job '{}', job type '{}' / '{}' cancelled after {} ms, abandoning request", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), self.started_at.elapsed().as_millis());
+        }
+        self.receiver = None;
+        return Some(json!({ "kind": "cancelled" }));
+    }
+}
+
+// replaces `{{name}}` placeholders with the job argument `name`, strings are inserted as is and other values as JSON
+fn render_template(template: &str, arguments: &HashMap<String, Value>) -> String {
+    let mut rendered = template.to_string();
+    for (key, value) in arguments.iter() {
+        let replacement = match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        rendered = rendered.replace(&format!("{{{{{}}}}}", key), &replacement);
+    }
+    return rendered;
+}
+
+// a string that is exactly one placeholder is replaced by the argument itself, keeping its JSON type
+fn render_json(template: &Value, arguments: &HashMap<String, Value>) -> Value {
+    return match template {
+        Value::String(s) => {
+            let whole = s.strip_prefix("{{").and_then(|s| s.strip_suffix("}}")).and_then(|key| arguments.get(key));
+            match whole {
+                Some(value) => value.clone(),
+                None => Value::String(render_template(s, arguments)),
+            }
+        },
+        Value::Array(arr) => Value::Array(arr.iter().map(|value| render_json(value, arguments)).collect()),
+        Value::Object(obj) => Value::Object(obj.iter().map(|(key, value)| (key.clone(), render_json(value, arguments))).collect()),
+        value => value.clone(),
+    }
+}
+
+fn response_output(response: ureq::Response) -> Value {
+    let status = response.status();
+    let mut headers = Map::new();
+    for name in response.headers_names() {
+        let values = response.all(&name).join(", ");
+        headers.insert(name.to_lowercase(), Value::String(values));
+    }
+    let body = response.into_string().unwrap_or_else(|e| {
+        warn!("Failed to read http response body: {:?}", e);
+        "".to_string()
+    });
+    return json!({ "status": status, "headers": headers, "body": body });
+}
+
+impl Executor for HttpExecutor {
+    type Context = HttpExecutorContext;
+
+    fn execute(&mut self, job: &Job) -> HttpExecutorContext {
+        let job_type = job.job_type.as_ref().unwrap();
+        let timeout = job_type.timeout;
+        // requests are set by the job type alone, so a job cannot send one anywhere else, its arguments are only used by placeholders
+        let param = |name: &str| job_type.metadata.get(name);
+        let url = match param("url") {
+            Some(Value::String(url)) => render_template(url, &job.arguments),
+            _ => {
+                error!("No url found in http execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated());
+                return HttpExecutorContext::failed(timeout);
+            }
+        };
+        let method = match param("method") {
+            None => "GET".to_string(),
+            Some(Value::String(method)) => method.to_uppercase(),
+            _ => {
+                error!("Invalid method in http execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated());
+                return HttpExecutorContext::failed(timeout);
+            }
+        };
+        let mut headers = match param("headers") {
+            None => Map::new(),
+            Some(Value::Object(value)) => value.clone(),
+            _ => {
+                warn!("Invalid headers parameter for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated());
+                Map::new()
+            }
+        };
+        let expected_status = match param("expected_status") {
+            None => None,
+            Some(Value::Array(statuses)) if statuses.iter().all(|status| status.is_u64()) => Some(statuses.iter().map(|status| status.as_u64().unwrap() as u16).collect::<Vec<u16>>()),
+            _ => {
+                error!("Invalid expected_status in http execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated());
+                return HttpExecutorContext::failed(timeout);
+            }
+        };
+        let body = match param("body") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(body)) => Some(render_template(body, &job.arguments)),
+            Some(body) => {
+                if !headers.keys().any(|name| name.eq_ignore_ascii_case("content-type")) {
+                    headers.insert("Content-Type".to_string(), Value::String("application/json".to_string()));
+                }
+                Some(render_json(body, &job.arguments).to_string())
+            },
+        };
+        let mut builder = ureq::AgentBuilder::new();
+        if timeout.is_some() {
+            builder = builder.timeout(Duration::from_millis(timeout.unwrap()));
+        }
+        let mut request = builder.build().request(&method, &url);
+        for (name, value) in headers.iter() {
+            match value {
+                Value::String(s) => request = request.set(name, &render_template(s, &job.arguments)),
+                _ => warn!("Non-string header '{}' for job '{}', job type '{}' / '{}'", name, job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated()),
+            }
+        }
+        let (sender, receiver) = channel();
+        let job_uuid = job.uuid;
+        let spawned = thread::Builder::new().name(format!("http-{}", job_uuid.hyphenated())).spawn(move || {
+            let response = match body {
+                Some(body) => request.send_string(&body),
+                None => request.call(),
+            };
+            let result = match response {
+                // ureq reports 4xx and 5xx responses as errors, they are checked against expected_status like any other
+                Ok(response) | Err(ureq::Error::Status(_, response)) => {
+                    let status = response.status();
+                    let output = response_output(response);
+                    let expected = match &expected_status {
+                        Some(expected_status) => expected_status.contains(&status),
+                        None => (200..300).contains(&status),
+                    };
+                    if expected {
+                        Ok(output)
+                    } else {
+                        let mut errors = output;
+                        errors.as_object_mut().unwrap().insert("kind".to_string(), Value::String("unexpected_status".to_string()));
+                        Err(errors)
+                    }
+                },
+                Err(e) => {
+                    warn!("Http request failed for job '{}': {}", job_uuid.hyphenated(), e);
+                    Err(json!({ "kind": "request_failed", "message": e.to_string() }))
+                },
+            };
+            let _ = sender.send(result);
+        });
+        if spawned.is_err() {
+            error!("Failed to spawn request thread in http execution for job '{}', job type '{}' / '{}': {:?}", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated(), spawned.unwrap_err());
+            return HttpExecutorContext::failed(timeout);
+        }
+        return HttpExecutorContext::started(receiver, timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::executors::tests as executor_tests;
+    use crate::exec::job_type::JobType;
+    use std::io::{ Read, Write };
+    use std::net::TcpListener;
+    use std::sync::mpsc::Sender;
+
+    fn make_job_type(url: String, timeout: Option<u64>) -> JobType {
+        let mut job_type = JobType { timeout: timeout, ..executor_tests::make_job_type("http") };
+        job_type.metadata.insert("url".to_string(), Value::String(url));
+        return job_type;
+    }
+
+    fn make_job(job_type: &JobType, arguments: Value) -> Job {
+        return Job { arguments: serde_json::from_value(arguments).unwrap(), ..executor_tests::make_job(job_type) };
+    }
+
+    // serves a single request with the given response after a delay, sending back the raw request
+    fn stub_server(response: &'static str, delay_ms: u64) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver): (Sender<String>, Receiver<String>) = channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 4096];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+            let _ = sender.send(String::from_utf8_lossy(&request).to_string());
+            thread::sleep(Duration::from_millis(delay_ms));
+            let _ = stream.write_all(response.as_bytes());
+        });
+        return (url, receiver);
+    }
+
+    #[test]
+    fn can_execute_request() {
+        let (url, requests) = stub_server("HTTP/1.1 201 Created\r\nX-Test: yes\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok", 0);
+        let mut job_type = make_job_type(format!("{}/users/{{{{user}}}}", url), Some(5000));
+        job_type.metadata.insert("method".to_string(), json!("post"));
+        job_type.metadata.insert("headers".to_string(), json!({ "X-Type": "type", "X-Job": "{{job_header}}" }));
+        job_type.metadata.insert("body".to_string(), json!({ "user": "{{user}}", "count": "{{count}}", "message": "hi {{user}}" }));
+        let job = make_job(&job_type, json!({ "user": "bob", "count": 3, "job_header": "job" }));
+        let mut executor = HttpExecutor {};
+        let mut context = executor.execute(&job);
+        let result = context.result(&job, false);
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /users/bob HTTP/1.1\r\n"));
+        let request_lower = request.to_lowercase();
+        assert!(request_lower.contains("x-type: type\r\n"));
+        assert!(request_lower.contains("x-job: job\r\n"));
+        assert!(request_lower.contains("content-type: application/json\r\n"));
+        let body: Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body, json!({ "user": "bob", "count": 3, "message": "hi bob" }));
+        let output = result.unwrap().unwrap().unwrap();
+        assert_eq!(output.get("status"), Some(&json!(201)));
+        assert_eq!(output.get("body"), Some(&json!("ok")));
+        assert_eq!(output.get("headers").unwrap().get("x-test"), Some(&json!("yes")));
+    }
+
+    #[test]
+    fn fails_on_unexpected_status() {
+        let (url, _requests) = stub_server("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 4\r\nConnection: close\r\n\r\nfail", 0);
+        let job_type = make_job_type(url.clone(), Some(5000));
+        let job = make_job(&job_type, json!({}));
+        let mut executor = HttpExecutor {};
+        let mut context = executor.execute(&job);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert_eq!(errors.get("kind"), Some(&json!("unexpected_status")));
+        assert_eq!(errors.get("status"), Some(&json!(500)));
+        assert_eq!(errors.get("body"), Some(&json!("fail")));
+
+        let (url, _requests) = stub_server("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", 0);
+        let mut job_type = make_job_type(url, Some(5000));
+        job_type.metadata.insert("expected_status".to_string(), json!([200, 404]));
+        let job = make_job(&job_type, json!({}));
+        let mut context = executor.execute(&job);
+        let output = context.result(&job, false).unwrap().unwrap().unwrap();
+        assert_eq!(output.get("status"), Some(&json!(404)));
+    }
+
+    #[test]
+    fn can_time_out() {
+        let (url, _requests) = stub_server("HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", 2000);
+        let job_type = make_job_type(url, Some(500));
+        let job = make_job(&job_type, json!({}));
+        let mut executor = HttpExecutor {};
+        let started_at = Instant::now();
+        let mut context = executor.execute(&job);
+        let errors = context.result(&job, false).unwrap().unwrap_err();
+        assert_eq!(errors.get("kind"), Some(&json!("timeout")));
+        assert!(started_at.elapsed() < Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn ignores_request_arguments() {
+        let (url, requests) = stub_server("HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", 0);
+        let job_type = make_job_type(format!("{}/hook", url), Some(5000));
+        let job = make_job(&job_type, json!({ "url": "http://127.0.0.1:1/internal", "method": "DELETE", "headers": { "X-Job": "job" }, "body": "injected" }));
+        let mut executor = HttpExecutor {};
+        let mut context = executor.execute(&job);
+        let output = context.result(&job, false).unwrap().unwrap().unwrap();
+        assert_eq!(output.get("status"), Some(&json!(200)));
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("GET /hook HTTP/1.1\r\n"));
+        assert!(!request.to_lowercase().contains("x-job"));
+        assert!(!request.contains("injected"));
+    }
+
+    #[test]
+    fn fails_without_url() {
+        let mut job_type = make_job_type("".to_string(), None);
+        job_type.metadata.remove("url");
+        let job = make_job(&job_type, json!({}));
+        let mut executor = HttpExecutor {};
+        let mut context = executor.execute(&job);
+        assert_eq!(context.result(&job, false), Some(Err(Value::Null)));
+    }
+}
@@ -1,23 +1,126 @@
 pub mod bash;
+pub mod http;
+pub mod process;
 pub mod sidekiq;
 
-use super::job::Job;
-use crate::store::StoreRef;
+use super::job::{ Job, JobProgress };
+use super::job_type::RetryOutcome;
+use crate::util::time::epoch;
+use crate::store::{ StoreRef, cancelled_errors, take_rate_limit_token };
 use super::executor::*;
+use super::registry::create_executor;
 use log::*;
-use serde_json::Value;
+use serde_json::{ Value, json };
+use std::thread;
+use std::time::{ Duration, Instant };
 
-fn finish_job_execution(store: &mut StoreRef, job: Job, result: Option<Result<Option<Value>, Value>>) {
-    let finish_result = match result {
-        Some(Err(e)) => {
-            store.finish_job(job, None, Some(e))
-        },
-        Some(Ok(value)) => {
-            store.finish_job(job, value, None)
-        },
-        _ => {
-            store.finish_job(job, None, Some(Value::String("invalid executor context [async not supported]".to_string())))
+// how often a running job is checked for completion
+const RESULT_POLL_MS: u64 = 10;
+// how often a running job is checked for cancellation
+const CANCEL_POLL_MS: u64 = 500;
+// how often a running job's output is appended to its logs, and its progress updated
+const FLUSH_MS: u64 = 500;
+
+// classifies a job's result, returning None if it succeeded
+fn failure_outcome(results: &Option<Value>, errors: &Option<Value>) -> Option<RetryOutcome> {
+    if errors.is_some() {
+        if errors.as_ref().unwrap().get("kind") == Some(&Value::String("timeout".to_string())) {
+            return Some(RetryOutcome::Timeout);
+        }
+        return Some(RetryOutcome::Error);
+    }
+    let exit_code = results.as_ref().and_then(|results| results.get("exit_code"));
+    if exit_code.is_some() && exit_code.unwrap().as_i64() != Some(0) {
+        return Some(RetryOutcome::NonZeroExit); // a null exit code means the process was killed by a signal
+    }
+    return None;
+}
+
+fn is_cancelled(errors: &Option<Value>) -> bool {
+    return errors.as_ref().and_then(|errors| errors.get("kind")) == Some(&Value::String("cancelled".to_string()));
+}
+
+// a job started by its executor, whose result is polled for without blocking
+pub struct RunningJob {
+    pub job: Job,
+    context: ExecutionContextRef,
+    checked_at: Instant,
+    flushed_at: Instant,
+    cancelling: bool, // set once the job has been cancelled, while its executor is still stopping it
+}
+
+// writes the output and progress the job reported since the last flush to the store
+fn flush(store: &mut StoreRef, running: &mut RunningJob) {
+    running.flushed_at = Instant::now();
+    let logs = running.context.take_logs();
+    if logs.is_some() {
+        let appended = store.append_job_logs(running.job.uuid, &logs.unwrap());
+        if appended.is_err() {
+            error!("Error appending job logs from redis server: {}", appended.err().unwrap());
+        }
+    }
+    let progress = running.context.take_progress();
+    if progress.is_some() {
+        let progress = JobProgress { updated_at: epoch(), ..progress.unwrap() };
+        // kept on the job too, so it is finished with its last progress
+        running.job.progress = Some(progress.clone());
+        let updated = store.set_job_progress(running.job.uuid, &progress);
+        if updated.is_err() {
+            error!("Error updating job progress from redis server: {}", updated.err().unwrap());
+        }
+    }
+}
+
+// checks once for a job's result, stopping it if it has been cancelled while running
+fn poll_result(store: &mut StoreRef, running: &mut RunningJob) -> Option<Result<Option<Value>, Value>> {
+    let result = running.context.result(&running.job, true);
+    if result.is_some() {
+        return result;
+    }
+    if !running.cancelling && running.checked_at.elapsed() >= Duration::from_millis(CANCEL_POLL_MS) {
+        running.checked_at = Instant::now();
+        let cancelled = store.is_job_cancelled(running.job.uuid);
+        if cancelled.is_err() {
+            error!("Error checking job cancellation from redis server: {}", cancelled.err().unwrap());
+        } else if cancelled.unwrap() {
+            let errors = running.context.cancel(&running.job);
+            if errors.is_some() {
+                return Some(Err(errors.unwrap()));
+            }
+            running.cancelling = true;
+        }
+    }
+    return None;
+}
+
+fn finish_job_execution(store: &mut StoreRef, mut job: Job, result: Option<Result<Option<Value>, Value>>) {
+    let (mut results, mut errors) = match result {
+        Some(Err(e)) => (None, Some(e)),
+        Some(Ok(value)) => (value, None),
+        _ => (None, Some(Value::String("invalid executor context [async not supported]".to_string()))),
+    };
+    // output is truncated before it is written to the store, where it is kept with the job
+    if let Some(output_limit) = job.job_type.as_ref().unwrap().output_limit.as_ref() {
+        results.iter_mut().chain(errors.iter_mut()).for_each(|value| output_limit.apply(value));
+    }
+    let retry_policy = job.job_type.as_ref().unwrap().retry.clone();
+    let outcome = failure_outcome(&results, &errors);
+    let finish_result = match (retry_policy, outcome) {
+        // cancelled jobs are never retried
+        _ if is_cancelled(&errors) => store.finish_job(job, results, errors),
+        (Some(retry_policy), Some(outcome)) if retry_policy.retry_on.contains(&outcome) => {
+            if job.attempt + 1 < retry_policy.max_attempts {
+                let delay_ms = retry_policy.delay_ms(job.attempt);
+                info!("Retrying job '{}' in {} ms after attempt {} of {} failed ({:?})", job.uuid.hyphenated(), delay_ms, job.attempt + 1, retry_policy.max_attempts, outcome);
+                // the next attempt reports its own progress
+                job.progress = None;
+                store.retry_job(job, results, errors, epoch() + delay_ms)
+            } else {
+                warn!("Job '{}' failed all {} attempts ({:?}), moving to dead jobs", job.uuid.hyphenated(), retry_policy.max_attempts, outcome);
+                store.kill_job(job, results, errors)
+            }
         },
+        _ => store.finish_job(job, results, errors),
     };
     if finish_result.is_err() {
         error!("Error finishing bash task from redis server: {}", finish_result.err().unwrap());
@@ -25,24 +128,120 @@ fn finish_job_execution(store: &mut StoreRef, job: Job, result: Option<Result<Op
     }
 }
 
+// runs a job to completion on the current thread
 pub fn run_job(store: &mut StoreRef, job: Job) {
+    let mut running = match start_job(store, job) {
+        Some(running) => running,
+        None => return,
+    };
+    loop {
+        running = match poll_job(store, running) {
+            Some(running) => running,
+            None => return,
+        };
+        thread::sleep(Duration::from_millis(RESULT_POLL_MS));
+    }
+}
+
+// finishes a running job if it has a result, otherwise returning it to be polled again
+pub fn poll_job(store: &mut StoreRef, mut running: RunningJob) -> Option<RunningJob> {
+    let result = poll_result(store, &mut running);
+    if result.is_none() {
+        if running.flushed_at.elapsed() >= Duration::from_millis(FLUSH_MS) {
+            flush(store, &mut running);
+        }
+        return Some(running);
+    }
+    flush(store, &mut running);
+    let closed = store.close_job_logs(running.job.uuid);
+    if closed.is_err() {
+        error!("Error closing job logs from redis server: {}", closed.err().unwrap());
+    }
+    finish_job_execution(store, running.job, result);
+    return None;
+}
+
+// starts a dequeued job on its executor, returning None if it was finished or delayed without being started
+pub fn start_job(store: &mut StoreRef, job: Job) -> Option<RunningJob> {
     let job_type = job.job_type.as_ref().unwrap();
-    if job_type.executor == "bash" {
-        let mut executor = bash::BashExecutor {};
-        let mut context = executor.execute(&job);
-        let result = context.result(&job, false);
-        finish_job_execution(store, job, result);
-    } else if job_type.executor == "sidekiq" {
-        let mut executor = sidekiq::SidekiqExecutor {};
-        let mut context = executor.execute(&job);
-        let result = context.result(&job, false);
-        finish_job_execution(store, job, result);
-    } else {
-        error!("Invalid executor type for job type '{}' / '{}' on job '{}': '{}'", job_type.name, job_type.uuid.hyphenated(), job.uuid.hyphenated(), job_type.executor);
-        let finish_result = store.finish_job(job, None, None);
-        if finish_result.is_err() {
-            error!("Error finishing bash task from redis server: {}", finish_result.err().unwrap());
-            return;
+    // the job may have been cancelled between being dequeued and started, or while its node was being reaped
+    if store.is_job_cancelled(job.uuid).unwrap_or(false) {
+        info!("Job '{}' was cancelled before it started", job.uuid.hyphenated());
+        finish_job_execution(store, job, Some(Err(cancelled_errors())));
+        return None;
+    }
+    // jobs over their job type's rate limit are delayed until it allows them, rather than started
+    let wait_ms = take_rate_limit_token(store, job_type);
+    if wait_ms.is_err() {
+        error!("Error checking rate limit of job type '{}' from redis server, starting job anyway: {}", job_type.uuid.hyphenated(), wait_ms.err().unwrap());
+    } else if let Some(wait_ms) = wait_ms.unwrap() {
+        info!("Job type '{}' / '{}' is over its rate limit, delaying job '{}' by {} ms", job_type.name, job_type.uuid.hyphenated(), job.uuid.hyphenated(), wait_ms);
+        let delay_result = store.delay_job(job, epoch() + wait_ms);
+        if delay_result.is_err() {
+            error!("Error delaying rate limited job from redis server: {}", delay_result.err().unwrap());
         }
+        return None;
+    }
+    let executor = create_executor(&job_type.executor);
+    if executor.is_none() {
+        error!("Invalid executor type for job type '{}' / '{}' on job '{}': '{}'", job_type.name, job_type.uuid.hyphenated(), job.uuid.hyphenated(), job_type.executor);
+        // failed like any other job that could not be started, so it is retried or moved to dead jobs by its retry policy
+        let errors = json!({ "kind": "invalid_executor", "executor": job_type.executor });
+        finish_job_execution(store, job, Some(Err(errors)));
+        return None;
+    }
+    // opens the job's logs, so they can be followed before it writes any output
+    let opened = store.append_job_logs(job.uuid, "");
+    if opened.is_err() {
+        error!("Error opening job logs from redis server: {}", opened.err().unwrap());
     }
-}
\ No newline at end of file
+    let context = executor.unwrap().execute_boxed(&job);
+    return Some(RunningJob { job: job, context: context, checked_at: Instant::now(), flushed_at: Instant::now(), cancelling: false });
+}
+#[cfg(test)]
+pub mod tests {
+    use crate::exec::job::{ Job, JobStatus };
+    use crate::exec::job_type::{ JobType, UniquePolicy, Priority };
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    // a job type for `executor` with no metadata or limits, for executor tests to override with `JobType { .., ..make_job_type(..) }`
+    pub fn make_job_type(executor: &str) -> JobType {
+        return JobType {
+            executor: executor.to_string(),
+            name: "test".to_string(),
+            node_type: "default".to_string(),
+            timeout: None,
+            retry: None,
+            priority: Priority::Normal,
+            max_concurrency: None,
+            rate_limit: None,
+            output_limit: None,
+            unique: false,
+            unique_policy: UniquePolicy::Reject,
+            uuid: Uuid::new_v4(),
+            metadata: HashMap::new(),
+        };
+    }
+
+    // a queued job of `job_type` with no arguments, likewise
+    pub fn make_job(job_type: &JobType) -> Job {
+        return Job {
+            uuid: Uuid::new_v4(),
+            status: JobStatus::Queued,
+            job_type_uuid: job_type.uuid,
+            job_type: Some(job_type.clone()),
+            arguments: HashMap::new(),
+            priority: job_type.priority,
+            executing_node: None,
+            enqueued_at: None,
+            run_at: None,
+            started_at: None,
+            ended_at: None,
+            results: None,
+            errors: None,
+            attempt: 0,
+            progress: None,
+        };
+    }
+}
@@ -0,0 +1,446 @@
+use std::process::*;
+use crate::exec::executor::*;
+use crate::exec::job::{ Job, JobProgress };
+use super::bash::{ GroupTermination, signal_group, terminate_process_group };
+use std::env;
+use log::*;
+use serde_json::{Value, json};
+use serde_json::map::Map;
+use std::collections::HashMap;
+use std::io::{ BufRead, BufReader, Write };
+use std::os::unix::process::CommandExt;
+use std::sync::Mutex;
+use std::sync::mpsc::{ channel, Receiver, TryRecvError };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+pub struct ProcessExecutor {
+
+}
+
+// a long lived worker program, exchanging newline delimited JSON messages over its stdin and stdout
+pub struct ProcessWorker {
+    pub handle: Child,
+    pub stdin: ChildStdin,
+    pub messages: Receiver<Value>,
+    pub stderr: Receiver<String>, // lines the worker wrote to its stderr
+}
+
+pub struct ProcessExecutorContext {
+    pub internal_failure: bool,
+    pub worker: Option<ProcessWorker>,
+    pub stopping: Option<Child>, // the worker being stopped, once it has been sent SIGTERM
+    pub termination: Option<GroupTermination>,
+    pub crashed_at: Option<Instant>, // set once the worker closed its stdout, while it is waited for to exit
+    pub key: String,
+    pub logs: String, // log messages not yet taken for the job's logs
+    pub progress: Option<JobProgress>, // the last progress message, until taken
+    pub timeout: Option<u64>,
+    pub started_at: Instant,
+}
+
+// how often a running job is checked for messages from its worker
+const MESSAGE_POLL_MS: u64 = 10;
+// how long a worker that closed its stdout has to exit before it is killed
+const CRASH_EXIT_WAIT_MS: u64 = 1000;
+
+lazy_static! {
+    // idle workers, by job type and worker command, that are reused by later jobs
+    static ref IDLE_WORKERS: Mutex<HashMap<String, Vec<ProcessWorker>>> = Mutex::new(HashMap::new());
+}
+
+impl ProcessWorker {
+    fn spawn(command: &Value, environment: &Map<String, Value>) -> Result<ProcessWorker, String> {
+        let mut builder = match command {
+            Value::String(command) => {
+                let mut builder = Command::new(env::var("SHELL").unwrap_or("/bin/bash".to_string()));
+                builder.arg("-c").arg(command);
+                builder
+            },
+            Value::Array(arr) if !arr.is_empty() && arr.iter().all(|arg| arg.is_string()) => {
+                let mut builder = Command::new(arr[0].as_str().unwrap());
+                builder.args(arr[1..].iter().map(|arg| arg.as_str().unwrap()));
+                builder
+            },
+            _ => return Err("invalid worker command".to_string()),
+        };
+        builder
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        for (key, value) in environment.iter() {
+            match value {
+                Value::String(s) => { builder.env(key, s); },
+                _ => warn!("Non-string environment parameter '{}' for process worker", key),
+            }
+        }
+        let mut handle = builder.spawn().map_err(|e| format!("failed to spawn worker: {:?}", e))?;
+        let stdin = handle.stdin.take().unwrap();
+        let stdout = handle.stdout.take().unwrap();
+        let stderr = handle.stderr.take().unwrap();
+        let (stderr_sender, stderr_receiver) = channel();
+        thread::spawn(move || {
+            let mut stderr = BufReader::new(stderr);
+            let mut line = vec![];
+            while stderr.read_until(b'\n', &mut line).map(|read| read > 0).unwrap_or(false) {
+                if stderr_sender.send(String::from_utf8_lossy(&line).to_string()).is_err() {
+                    break;
+                }
+                line.clear();
+            }
+        });
+        let (sender, receiver) = channel();
+        // the sender is dropped when the worker closes its stdout, which is how crashes are noticed
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if line.is_err() {
+                    break;
+                }
+                let line = line.unwrap();
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<Value>(&line) {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => warn!("Ignoring non-JSON line from process worker: {}", line),
+                }
+            }
+        });
+        return Ok(ProcessWorker { handle: handle, stdin: stdin, messages: receiver, stderr: stderr_receiver });
+    }
+
+    fn send(&mut self, message: &Value) -> Result<(), String> {
+        return writeln!(self.stdin, "{}", message).and_then(|_| self.stdin.flush()).map_err(|e| format!("{:?}", e));
+    }
+}
+
+impl ProcessExecutorContext {
+    pub fn failed(timeout: Option<u64>) -> ProcessExecutorContext {
+        ProcessExecutorContext { internal_failure: true, worker: None, stopping: None, termination: None, crashed_at: None, key: "".to_string(), logs: "".to_string(), progress: None, timeout: timeout, started_at: Instant::now() }
+    }
+
+    pub fn started(worker: ProcessWorker, key: String, timeout: Option<u64>) -> ProcessExecutorContext {
+        ProcessExecutorContext { internal_failure: false, worker: Some(worker), stopping: None, termination: None, crashed_at: None, key: key, logs: "".to_string(), progress: None, timeout: timeout, started_at: Instant::now() }
+    }
+
+    fn timed_out(&self) -> bool {
+        return self.timeout.map(|timeout| self.started_at.elapsed() >= Duration::from_millis(timeout)).unwrap_or(false);
+    }
+
+    // a worker is stopped when a job on it times out or is cancelled, the next job starts a new one
+    fn terminate(&mut self, kind: &str) {
+        let after_ms = self.started_at.elapsed().as_millis() as u64;
+        let worker = self.worker.take().unwrap();
+        self.termination = Some(GroupTermination::start(worker.handle.id() as i32, kind, after_ms));
+        self.stopping = Some(worker.handle);
+    }
+
+    // the errors to record for a job whose worker has been stopped, or None while it is still stopping
+    fn terminated(&mut self) -> Option<Value> {
+        let termination = self.termination.as_mut().unwrap();
+        if !termination.poll(&mut self.stopping) {
+            return None;
+        }
+        return Some(json!({ "kind": termination.kind, "after_ms": termination.after_ms }));
+    }
+
+    // the errors to record for a job whose worker closed its stdout, or None while it is still exiting
+    fn crashed(&mut self, job: &Job) -> Option<Value> {
+        let worker = self.worker.as_mut().unwrap();
+        let exited = worker.handle.try_wait();
+        if let Ok(None) = exited {
+            // its stdout may close just before it exits, a worker that keeps running without it is killed
+            if self.crashed_at.unwrap().elapsed() >= Duration::from_millis(CRASH_EXIT_WAIT_MS) {
+                signal_group(worker.handle.id() as i32, libc::SIGKILL);
+            }
+            return None;
+        }
+        self.take_stderr();
+        self.worker = None;
+        let exit_code = exited.ok().flatten().and_then(|status| status.code());
+        warn!("Process worker crashed running job '{}', job type '{}' / '{}', exit code {:?}", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), exit_code);
+        return Some(json!({ "kind": "worker_crashed", "exit_code": exit_code }));
+    }
+
+    // the worker's stderr is logged along with the job's log messages
+    fn take_stderr(&mut self) {
+        if let Some(worker) = self.worker.as_ref() {
+            for line in worker.stderr.try_iter() {
+                self.logs.push_str(&line);
+            }
+        }
+    }
+
+    fn release(&mut self) {
+        self.take_stderr();
+        let worker = self.worker.take().unwrap();
+        IDLE_WORKERS.lock().unwrap().entry(self.key.clone()).or_default().push(worker);
+    }
+}
+
+impl ExecutionContext for ProcessExecutorContext {
+    fn result(&mut self, job: &Job, is_async: bool) -> Option<Result<Option<Value>, Value>> {
+        if self.internal_failure || (self.worker.is_none() && self.termination.is_none()) {
+            return Some(Err(Value::Null));
+        }
+        loop {
+            if self.termination.is_some() {
+                if let Some(errors) = self.terminated() {
+                    return Some(Err(errors));
+                }
+            } else {
+                match self.worker.as_ref().unwrap().messages.try_recv() {
+                    Ok(message) => {
+                        // a reused worker may still send messages about its previous job, which are not this job's
+                        if message.get("job_uuid") != Some(&json!(job.uuid)) {
+                            warn!("Ignoring message from process worker not for job '{}': {}", job.uuid.hyphenated(), message);
+                            continue;
+                        }
+                        match message.get("type").and_then(|kind| kind.as_str()) {
+                            Some("result") => {
+                                self.release();
+                                let errors = message.get("errors").cloned().unwrap_or(Value::Null);
+                                if !errors.is_null() {
+                                    return Some(Err(errors));
+                                }
+                                return Some(Ok(message.get("results").cloned().filter(|results| !results.is_null())));
+                            },
+                            Some("log") => {
+                                match message.get("message") {
+                                    Some(Value::String(line)) => self.logs.push_str(line),
+                                    other => self.logs.push_str(&other.unwrap_or(&Value::Null).to_string()),
+                                }
+                                self.logs.push('\n');
+                            },
+                            Some("progress") => {
+                                match serde_json::from_value::<JobProgress>(message.clone()) {
+                                    Ok(progress) => self.progress = Some(progress),
+                                    Err(e) => warn!("Ignoring invalid progress message from process worker for job '{}': {}", job.uuid.hyphenated(), e),
+                                }
+                            },
+                            _ => warn!("Ignoring unknown message from process worker for job '{}': {}", job.uuid.hyphenated(), message),
+                        }
+                        continue;
+                    },
+                    Err(TryRecvError::Disconnected) => {
+                        self.crashed_at = self.crashed_at.or(Some(Instant::now()));
+                        if let Some(errors) = self.crashed(job) {
+                            return Some(Err(errors));
+                        }
+                    },
+                    Err(TryRecvError::Empty) => (),
+                }
+                if self.timed_out() {
+                    warn!("Job '{}', job type '{}' / '{}' timed out after {} ms, terminating worker", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), self.started_at.elapsed().as_millis());
+                    self.terminate("timeout");
+                    continue;
+                }
+            }
+            if is_async {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(MESSAGE_POLL_MS));
+        }
+    }
+
+    fn cancel(&mut self, job: &Job) -> Option<Value> {
+        if self.termination.is_some() {
+            return None;
+        }
+        if self.worker.is_none() {
+            return Some(json!({ "kind": "cancelled" }));
+        }
+        info!("Job '{}', job type '{}' / '{}' cancelled after {} ms, terminating worker", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), self.started_at.elapsed().as_millis());
+        self.terminate("cancelled");
+        return None;
+    }
+
+    fn take_logs(&mut self) -> Option<String> {
+        self.take_stderr();
+        if self.logs.is_empty() {
+            return None;
+        }
+        return Some(std::mem::take(&mut self.logs));
+    }
+
+    fn take_progress(&mut self) -> Option<JobProgress> {
+        return self.progress.take();
+    }
+}
+
+impl Executor for ProcessExecutor {
+    type Context = ProcessExecutorContext;
+
+    fn execute(&mut self, job: &Job) -> ProcessExecutorContext {
+        let job_type = job.job_type.as_ref().unwrap();
+        let timeout = job_type.timeout;
+        let command = job_type.metadata.get("command");
+        if command.is_none() || !(command.unwrap().is_string() || command.unwrap().is_array()) {
+            error!("No command found in job type in process execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated());
+            return ProcessExecutorContext::failed(timeout);
+        }
+        let environment = match job_type.metadata.get("environment") {
+            None => Map::new(),
+            Some(Value::Object(value)) => value.clone(),
+            _ => {
+                warn!("Invalid environment parameter in job type for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated());
+                Map::new()
+            }
+        };
+        // workers are only reused by jobs of the same job type with the same command and environment
+        let key = format!("{}:{}", job_type.uuid.hyphenated(), json!([command.unwrap(), environment]));
+        let message = json!({
+            "type": "job",
+            "job": {
+                "uuid": job.uuid,
+                "job_type_uuid": job.job_type_uuid,
+                "job_type_name": job_type.name,
+                "arguments": job.arguments,
+                "attempt": job.attempt,
+            },
+        });
+        loop {
+            let idle = IDLE_WORKERS.lock().unwrap().get_mut(&key).and_then(|workers| workers.pop());
+            let reused = idle.is_some();
+            let mut worker = match idle {
+                Some(worker) => {
+                    for line in worker.stderr.try_iter() {
+                        info!("Process worker stderr between jobs of job type '{}' / '{}': {}", job_type.name, job_type.uuid.hyphenated(), line.trim_end());
+                    }
+                    worker
+                },
+                None => match ProcessWorker::spawn(command.unwrap(), &environment) {
+                    Ok(worker) => worker,
+                    Err(e) => {
+                        error!("Failed to start process worker for job '{}', job type '{}' / '{}': {}", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated(), e);
+                        return ProcessExecutorContext::failed(timeout);
+                    },
+                },
+            };
+            let sent = worker.send(&message);
+            if sent.is_ok() {
+                return ProcessExecutorContext::started(worker, key, timeout);
+            }
+            // an idle worker may have exited since its last job, in which case it is replaced
+            terminate_process_group(&mut worker.handle);
+            if !reused {
+                error!("Failed to send job '{}' to new process worker, job type '{}' / '{}': {}", job.uuid.hyphenated(), job_type.name, job_type.uuid.hyphenated(), sent.unwrap_err());
+                return ProcessExecutorContext::failed(timeout);
+            }
+            info!("Restarting exited process worker for job type '{}' / '{}'", job_type.name, job_type.uuid.hyphenated());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::executors::tests as executor_tests;
+    use crate::exec::executors::tests::make_job;
+    use crate::exec::job_type::JobType;
+
+    fn make_job_type(command: &str, timeout: Option<u64>) -> JobType {
+        let mut job_type = JobType { timeout: timeout, ..executor_tests::make_job_type("process") };
+        job_type.metadata.insert("command".to_string(), Value::String(command.to_string()));
+        return job_type;
+    }
+
+
+    // a worker script, with `$uuid` set to the uuid of each job it reads into `$line`
+    fn worker_script(body: &str) -> String {
+        return format!(r#"while read line; do uuid=$(echo "$line" | sed 's/.*"uuid":"\([^"]*\)".*/\1/'); {} done"#, body);
+    }
+
+    fn run(job_type: &JobType) -> Option<Result<Option<Value>, Value>> {
+        let job = make_job(job_type);
+        let mut executor = ProcessExecutor {};
+        let mut context = executor.execute(&job);
+        return context.result(&job, false);
+    }
+
+    #[test]
+    fn can_reuse_workers() {
+        let job_type = make_job_type(&worker_script(r#"echo "{\"type\": \"log\", \"job_uuid\": \"$uuid\", \"message\": \"working\"}"; echo "{\"type\": \"progress\", \"job_uuid\": \"$uuid\", \"percent\": 50}"; echo "{\"type\": \"result\", \"job_uuid\": \"$uuid\", \"results\": {\"pid\": $$, \"job\": $line}}";"#), Some(5000));
+        let job = make_job(&job_type);
+        let mut executor = ProcessExecutor {};
+        let mut context = executor.execute(&job);
+        let first = context.result(&job, false).unwrap().unwrap().unwrap();
+        assert_eq!(first.get("job").unwrap().get("job").unwrap().get("uuid"), Some(&json!(job.uuid)));
+        assert_eq!(context.take_logs(), Some("working\n".to_string()));
+        assert_eq!(context.take_logs(), None);
+        assert_eq!(context.take_progress(), Some(JobProgress { percent: Some(50.0), message: None, updated_at: 0 }));
+        assert_eq!(context.take_progress(), None);
+        let second = run(&job_type).unwrap().unwrap().unwrap();
+        assert_eq!(first.get("pid"), second.get("pid"));
+    }
+
+    #[test]
+    fn ignores_messages_for_other_jobs() {
+        // each job's result is followed by a late one, which the next job on the worker must not take as its own
+        let job_type = make_job_type(&worker_script(r#"echo "{\"type\": \"result\", \"job_uuid\": \"$uuid\", \"results\": {\"job\": \"$uuid\"}}"; echo "{\"type\": \"result\", \"job_uuid\": \"$uuid\", \"results\": {\"late\": true}}";"#), Some(5000));
+        for _ in 0..2 {
+            let job = make_job(&job_type);
+            let mut executor = ProcessExecutor {};
+            let mut context = executor.execute(&job);
+            assert_eq!(context.result(&job, false), Some(Ok(Some(json!({ "job": job.uuid })))));
+        }
+    }
+
+    #[test]
+    fn can_log_stderr() {
+        let job_type = make_job_type(&worker_script(r#"echo 'warning' 1>&2; sleep 0.1; echo "{\"type\": \"result\", \"job_uuid\": \"$uuid\"}";"#), Some(5000));
+        let job = make_job(&job_type);
+        let mut executor = ProcessExecutor {};
+        let mut context = executor.execute(&job);
+        assert_eq!(context.result(&job, false), Some(Ok(None)));
+        assert_eq!(context.take_logs(), Some("warning\n".to_string()));
+    }
+
+    #[test]
+    fn can_report_errors() {
+        let job_type = make_job_type(&worker_script(r#"echo "{\"type\": \"result\", \"job_uuid\": \"$uuid\", \"errors\": {\"kind\": \"failed\"}}";"#), Some(5000));
+        assert_eq!(run(&job_type), Some(Err(json!({ "kind": "failed" }))));
+    }
+
+    #[test]
+    fn can_restart_crashed_workers() {
+        let job_type = make_job_type(r#"read line; uuid=$(echo "$line" | sed 's/.*"uuid":"\([^"]*\)".*/\1/'); echo "{\"type\": \"result\", \"job_uuid\": \"$uuid\", \"results\": {\"pid\": $$}}"; read line; exit 3"#, Some(5000));
+        let first = run(&job_type).unwrap().unwrap().unwrap();
+        assert_eq!(run(&job_type), Some(Err(json!({ "kind": "worker_crashed", "exit_code": 3 }))));
+        let third = run(&job_type).unwrap().unwrap().unwrap();
+        assert_ne!(first.get("pid"), third.get("pid"));
+    }
+
+    #[test]
+    fn can_kill_worker_closing_stdout() {
+        let job_type = make_job_type("read line; exec 1>&-; sleep 10", Some(5000));
+        let job = make_job(&job_type);
+        let mut executor = ProcessExecutor {};
+        let mut context = executor.execute(&job);
+        let started_at = Instant::now();
+        let result = loop {
+            // waiting for the worker to exit never blocks a poll
+            let polled_at = Instant::now();
+            let result = context.result(&job, true);
+            assert!(polled_at.elapsed() < Duration::from_millis(100));
+            if let Some(result) = result {
+                break result;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(result, Err(json!({ "kind": "worker_crashed", "exit_code": null })));
+        assert!(started_at.elapsed() < Duration::from_millis(5000));
+    }
+
+    #[test]
+    fn can_time_out() {
+        let job_type = make_job_type("while read line; do sleep 10; done", Some(200));
+        let errors = run(&job_type).unwrap().unwrap_err();
+        assert_eq!(errors.get("kind"), Some(&json!("timeout")));
+    }
+}
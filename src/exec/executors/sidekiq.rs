@@ -1,22 +1,239 @@
 use std::process::*;
 use crate::exec::executor::*;
 use std::env;
-use crate::exec::job::Job;
+use crate::exec::job::{ Job, JobProgress };
 use log::*;
 use serde_json::{Value};
 use regex::Regex;
 use serde_json::map::Map;
-use super::bash::BashExecutorContext;
+use super::bash::{ BashExecutorContext, set_job_files };
 use std::io::{ BufWriter, Write };
+use std::os::unix::process::CommandExt;
+use serde_json::json;
+use crate::util::config;
+use crate::util::time::epoch;
+use std::thread;
+use std::time::{ Duration, Instant };
 
 pub struct SidekiqExecutor {
 
 }
 
-impl Executor for SidekiqExecutor {
-    type Context = BashExecutorContext;
+// a job pushed to the queue of an existing Sidekiq fleet, whose completion may be tracked
+pub struct SidekiqQueueContext {
+    pub pushed: Result<Value, Value>,
+    pub connection: Option<::redis::Connection>,
+    pub queue_key: String,
+    pub payload: String,
+    pub result_key: Option<String>,
+    pub timeout: Option<u64>,
+    pub started_at: Instant,
+    pub checked_at: Option<Instant>,
+}
+
+pub enum SidekiqExecutorContext {
+    Rails(BashExecutorContext),
+    Queue(SidekiqQueueContext),
+}
+
+// how often a tracked job's result is checked for in Sidekiq's redis
+const TRACK_POLL_MS: u64 = 250;
+// prefix of the key a tracked job's result is written to by the Sidekiq middleware, followed by its jid, see README
+const RESULT_KEY_PREFIX: &str = "synchrony_sidekiq_";
+
+// the job as Sidekiq's own client would push it, the arguments are passed to `perform` like in `rails` mode
+pub fn sidekiq_payload(job: &Job, worker: &str, queue: &str, track: bool, now: u64) -> Value {
+    let arguments = job.arguments.get("sidekiq_arguments").cloned().unwrap_or(Value::Null);
+    let mut payload = json!({
+        "class": worker,
+        "args": [arguments],
+        "jid": sidekiq_jid(job),
+        "queue": queue,
+        "created_at": now as f64 / 1000.0,
+        "enqueued_at": now as f64 / 1000.0,
+        "synchrony_job": job.uuid,
+    });
+    if track {
+        // tracked jobs are retried by their job type's retry policy instead of by Sidekiq
+        payload.as_object_mut().unwrap().insert("retry".to_string(), Value::Bool(false));
+        payload.as_object_mut().unwrap().insert("synchrony_track".to_string(), Value::Bool(true));
+    }
+    return payload;
+}
+
+// Sidekiq job ids are 24 hex characters
+pub fn sidekiq_jid(job: &Job) -> String {
+    return job.uuid.simple().to_string()[..24].to_string();
+}
+
+impl SidekiqQueueContext {
+    fn failed(errors: Value, timeout: Option<u64>) -> SidekiqQueueContext {
+        SidekiqQueueContext { pushed: Err(errors), connection: None, queue_key: "".to_string(), payload: "".to_string(), result_key: None, timeout: timeout, started_at: Instant::now(), checked_at: None }
+    }
+
+    fn timed_out(&self) -> bool {
+        return self.timeout.map(|timeout| self.started_at.elapsed() >= Duration::from_millis(timeout)).unwrap_or(false);
+    }
+
+    // removes the job from its Sidekiq queue if no Sidekiq process has fetched it yet
+    fn unqueue(&mut self) -> bool {
+        let removed: Result<u64, ::redis::RedisError> = ::redis::cmd("LREM").arg(&*self.queue_key).arg(1).arg(&*self.payload).query(self.connection.as_mut().unwrap());
+        return removed.unwrap_or(0) > 0;
+    }
+
+    fn check_result(&mut self, job: &Job) -> Option<Result<Option<Value>, Value>> {
+        let result_key = self.result_key.clone().unwrap();
+        let result: Result<Option<String>, ::redis::RedisError> = ::redis::cmd("GET").arg(&*result_key).query(self.connection.as_mut().unwrap());
+        let result = match result {
+            Err(e) => {
+                warn!("Failed to check sidekiq result of job '{}': {:?}", job.uuid.hyphenated(), e);
+                return None;
+            },
+            Ok(None) => return None,
+            Ok(Some(result)) => result,
+        };
+        let _: Result<(), ::redis::RedisError> = ::redis::cmd("DEL").arg(&*result_key).query(self.connection.as_mut().unwrap());
+        let result: Value = serde_json::from_str(&result).unwrap_or(Value::Null);
+        let mut output = self.pushed.clone().unwrap();
+        if result.get("status") == Some(&Value::String("succeeded".to_string())) {
+            output.as_object_mut().unwrap().insert("status".to_string(), Value::String("succeeded".to_string()));
+            return Some(Ok(Some(output)));
+        }
+        output.as_object_mut().unwrap().insert("kind".to_string(), Value::String("sidekiq_failed".to_string()));
+        output.as_object_mut().unwrap().insert("error".to_string(), result.get("error").cloned().unwrap_or(Value::Null));
+        return Some(Err(output));
+    }
+}
+
+impl ExecutionContext for SidekiqQueueContext {
+    fn result(&mut self, job: &Job, is_async: bool) -> Option<Result<Option<Value>, Value>> {
+        if self.pushed.is_err() {
+            return Some(Err(self.pushed.clone().unwrap_err()));
+        }
+        if self.result_key.is_none() {
+            return Some(Ok(Some(self.pushed.clone().unwrap())));
+        }
+        loop {
+            if self.checked_at.map(|checked_at| checked_at.elapsed() >= Duration::from_millis(TRACK_POLL_MS)).unwrap_or(true) {
+                self.checked_at = Some(Instant::now());
+                let result = self.check_result(job);
+                if result.is_some() {
+                    return result;
+                }
+            }
+            if self.timed_out() {
+                // a job already fetched by Sidekiq cannot be stopped, and is left to finish untracked
+                let unqueued = self.unqueue();
+                warn!("Sidekiq job '{}' timed out after {} ms, {}", job.uuid.hyphenated(), self.started_at.elapsed().as_millis(), if unqueued { "removed from its queue" } else { "no longer tracking it" });
+                return Some(Err(json!({ "kind": "timeout", "after_ms": self.started_at.elapsed().as_millis() as u64 })));
+            }
+            if is_async {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(TRACK_POLL_MS));
+        }
+    }
+
+    fn cancel(&mut self, job: &Job) -> Option<Value> {
+        if self.connection.is_some() && !self.unqueue() {
+            warn!("Sidekiq job '{}' was cancelled after Sidekiq fetched it, it will not be stopped", job.uuid.hyphenated());
+        }
+        return Some(json!({ "kind": "cancelled" }));
+    }
+}
+
+impl ExecutionContext for SidekiqExecutorContext {
+    fn result(&mut self, job: &Job, is_async: bool) -> Option<Result<Option<Value>, Value>> {
+        return match self {
+            SidekiqExecutorContext::Rails(context) => context.result(job, is_async),
+            SidekiqExecutorContext::Queue(context) => context.result(job, is_async),
+        }
+    }
+
+    fn cancel(&mut self, job: &Job) -> Option<Value> {
+        return match self {
+            SidekiqExecutorContext::Rails(context) => context.cancel(job),
+            SidekiqExecutorContext::Queue(context) => context.cancel(job),
+        }
+    }
+
+    fn take_logs(&mut self) -> Option<String> {
+        return match self {
+            SidekiqExecutorContext::Rails(context) => context.take_logs(),
+            SidekiqExecutorContext::Queue(context) => context.take_logs(),
+        }
+    }
+
+    fn take_progress(&mut self) -> Option<JobProgress> {
+        return match self {
+            SidekiqExecutorContext::Rails(context) => context.take_progress(),
+            SidekiqExecutorContext::Queue(context) => context.take_progress(),
+        }
+    }
+}
 
-    fn execute(&mut self, job: &Job) -> BashExecutorContext {
+impl SidekiqExecutor {
+    // pushes the job into a Sidekiq `queue:<name>` list, rather than booting rails to run it
+    fn execute_queue(&mut self, job: &Job) -> SidekiqQueueContext {
+        lazy_static! {
+            static ref SAFE_CLASS_REGEX: Regex = Regex::new("^[a-zA-Z0-9_]+(::[a-zA-Z0-9_]+)*$").unwrap();
+        }
+        let job_type = job.job_type.as_ref().unwrap();
+        let metadata = &job_type.metadata;
+        let timeout = job_type.timeout;
+        let sidekiq_worker = match metadata.get("sidekiq_worker") {
+            Some(Value::String(s)) if SAFE_CLASS_REGEX.is_match(s) => s,
+            _ => {
+                error!("No valid sidekiq_worker found in job type in sidekiq execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job.job_type_uuid.hyphenated());
+                return SidekiqQueueContext::failed(Value::Null, timeout);
+            },
+        };
+        let queue = match metadata.get("queue") {
+            None => "default",
+            Some(Value::String(s)) => s,
+            _ => {
+                error!("Invalid queue in job type in sidekiq execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job.job_type_uuid.hyphenated());
+                return SidekiqQueueContext::failed(Value::Null, timeout);
+            },
+        };
+        let default_url = format!("redis://{}:{}/0", &*config::REDIS_HOST, &*config::REDIS_PORT);
+        let redis_url = match metadata.get("redis_url") {
+            Some(Value::String(s)) => s.clone(),
+            _ => default_url,
+        };
+        let track = metadata.get("track_completion") == Some(&Value::Bool(true));
+        let payload = sidekiq_payload(job, sidekiq_worker, queue, track, epoch()).to_string();
+        let queue_key = format!("queue:{}", queue);
+        let connection = ::redis::Client::open(&*redis_url).and_then(|client| client.get_connection());
+        if connection.is_err() {
+            error!("Failed to connect to sidekiq redis for job '{}', job type '{}' / '{}': {:?}", job.uuid.hyphenated(), job_type.name, job.job_type_uuid.hyphenated(), connection.err().unwrap());
+            return SidekiqQueueContext::failed(json!({ "kind": "sidekiq_unavailable" }), timeout);
+        }
+        let mut connection = connection.unwrap();
+        // mirrors Sidekiq's client, which registers the queue and pushes to the left of its list
+        let pushed: Result<(), ::redis::RedisError> = ::redis::pipe().atomic()
+            .cmd("SADD").arg("queues").arg(queue).ignore()
+            .cmd("LPUSH").arg(&*queue_key).arg(&*payload).ignore()
+            .query(&mut connection);
+        if pushed.is_err() {
+            error!("Failed to push sidekiq job '{}', job type '{}' / '{}': {:?}", job.uuid.hyphenated(), job_type.name, job.job_type_uuid.hyphenated(), pushed.err().unwrap());
+            return SidekiqQueueContext::failed(json!({ "kind": "sidekiq_unavailable" }), timeout);
+        }
+        let jid = sidekiq_jid(job);
+        return SidekiqQueueContext {
+            pushed: Ok(json!({ "jid": jid, "queue": queue })),
+            connection: Some(connection),
+            queue_key: queue_key,
+            payload: payload,
+            result_key: if track { Some(format!("{}{}", RESULT_KEY_PREFIX, jid)) } else { None },
+            timeout: timeout,
+            started_at: Instant::now(),
+            checked_at: None,
+        };
+    }
+
+    // boots rails in a new ruby process to run the worker inline
+    fn execute_rails(&mut self, job: &Job) -> BashExecutorContext {
         lazy_static! {
             static ref SAFE_WORKER_REGEX: Regex = Regex::new("^[a-zA-Z0-9]+$").unwrap();
         }
@@ -29,7 +246,7 @@ impl Executor for SidekiqExecutor {
             },
             _ => {
                 error!("No rails_dir found in job type in sidekiq execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated());
-                return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };    
+                return BashExecutorContext::failed(timeout);    
             },
         };
         let sidekiq_worker = match metadata.get("sidekiq_worker") {
@@ -38,12 +255,12 @@ impl Executor for SidekiqExecutor {
             },
             _ => {
                 error!("No sidekiq_worker found in job type in sidekiq execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated());
-                return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };    
+                return BashExecutorContext::failed(timeout);    
             },
         };
         if !SAFE_WORKER_REGEX.is_match(sidekiq_worker) {
             error!("Invalid sidekiq_worker for job '{}', job type '{}' / '{}': '{}'", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), sidekiq_worker);
-            return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };    
+            return BashExecutorContext::failed(timeout);    
         }
         let ruby_executable = match metadata.get("ruby_executable") {
             Some(Value::String(s)) => {
@@ -93,7 +310,8 @@ impl Executor for SidekiqExecutor {
             .stdout(Stdio::piped())
             .arg("-c")
             .stdin(Stdio::piped())
-            .current_dir(rails_dir);
+            .current_dir(rails_dir)
+            .process_group(0);
         environment.iter().for_each(|(key, value)| {
             match value {
                 Value::String(s) => {
@@ -105,10 +323,11 @@ impl Executor for SidekiqExecutor {
             }            
         } );
         builder.arg(ruby_executable);
+        set_job_files(&mut builder, job);
         let handle = builder.spawn();
         if handle.is_err() {
             error!("Failed to spawn bash in bash execution for job '{}', job type '{}' / '{}': {:?}", job.uuid.hyphenated(), job.job_type.as_ref().unwrap().name, job.job_type_uuid.hyphenated(), handle.unwrap_err());
-            return BashExecutorContext { internal_failure: true, handle: None, timeout: timeout };
+            return BashExecutorContext::failed(timeout);
         }
         let mut handle = handle.unwrap();
         {
@@ -128,7 +347,24 @@ impl Executor for SidekiqExecutor {
                 .expect("failed to write to sidekiq executor stdin");
         }
         drop(handle.stdin.take());
-        return BashExecutorContext { internal_failure: false, handle: Some(handle), timeout: timeout }
+        return BashExecutorContext::started(handle, timeout, job)
+    }
+}
+
+impl Executor for SidekiqExecutor {
+    type Context = SidekiqExecutorContext;
+
+    fn execute(&mut self, job: &Job) -> SidekiqExecutorContext {
+        let job_type = job.job_type.as_ref().unwrap();
+        return match job_type.metadata.get("mode") {
+            None => SidekiqExecutorContext::Rails(self.execute_rails(job)),
+            Some(Value::String(mode)) if mode == "rails" => SidekiqExecutorContext::Rails(self.execute_rails(job)),
+            Some(Value::String(mode)) if mode == "queue" => SidekiqExecutorContext::Queue(self.execute_queue(job)),
+            _ => {
+                error!("Invalid mode in job type in sidekiq execution for job '{}', job type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job.job_type_uuid.hyphenated());
+                SidekiqExecutorContext::Rails(BashExecutorContext::failed(job_type.timeout))
+            },
+        }
     }
 }
 
@@ -136,24 +372,14 @@ impl Executor for SidekiqExecutor {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use uuid::Uuid;
+    use crate::exec::executors::tests as executor_tests;
     use crate::exec::job_type::JobType;
-    use std::collections::HashMap;
     use serde_json::Number;
 
     fn make_job_type(environment: Option<Map<String, Value>>) -> JobType {
-        let mut job_type = JobType {
-            executor: "sidekiq".to_string(),
-            name: "test".to_string(),
-            node_type: "default".to_string(),
-            timeout: None,
-            unique: false,
-            uuid: Uuid::new_v4(),
-            metadata: HashMap::new(),
-        };
+        let mut job_type = executor_tests::make_job_type("sidekiq");
         job_type.metadata.insert("rails_dir".to_string(), Value::String("./rails_test".to_string()));
         job_type.metadata.insert("sidekiq_worker".to_string(), Value::String("TestWorker".to_string()));
-        
         if environment.is_some() {
             job_type.metadata.insert("environment".to_string(), Value::Object(environment.unwrap()));
         }
@@ -161,18 +387,7 @@ mod tests {
     }
 
     fn make_job(job_type: &JobType, arguments: Option<Value>, environment: Option<Map<String, Value>>) -> Job {
-        let mut job = Job {
-            uuid: Uuid::new_v4(),
-            job_type_uuid: job_type.uuid,
-            job_type: Some(job_type.clone()),
-            arguments: HashMap::new(),
-            executing_node: None,
-            enqueued_at: None,
-            started_at: None,
-            ended_at: None,
-            results: None,
-            errors: None,
-        };
+        let mut job = executor_tests::make_job(job_type);
         if arguments.is_some() {
             job.arguments.insert("sidekiq_arguments".to_string(), arguments.unwrap());
         }
@@ -209,4 +424,52 @@ mod tests {
         rails_test(&job_type, Some(Value::Object(map)), "{\"test_key\":\"test_value\"}");
     }
 
+    #[test]
+    fn can_build_sidekiq_payload() {
+        let job_type = make_job_type(None);
+        let job = make_job(&job_type, Some(json!({ "test_key": "test_value" })), None);
+        let payload = sidekiq_payload(&job, "Admin::TestWorker", "low", false, 1500);
+        assert_eq!(payload, json!({
+            "class": "Admin::TestWorker",
+            "args": [{ "test_key": "test_value" }],
+            "jid": sidekiq_jid(&job),
+            "queue": "low",
+            "created_at": 1.5,
+            "enqueued_at": 1.5,
+            "synchrony_job": job.uuid,
+        }));
+        assert_eq!(sidekiq_jid(&job).len(), 24);
+        let job = make_job(&job_type, None, None);
+        let payload = sidekiq_payload(&job, "TestWorker", "default", true, 1500);
+        assert_eq!(payload.get("args"), Some(&json!([null])));
+        assert_eq!(payload.get("retry"), Some(&json!(false)));
+        assert_eq!(payload.get("synchrony_track"), Some(&json!(true)));
+    }
+
+    // requires a redis server, so only runs when testing the redis store
+    #[test]
+    fn can_push_sidekiq_job() {
+        if &*config::STORE_TYPE != "redis" {
+            return;
+        }
+        let mut job_type = make_job_type(None);
+        job_type.metadata.insert("mode".to_string(), json!("queue"));
+        job_type.metadata.insert("queue".to_string(), json!("synchrony_test"));
+        job_type.metadata.insert("track_completion".to_string(), json!(true));
+        job_type.timeout = Some(5000);
+        let job = make_job(&job_type, Some(json!(1)), None);
+        let mut connection = ::redis::Client::open(&*format!("redis://{}:{}/0", &*config::REDIS_HOST, &*config::REDIS_PORT)).unwrap().get_connection().unwrap();
+        let _: () = ::redis::cmd("DEL").arg("queue:synchrony_test").query(&mut connection).unwrap();
+        let mut context = (SidekiqExecutor {}).execute(&job);
+        assert_eq!(context.result(&job, true), None);
+        let pushed: Vec<String> = ::redis::cmd("LRANGE").arg("queue:synchrony_test").arg(0).arg(-1).query(&mut connection).unwrap();
+        assert_eq!(pushed.len(), 1);
+        let payload: Value = serde_json::from_str(&pushed[0]).unwrap();
+        assert_eq!(payload.get("jid"), Some(&json!(sidekiq_jid(&job))));
+        // as the tracking middleware would on success
+        let _: () = ::redis::cmd("SET").arg(format!("{}{}", RESULT_KEY_PREFIX, sidekiq_jid(&job))).arg("{\"status\":\"succeeded\"}").query(&mut connection).unwrap();
+        assert_eq!(context.result(&job, false), Some(Ok(Some(json!({ "jid": sidekiq_jid(&job), "queue": "synchrony_test", "status": "succeeded" })))));
+        let _: () = ::redis::cmd("DEL").arg("queue:synchrony_test").query(&mut connection).unwrap();
+    }
+
 }
\ No newline at end of file
@@ -2,18 +2,182 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use serde_json::Value;
-use super::job_type::JobType;
+use super::job_type::{ JobType, Priority };
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    #[default]
+    Queued, // waiting in its node type's queue
+    Scheduled, // waiting for its `run_at` time, for its job type's unique lock, or for room under its job type's limits
+    Running,
+    Succeeded,
+    Failed, // returned errors or a non-zero exit code
+    TimedOut,
+    Cancelled,
+    Retrying, // waiting to be queued again by its job type's retry policy
+    Dead, // failed all attempts allowed by its job type's retry policy
+}
+
+impl JobStatus {
+    pub fn can_transition_to(self, next: JobStatus) -> bool {
+        use JobStatus::*;
+        match (self, next) {
+            (Queued, Scheduled) | (Queued, Running) | (Queued, Cancelled) => true,
+            (Scheduled, Queued) | (Scheduled, Cancelled) => true,
+            // running jobs are queued again when their node is reaped, and delayed when their job type's rate limit is exceeded
+            (Running, Queued) | (Running, Scheduled) | (Running, Succeeded) | (Running, Failed) | (Running, TimedOut) | (Running, Cancelled) | (Running, Retrying) | (Running, Dead) => true,
+            (Retrying, Queued) | (Retrying, Cancelled) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_finished(self) -> bool {
+        return matches!(self, JobStatus::Succeeded | JobStatus::Failed | JobStatus::TimedOut | JobStatus::Cancelled | JobStatus::Dead);
+    }
+
+    // the status a job finishes with, given its results and errors
+    pub fn from_outcome(results: &Option<Value>, errors: &Option<Value>) -> JobStatus {
+        if errors.is_some() {
+            return match errors.as_ref().unwrap().get("kind").and_then(|kind| kind.as_str()) {
+                Some("timeout") => JobStatus::TimedOut,
+                Some("cancelled") => JobStatus::Cancelled,
+                _ => JobStatus::Failed,
+            };
+        }
+        let exit_code = results.as_ref().and_then(|results| results.get("exit_code"));
+        if exit_code.is_some() && exit_code.unwrap().as_i64() != Some(0) {
+            return JobStatus::Failed;
+        }
+        return JobStatus::Succeeded;
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
 pub struct Job {
     pub uuid: Uuid,
+    #[serde(default)] pub status: JobStatus, // as last set outside redis scripts, which move queued jobs without rewriting them
     pub job_type_uuid: Uuid,
     #[serde(skip)] pub job_type: Option<JobType>,
     pub arguments: HashMap<String, Value>,
+    #[serde(default)] pub priority: Priority, // the job type's priority, unless overridden when enqueued
     pub executing_node: Option<Uuid>,
     pub enqueued_at: Option<u64>,
+    #[serde(default)] pub run_at: Option<u64>, // when a delayed job is due to be queued
     pub started_at: Option<u64>,
     pub ended_at: Option<u64>,
     pub results: Option<Value>,
     pub errors: Option<Value>,
+    #[serde(default)] pub attempt: u32, // number of previous attempts, when retried by the job type's retry policy
+    #[serde(default)] pub progress: Option<JobProgress>, // last reported by the job while running
+}
+
+// how far along a running job is, as reported by the job itself
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct JobProgress {
+    #[serde(default)] pub percent: Option<f64>,
+    #[serde(default)] pub message: Option<String>,
+    #[serde(default)] pub updated_at: u64, // milliseconds UNIX epoch, set by the node when it is reported
+}
+
+impl Job {
+    // moves the job to `status`, failing if its current status does not allow it
+    pub fn transition(&mut self, status: JobStatus) -> Result<(), String> {
+        if !self.status.can_transition_to(status) {
+            return Err(format!("invalid status transition for job '{}': {:?} to {:?}", self.uuid.hyphenated(), self.status, status));
+        }
+        self.status = status;
+        return Ok(());
+    }
+}
+
+// how much of a job's output its logs keep, older output is dropped
+pub const JOB_LOGS_CAPACITY: usize = 1024 * 1024;
+// how long a job's logs are kept once closed, unless more output is appended
+pub const JOB_LOGS_RETENTION_MS: u64 = 60 * 60 * 1000;
+
+// the output of a job's current or last attempt, as a ring buffer addressed by byte offsets into all output ever appended
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct JobLogs {
+    pub output: String, // the retained tail of the output
+    pub end: u64, // offset just past `output`
+    pub closed: bool, // set once the attempt finishes, until more output is appended
+}
+
+impl JobLogs {
+    pub fn append(&mut self, output: &str) {
+        self.output.push_str(output);
+        self.end += output.len() as u64;
+        self.closed = false;
+        self.trim();
+    }
+
+    // drops the oldest output beyond the capacity, for stores that keep more than it between reads
+    pub fn trim(&mut self) {
+        if self.output.len() > JOB_LOGS_CAPACITY {
+            let mut cut = self.output.len() - JOB_LOGS_CAPACITY;
+            while !self.output.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.output.drain(..cut);
+        }
+    }
+
+    // offset of the start of `output`
+    pub fn start(&self) -> u64 {
+        return self.end - self.output.len() as u64;
+    }
+
+    // the retained output from `offset` on, starting from the oldest retained output if it has been dropped
+    pub fn since(&self, offset: u64) -> &str {
+        let mut skip = offset.saturating_sub(self.start()).min(self.output.len() as u64) as usize;
+        while !self.output.is_char_boundary(skip) {
+            skip += 1;
+        }
+        return &self.output[skip..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn can_validate_transitions() {
+        assert!(JobStatus::Queued.can_transition_to(JobStatus::Running));
+        assert!(JobStatus::Running.can_transition_to(JobStatus::Retrying));
+        assert!(JobStatus::Retrying.can_transition_to(JobStatus::Queued));
+        assert!(JobStatus::Running.can_transition_to(JobStatus::Scheduled));
+        assert!(!JobStatus::Queued.can_transition_to(JobStatus::Succeeded));
+        assert!(!JobStatus::Succeeded.can_transition_to(JobStatus::Queued));
+        assert!(!JobStatus::Dead.can_transition_to(JobStatus::Queued));
+        assert!(!JobStatus::Running.can_transition_to(JobStatus::Running));
+    }
+
+    #[test]
+    fn can_classify_outcomes() {
+        assert_eq!(JobStatus::from_outcome(&Some(json!({"exit_code": 0})), &None), JobStatus::Succeeded);
+        assert_eq!(JobStatus::from_outcome(&Some(json!({"exit_code": 1})), &None), JobStatus::Failed);
+        assert_eq!(JobStatus::from_outcome(&None, &Some(json!("errors"))), JobStatus::Failed);
+        assert_eq!(JobStatus::from_outcome(&None, &Some(json!({"kind": "timeout"}))), JobStatus::TimedOut);
+        assert_eq!(JobStatus::from_outcome(&None, &Some(json!({"kind": "cancelled"}))), JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn can_keep_job_logs() {
+        let mut logs = JobLogs::default();
+        logs.append("first\n");
+        logs.closed = true;
+        logs.append("second\n");
+        assert!(!logs.closed);
+        assert_eq!(logs.since(0), "first\nsecond\n");
+        assert_eq!(logs.since(6), "second\n");
+        assert_eq!(logs.since(100), "");
+        logs.append(&"x".repeat(JOB_LOGS_CAPACITY));
+        assert_eq!(logs.output.len(), JOB_LOGS_CAPACITY);
+        assert_eq!(logs.end, 13 + JOB_LOGS_CAPACITY as u64);
+        assert_eq!(logs.start(), 13);
+        assert_eq!(logs.since(0).len(), JOB_LOGS_CAPACITY);
+    }
 }
@@ -2,6 +2,176 @@ use uuid::Uuid;
 use serde_json::Value;
 use serde::{ Deserialize, Serialize };
 use std::collections::HashMap;
+use rand::Rng;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UniquePolicy {
+    #[default]
+    Reject, // refuse the new job
+    Coalesce, // drop the new job in favor of the existing one
+    Defer, // hold the new job until the existing one finishes
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+// the priority preferred by each dequeue of a node, in turn: 6 in 10 prefer high, 3 normal and 1 low, so lower priorities are never starved
+const DEQUEUE_CYCLE: [Priority; 10] = [
+    Priority::High, Priority::Normal, Priority::High, Priority::High, Priority::Normal,
+    Priority::High, Priority::Low, Priority::High, Priority::Normal, Priority::High,
+];
+
+impl Priority {
+    // highest first
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    // the order in which a node's `dequeue_count`th dequeue checks each priority: the preferred priority first, then highest first
+    pub fn dequeue_order(dequeue_count: u64) -> Vec<Priority> {
+        let preferred = DEQUEUE_CYCLE[(dequeue_count % DEQUEUE_CYCLE.len() as u64) as usize];
+        let mut order = vec![preferred];
+        order.extend(Priority::ALL.iter().filter(|priority| **priority != preferred));
+        return order;
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryOutcome {
+    Error, // the executor failed to run the job, or returned an error
+    Timeout, // the job exceeded its timeout
+    NonZeroExit, // the job ran, but exited with a non-zero `exit_code`
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_base_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_delay_ms() -> u64 {
+    300000
+}
+
+fn default_retry_on() -> Vec<RetryOutcome> {
+    vec![RetryOutcome::Error, RetryOutcome::Timeout, RetryOutcome::NonZeroExit]
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_attempts")] pub max_attempts: u32, // including the first attempt
+    #[serde(default = "default_base_delay_ms")] pub base_delay_ms: u64,
+    #[serde(default = "default_max_delay_ms")] pub max_delay_ms: u64,
+    #[serde(default)] pub jitter: f64, // fraction of the delay that is randomized, from 0 to 1
+    #[serde(default = "default_retry_on")] pub retry_on: Vec<RetryOutcome>,
+}
+
+impl RetryPolicy {
+    // delay before the attempt following `attempt` (0 being the first), doubling each time up to `max_delay_ms`
+    pub fn delay_ms(&self, attempt: u32) -> u64 {
+        let delay = self.base_delay_ms.saturating_mul(2u64.saturating_pow(attempt)).min(self.max_delay_ms);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        return (delay as f64 * (1.0 - jitter * rand::thread_rng().gen::<f64>())) as u64;
+    }
+}
+
+// allows `limit` jobs to start per `period_ms`, as a token bucket holding up to `limit` tokens
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub period_ms: u64,
+}
+
+// the token bucket of a rate limited job type, shared by all nodes through the store
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RateLimitBucket {
+    pub tokens: f64,
+    pub updated_at: u64, // milliseconds UNIX epoch
+}
+
+impl RateLimit {
+    // the bucket as of `now`, refilled at `limit` tokens per `period_ms`. a job type without a bucket yet starts with a full one
+    pub fn refill(&self, bucket: Option<&RateLimitBucket>, now: u64) -> RateLimitBucket {
+        let capacity = self.limit as f64;
+        if bucket.is_none() {
+            return RateLimitBucket { tokens: capacity, updated_at: now };
+        }
+        let bucket = bucket.unwrap();
+        let elapsed = now.saturating_sub(bucket.updated_at) as f64;
+        let tokens = (bucket.tokens + elapsed * capacity / self.period_ms.max(1) as f64).min(capacity);
+        return RateLimitBucket { tokens: tokens, updated_at: now.max(bucket.updated_at) };
+    }
+
+    // how long until the bucket holds a whole token
+    pub fn wait_ms(&self, bucket: &RateLimitBucket) -> u64 {
+        if bucket.tokens >= 1.0 {
+            return 0;
+        }
+        return ((1.0 - bucket.tokens) * self.period_ms as f64 / self.limit.max(1) as f64).ceil() as u64;
+    }
+}
+
+// caps the size of each string field of a job's results and errors, such as `stdout`, keeping the start and end of longer ones
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct OutputLimit {
+    pub head_bytes: usize,
+    #[serde(default)] pub tail_bytes: usize,
+}
+
+impl OutputLimit {
+    // truncates the fields of a results or errors object over the limit, marking it `truncated` with each such field's total `<field>_bytes`
+    pub fn apply(&self, value: &mut Value) {
+        let fields = match value.as_object_mut() {
+            Some(fields) => fields,
+            None => return,
+        };
+        let mut truncated = vec![];
+        for (name, field) in fields.iter_mut() {
+            let text = match field.as_str() {
+                Some(text) if text.len() > self.head_bytes + self.tail_bytes => text,
+                _ => continue,
+            };
+            let mut head_end = self.head_bytes;
+            while !text.is_char_boundary(head_end) {
+                head_end -= 1;
+            }
+            let mut tail_start = text.len() - self.tail_bytes;
+            while !text.is_char_boundary(tail_start) {
+                tail_start += 1;
+            }
+            let bytes = text.len();
+            let kept = format!("{}\n[... {} bytes truncated ...]\n{}", &text[..head_end], tail_start - head_end, &text[tail_start..]);
+            *field = Value::String(kept);
+            truncated.push((format!("{}_bytes", name), bytes));
+        }
+        if truncated.is_empty() {
+            return;
+        }
+        for (name, bytes) in truncated {
+            fields.insert(name, Value::from(bytes));
+        }
+        fields.insert("truncated".to_string(), Value::Bool(true));
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct JobType {
@@ -10,6 +180,82 @@ pub struct JobType {
     pub executor: String,
     pub metadata: HashMap<String, Value>,
     pub unique: bool,
+    #[serde(default)] pub unique_policy: UniquePolicy,
     pub node_type: String, // name not UUID to avoid versioning issues until node_types have more attached data
     pub timeout: Option<u64>,
+    #[serde(default)] pub retry: Option<RetryPolicy>,
+    #[serde(default)] pub priority: Priority, // default priority of the job type's jobs
+    #[serde(default)] pub max_concurrency: Option<u32>, // maximum number of the job type's jobs running across all nodes
+    #[serde(default)] pub rate_limit: Option<RateLimit>, // maximum rate the job type's jobs start at across all nodes
+    #[serde(default)] pub output_limit: Option<OutputLimit>, // maximum size of each field of the job type's jobs' results and errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn can_order_priorities() {
+        assert_eq!(Priority::dequeue_order(0), vec![Priority::High, Priority::Normal, Priority::Low]);
+        assert_eq!(Priority::dequeue_order(1), vec![Priority::Normal, Priority::High, Priority::Low]);
+        assert_eq!(Priority::dequeue_order(6), vec![Priority::Low, Priority::High, Priority::Normal]);
+        let preferred: Vec<Priority> = (0..100).map(|count| Priority::dequeue_order(count)[0]).collect();
+        assert_eq!(preferred.iter().filter(|priority| **priority == Priority::High).count(), 60);
+        assert_eq!(preferred.iter().filter(|priority| **priority == Priority::Low).count(), 10);
+    }
+
+    #[test]
+    fn can_refill_rate_limit_buckets() {
+        let rate_limit = RateLimit { limit: 2, period_ms: 1000 };
+        let bucket = rate_limit.refill(None, 10000);
+        assert_eq!(bucket, RateLimitBucket { tokens: 2.0, updated_at: 10000 });
+        let empty = RateLimitBucket { tokens: 0.0, updated_at: 10000 };
+        assert_eq!(rate_limit.wait_ms(&empty), 500);
+        let bucket = rate_limit.refill(Some(&empty), 10250);
+        assert_eq!(bucket.tokens, 0.5);
+        assert_eq!(rate_limit.wait_ms(&bucket), 250);
+        assert_eq!(rate_limit.wait_ms(&rate_limit.refill(Some(&bucket), 10500)), 0);
+        assert_eq!(rate_limit.refill(Some(&empty), 60000).tokens, 2.0);
+    }
+
+    #[test]
+    fn can_limit_output() {
+        let limit = OutputLimit { head_bytes: 4, tail_bytes: 3 };
+        let mut results = json!({ "stdout": "0123456789", "stderr": "short", "exit_code": 0 });
+        limit.apply(&mut results);
+        assert_eq!(results, json!({
+            "stdout": "0123\n[... 3 bytes truncated ...]\n789",
+            "stdout_bytes": 10,
+            "stderr": "short",
+            "exit_code": 0,
+            "truncated": true,
+        }));
+        let mut results = json!({ "stdout": "ééééé" });
+        limit.apply(&mut results);
+        assert_eq!(results["stdout"], Value::String("éé\n[... 4 bytes truncated ...]\né".to_string()));
+        let mut results = json!({ "stdout": "aéééé" });
+        limit.apply(&mut results);
+        assert_eq!(results["stdout"], Value::String("aé\n[... 4 bytes truncated ...]\né".to_string()));
+        let mut results = json!({ "stdout": "0123456" });
+        limit.apply(&mut results);
+        assert_eq!(results, json!({ "stdout": "0123456" }));
+    }
+
+    #[test]
+    fn can_back_off_exponentially() {
+        let mut policy: RetryPolicy = serde_json::from_str("{\"base_delay_ms\": 100, \"max_delay_ms\": 1000}").unwrap();
+        assert_eq!(policy.max_attempts, 3);
+        assert_eq!(policy.retry_on, default_retry_on());
+        assert_eq!(policy.delay_ms(0), 100);
+        assert_eq!(policy.delay_ms(1), 200);
+        assert_eq!(policy.delay_ms(3), 800);
+        assert_eq!(policy.delay_ms(4), 1000);
+        assert_eq!(policy.delay_ms(100), 1000);
+        policy.jitter = 0.5;
+        for attempt in 0..5 {
+            let delay = policy.delay_ms(attempt);
+            assert!(delay <= policy.base_delay_ms * 2u64.pow(attempt) && delay >= (policy.base_delay_ms * 2u64.pow(attempt)).min(1000) / 2);
+        }
+    }
 }
\ No newline at end of file
@@ -3,4 +3,5 @@ pub mod job_type;
 pub mod node_type;
 pub mod executor;
 pub mod executors;
+pub mod registry;
 pub mod node;
\ No newline at end of file
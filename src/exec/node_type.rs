@@ -6,4 +6,5 @@ pub struct NodeType {
     pub uuid: Uuid,
     pub name: String,
     pub thread_count: u32,
+    #[serde(default)] pub jobs_per_thread: Option<u32>, // jobs each worker thread runs at once, polling them asynchronously
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+use super::executor::DynExecutor;
+use super::executors::{ bash::BashExecutor, http::HttpExecutor, process::ProcessExecutor, sidekiq::SidekiqExecutor };
+use std::collections::BTreeMap;
+use std::sync::{ Arc, RwLock };
+
+// creates a new executor for each job, executors are matched to job types by their `executor` name
+pub type ExecutorFactory = Arc<dyn Fn() -> Box<dyn DynExecutor> + Send + Sync>;
+
+lazy_static! {
+    static ref EXECUTORS: RwLock<BTreeMap<String, ExecutorFactory>> = {
+        let mut executors: BTreeMap<String, ExecutorFactory> = BTreeMap::new();
+        executors.insert("bash".to_string(), Arc::new(|| Box::new(BashExecutor {})));
+        executors.insert("http".to_string(), Arc::new(|| Box::new(HttpExecutor {})));
+        executors.insert("process".to_string(), Arc::new(|| Box::new(ProcessExecutor {})));
+        executors.insert("sidekiq".to_string(), Arc::new(|| Box::new(SidekiqExecutor {})));
+        RwLock::new(executors)
+    };
+}
+
+// registers an executor under `name`, replacing any executor already registered under it, including builtins
+pub fn register_executor<F>(name: &str, factory: F) where F: Fn() -> Box<dyn DynExecutor> + Send + Sync + 'static {
+    EXECUTORS.write().unwrap().insert(name.to_string(), Arc::new(factory));
+}
+
+pub fn create_executor(name: &str) -> Option<Box<dyn DynExecutor>> {
+    // the factory is called outside the lock, so it may itself use the registry
+    let factory = EXECUTORS.read().unwrap().get(name).cloned();
+    return factory.map(|factory| factory());
+}
+
+// sorted names of all registered executors
+pub fn executor_names() -> Vec<String> {
+    return EXECUTORS.read().unwrap().keys().cloned().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::executor::*;
+    use crate::exec::job::Job;
+    use serde_json::{ Value, json };
+
+    struct EchoExecutor {}
+
+    struct EchoExecutorContext {
+        arguments: Value,
+    }
+
+    impl ExecutionContext for EchoExecutorContext {
+        fn result(&mut self, _: &Job, _: bool) -> Option<Result<Option<Value>, Value>> {
+            return Some(Ok(Some(self.arguments.clone())));
+        }
+
+        fn cancel(&mut self, _: &Job) -> Option<Value> {
+            return Some(json!({ "kind": "cancelled" }));
+        }
+    }
+
+    impl Executor for EchoExecutor {
+        type Context = EchoExecutorContext;
+
+        fn execute(&mut self, job: &Job) -> EchoExecutorContext {
+            return EchoExecutorContext { arguments: serde_json::to_value(&job.arguments).unwrap() };
+        }
+    }
+
+    #[test]
+    fn can_register_executors() {
+        assert!(executor_names().contains(&"bash".to_string()));
+        assert!(create_executor("registry_test_echo").is_none());
+        register_executor("registry_test_echo", || Box::new(EchoExecutor {}));
+        assert!(executor_names().contains(&"registry_test_echo".to_string()));
+        let job: Job = serde_json::from_value(json!({
+            "uuid": "00000000-0000-0000-0000-000000000001",
+            "job_type_uuid": "00000000-0000-0000-0000-000000000002",
+            "arguments": { "value": 1 },
+        })).unwrap();
+        let mut context = create_executor("registry_test_echo").unwrap().execute_boxed(&job);
+        assert_eq!(context.result(&job, false), Some(Ok(Some(json!({ "value": 1 })))));
+    }
+}
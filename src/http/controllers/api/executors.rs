@@ -0,0 +1,42 @@
+use iron::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::exec::registry::executor_names;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IndexResponse {
+    executors: Vec<String>,
+}
+
+pub fn index(
+    _: &mut Request,
+    _: &(),
+) -> Result<IndexResponse, IronResult<Response>> {
+    Ok(IndexResponse {
+        executors: executor_names(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iron_test::request::get;
+    use iron::Headers;
+    use crate::http::controllers::tests::*;
+    use crate::config;
+    use iron::status;
+    use crate::http::tests::initialize_tests;
+    use crate::store::{ self, StoreRef };
+
+    #[test]
+    fn test_executors_index() -> Result<(), String> {
+        let store: StoreRef = store::init_store_untyped();
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/executors", &*config::HTTP_BIND_ADDRESS), Headers::new(), &initialize_tests(store)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: IndexResponse = parse_body(response.body)?;
+        for name in ["bash", "http", "process", "sidekiq"] {
+            assert!(body.executors.contains(&name.to_string()));
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,15 @@
 use crate::http::middleware::redis::IronRedis;
 use iron::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::exec::job_type::JobType;
+use crate::exec::job_type::{ JobType, UniquePolicy, RetryPolicy, Priority, RateLimit, RateLimitBucket, OutputLimit };
 use uuid::Uuid;
 use std::collections::HashMap;
 use serde_json::Value;
 use super::{ get_uuid_from_arg, redis_error_translate, option_translate };
+use crate::http::helpers::control::status_error;
+use iron::status;
+use log::*;
+use crate::util::time::epoch;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct IndexResponse {
@@ -43,6 +47,36 @@ pub fn get(
     Ok(job_type)
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RateLimitResponse {
+    rate_limit: RateLimit,
+    bucket: RateLimitBucket, // refilled up to now
+    wait_ms: u64, // until the next job of the job type may start
+}
+
+pub fn rate_limit(
+    req: &mut Request,
+    _: &(),
+) -> Result<RateLimitResponse, IronResult<Response>> {
+    let uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let job_type = option_translate(
+        redis_error_translate(store.get_job_type(uuid))?
+    )?;
+    let rate_limit = option_translate(job_type.rate_limit)?;
+    let bucket = redis_error_translate(store.get_rate_limit_bucket(uuid))?;
+    let bucket = rate_limit.refill(bucket.as_ref(), epoch());
+    Ok(RateLimitResponse {
+        wait_ms: rate_limit.wait_ms(&bucket),
+        rate_limit: rate_limit,
+        bucket: bucket,
+    })
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostResponse {
     status: String,
@@ -55,14 +89,28 @@ pub struct PostBody {
     pub executor: String,
     pub metadata: HashMap<String, Value>,
     pub unique: bool,
+    #[serde(default)] pub unique_policy: UniquePolicy,
     pub node_type: String,
     pub timeout: Option<u64>,
+    #[serde(default)] pub retry: Option<RetryPolicy>,
+    #[serde(default)] pub priority: Priority,
+    #[serde(default)] pub max_concurrency: Option<u32>,
+    #[serde(default)] pub rate_limit: Option<RateLimit>,
+    #[serde(default)] pub output_limit: Option<OutputLimit>,
 }
 
 pub fn post(
     req: &mut Request,
     body: &PostBody,
 ) -> Result<PostResponse, IronResult<Response>> {
+    if body.max_concurrency == Some(0) {
+        warn!("max_concurrency must be at least 1");
+        return Err(status_error(status::BadRequest));
+    }
+    if body.rate_limit.as_ref().map(|rate_limit| rate_limit.limit == 0 || rate_limit.period_ms == 0).unwrap_or(false) {
+        warn!("rate_limit limit and period_ms must be at least 1");
+        return Err(status_error(status::BadRequest));
+    }
     let mut store = req.extensions
         .get::<IronRedis>()
         .unwrap()
@@ -74,8 +122,14 @@ pub fn post(
         executor: body.executor.clone(),
         metadata: body.metadata.clone(),
         unique: body.unique,
+        unique_policy: body.unique_policy,
         node_type: body.node_type.clone(),
         timeout: body.timeout,
+        retry: body.retry.clone(),
+        priority: body.priority,
+        max_concurrency: body.max_concurrency,
+        rate_limit: body.rate_limit.clone(),
+        output_limit: body.output_limit.clone(),
     };
     redis_error_translate(store.new_job_type(&job_type))?;
     Ok(PostResponse {
@@ -92,9 +146,8 @@ mod tests {
     use iron::{ Headers, headers::ContentType };
     use crate::http::controllers::tests::*;
     use crate::config;
-    use iron::status;
     use crate::http::tests::initialize_tests;
-    use crate::store::{ self, StoreRef, tests::* };
+    use crate::store::{ self, StoreRef, take_rate_limit_token, tests::* };
 
     #[test]
     fn test_job_types_index() -> Result<(), String> {
@@ -128,6 +181,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_job_types_rate_limit() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/job_types/{}/rate_limit", &*config::HTTP_BIND_ADDRESS, test_job_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::NotFound));
+
+        test_job_type.rate_limit = Some(RateLimit { limit: 2, period_ms: 60000 });
+        store.new_job_type(&test_job_type)?;
+        let response = iron_error_translate(get(&*format!("http://{}/api/job_types/{}/rate_limit", &*config::HTTP_BIND_ADDRESS, test_job_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: RateLimitResponse = parse_body(response.body)?;
+        assert_eq!(body.bucket.tokens, 2.0);
+        assert_eq!(body.wait_ms, 0);
+
+        assert_eq!(take_rate_limit_token(&mut store, &test_job_type)?, None);
+        assert_eq!(take_rate_limit_token(&mut store, &test_job_type)?, None);
+        let response = iron_error_translate(get(&*format!("http://{}/api/job_types/{}/rate_limit", &*config::HTTP_BIND_ADDRESS, test_job_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        let body: RateLimitResponse = parse_body(response.body)?;
+        assert!(body.bucket.tokens < 1.0);
+        assert!(body.wait_ms > 0 && body.wait_ms <= 30000);
+        Ok(())
+    }
+
     #[test]
     fn test_job_types_post() -> Result<(), String> {
         let mut store: StoreRef = store::init_store_untyped();
@@ -140,14 +221,20 @@ mod tests {
             name: "test".to_string(),
             node_type: "default".to_string(),
             timeout: None,
+            retry: None,
+            priority: Priority::High,
+            max_concurrency: Some(2),
+            rate_limit: Some(RateLimit { limit: 10, period_ms: 60000 }),
+            output_limit: Some(OutputLimit { head_bytes: 1024, tail_bytes: 1024 }),
             unique: false,
+            unique_policy: UniquePolicy::Reject,
             metadata: HashMap::new(),
         };
         let job_type_serialized = serde_json::to_string(&test_job_type).unwrap();
 
         let mut headers = Headers::new();
         headers.set::<ContentType>(ContentType::json());
-        let response = iron_error_translate(post(&*format!("http://{}/api/job_types", &*config::HTTP_BIND_ADDRESS), headers, &*job_type_serialized, &initialize_tests(store.replicate()?)))?;
+        let response = iron_error_translate(post(&*format!("http://{}/api/job_types", &*config::HTTP_BIND_ADDRESS), headers.clone(), &*job_type_serialized, &initialize_tests(store.replicate()?)))?;
         assert_eq!(response.status, Some(status::Ok));
         let body: PostResponse = parse_body(response.body)?;
         let new_job = store.get_job_type(body.uuid)?.unwrap();
@@ -157,9 +244,19 @@ mod tests {
             name: test_job_type.name,
             node_type: test_job_type.node_type,
             timeout: test_job_type.timeout,
+            retry: test_job_type.retry,
+            priority: test_job_type.priority,
+            max_concurrency: test_job_type.max_concurrency,
+            rate_limit: test_job_type.rate_limit,
+            output_limit: test_job_type.output_limit,
             unique: test_job_type.unique,
+            unique_policy: test_job_type.unique_policy,
             metadata: test_job_type.metadata,
         });
+
+        let body = "{\"name\": \"test\", \"executor\": \"bash\", \"metadata\": {}, \"unique\": false, \"node_type\": \"default\", \"timeout\": null, \"max_concurrency\": 0}";
+        let response = iron_error_translate(post(&*format!("http://{}/api/job_types", &*config::HTTP_BIND_ADDRESS), headers, body, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
         Ok(())
     }
 
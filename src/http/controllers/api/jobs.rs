@@ -7,17 +7,47 @@
 use crate::http::middleware::redis::IronRedis;
 use iron::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::exec::job::Job;
+use crate::exec::job::{ Job, JobStatus };
+use crate::exec::job_type::Priority;
 use uuid::Uuid;
 use std::collections::HashMap;
 use serde_json::Value;
-use super::{ get_uuid_from_arg, redis_error_translate, option_translate };
+use super::{ get_uuid_from_arg, get_query_values, redis_error_translate, option_translate };
+use crate::store::{ CancelResult, EnqueueResult, StoreRef, job_node_type_uuid };
+use router::Router;
+use crate::http::helpers::control::status_error;
+use iron::status;
+use log::*;
+use crate::util::time::epoch;
+use iron::mime::*;
+use iron::response::WriteBody;
+use std::io::{ self, Write };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::{ Duration, Instant };
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct IndexResponse {
     jobs: Vec<Job>,
 }
 
+// keeps only the jobs with one of the statuses given by the `status` query parameter, if any
+fn filter_status(req: &Request, jobs: Vec<Job>) -> Result<Vec<Job>, IronResult<Response>> {
+    let mut statuses: Vec<JobStatus> = vec![];
+    for value in get_query_values(req, "status") {
+        let status: Result<JobStatus, serde_json::Error> = serde_json::from_value(Value::String(value.clone()));
+        if status.is_err() {
+            warn!("Invalid job status: {}", value);
+            return Err(status_error(status::BadRequest));
+        }
+        statuses.push(status.unwrap());
+    }
+    if statuses.is_empty() {
+        return Ok(jobs);
+    }
+    return Ok(jobs.into_iter().filter(|job| statuses.contains(&job.status)).collect());
+}
+
 pub fn index_queued(
     req: &mut Request,
     _: &(),
@@ -36,7 +66,7 @@ pub fn index_queued(
         return Err(jobs.err().unwrap());
     }
     Ok(IndexResponse {
-        jobs: jobs.unwrap(),
+        jobs: filter_status(req, jobs.unwrap())?,
     })
 }
 
@@ -58,7 +88,7 @@ pub fn index_in_progress(
         return Err(jobs.err().unwrap());
     }
     Ok(IndexResponse {
-        jobs: jobs.unwrap(),
+        jobs: filter_status(req, jobs.unwrap())?,
     })
 }
 
@@ -79,25 +109,97 @@ pub fn index_finished(
     if jobs.is_err() {
         return Err(jobs.err().unwrap());
     }
-    let jobs_unwrapped = jobs.unwrap().iter().map(|job| {
-        Job {
-            uuid: job.uuid,
-            job_type_uuid: job.job_type_uuid,
-            job_type: None,
-            arguments: job.arguments.clone(),
-            executing_node: job.executing_node,
-            enqueued_at: job.enqueued_at,
-            started_at: job.started_at,
-            ended_at: job.ended_at,
-            results: Some(Value::Bool(job.results.is_some())),
-            errors: Some(Value::Bool(job.errors.is_some())),
-        }
-    }).collect();
     Ok(IndexResponse {
-        jobs: jobs_unwrapped,
+        jobs: filter_status(req, jobs.unwrap())?.iter().map(summarize_job).collect(),
     })
 }
 
+pub fn index_dead(
+    req: &mut Request,
+    _: &(),
+) -> Result<IndexResponse, IronResult<Response>> {
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let current_node_type_uuid = store.get_node().node_type_uuid.unwrap();
+    let node_type_uuid = get_uuid_from_arg(req, "node_type_uuid")?;
+    option_translate(redis_error_translate(store.set_node_type_soft(node_type_uuid))?)?;
+    let jobs = redis_error_translate(store.get_all_jobs_dead());
+    option_translate(redis_error_translate(store.set_node_type_soft(current_node_type_uuid))?)?;
+    if jobs.is_err() {
+        return Err(jobs.err().unwrap());
+    }
+    Ok(IndexResponse {
+        jobs: filter_status(req, jobs.unwrap())?.iter().map(summarize_job).collect(),
+    })
+}
+
+pub fn index_delayed(
+    req: &mut Request,
+    _: &(),
+) -> Result<IndexResponse, IronResult<Response>> {
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let current_node_type_uuid = store.get_node().node_type_uuid.unwrap();
+    let node_type_uuid = get_uuid_from_arg(req, "node_type_uuid")?;
+    option_translate(redis_error_translate(store.set_node_type_soft(node_type_uuid))?)?;
+    let jobs = redis_error_translate(store.get_all_jobs_delayed());
+    option_translate(redis_error_translate(store.set_node_type_soft(current_node_type_uuid))?)?;
+    if jobs.is_err() {
+        return Err(jobs.err().unwrap());
+    }
+    Ok(IndexResponse {
+        jobs: filter_status(req, jobs.unwrap())?.iter().map(summarize_job).collect(),
+    })
+}
+
+pub fn cancel_delayed(
+    req: &mut Request,
+    _: &(),
+) -> Result<Job, IronResult<Response>> {
+    let node_type_uuid = get_uuid_from_arg(req, "node_type_uuid")?;
+    let job_uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let current_node_type_uuid = store.get_node().node_type_uuid.unwrap();
+    option_translate(redis_error_translate(store.set_node_type_soft(node_type_uuid))?)?;
+    let job = redis_error_translate(store.cancel_delayed_job(job_uuid));
+    option_translate(redis_error_translate(store.set_node_type_soft(current_node_type_uuid))?)?;
+    if job.is_err() {
+        return Err(job.err().unwrap());
+    }
+    Ok(summarize_job(&option_translate(job.unwrap())?))
+}
+
+// replaces results/errors with a boolean presence summary
+fn summarize_job(job: &Job) -> Job {
+    Job {
+        uuid: job.uuid,
+        status: job.status,
+        job_type_uuid: job.job_type_uuid,
+        job_type: None,
+        arguments: job.arguments.clone(),
+        priority: job.priority,
+        executing_node: job.executing_node,
+        enqueued_at: job.enqueued_at,
+        run_at: job.run_at,
+        started_at: job.started_at,
+        ended_at: job.ended_at,
+        results: Some(Value::Bool(job.results.is_some())),
+        errors: Some(Value::Bool(job.errors.is_some())),
+        attempt: job.attempt,
+        progress: job.progress.clone(),
+    }
+}
+
 pub fn get(
     req: &mut Request,
     _: &(),
@@ -116,7 +218,163 @@ pub fn get(
     if job.is_err() {
         return Err(job.err().unwrap());
     }
-    Ok(option_translate(job.unwrap())?)
+    return option_translate(job.unwrap());
+}
+
+pub fn get_dead(
+    req: &mut Request,
+    _: &(),
+) -> Result<Job, IronResult<Response>> {
+    let node_type_uuid = get_uuid_from_arg(req, "node_type_uuid")?;
+    let job_uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let current_node_type_uuid = store.get_node().node_type_uuid.unwrap();
+    option_translate(redis_error_translate(store.set_node_type_soft(node_type_uuid))?)?;
+    let job = redis_error_translate(store.get_dead_job(job_uuid));
+    option_translate(redis_error_translate(store.set_node_type_soft(current_node_type_uuid))?)?;
+    if job.is_err() {
+        return Err(job.err().unwrap());
+    }
+    return option_translate(job.unwrap());
+}
+
+// enqueues a fresh copy of a dead job, only removing it from the dead jobs once it has been accepted
+fn requeue_dead_job(store: &mut StoreRef, job_uuid: Uuid) -> Result<PostResponse, IronResult<Response>> {
+    let dead_job = option_translate(redis_error_translate(store.get_dead_job(job_uuid))?)?;
+    let new_job_uuid = Uuid::new_v4();
+    let job = Job {
+        uuid: new_job_uuid,
+        status: JobStatus::Queued,
+        executing_node: None,
+        enqueued_at: None,
+        run_at: None,
+        started_at: None,
+        ended_at: None,
+        results: None,
+        errors: None,
+        attempt: 0,
+        progress: None,
+        ..dead_job
+    };
+    let enqueued = redis_error_translate(store.enqueue_job(job))?;
+    let response = enqueue_response(dead_job.job_type_uuid, new_job_uuid, enqueued)?;
+    redis_error_translate(store.purge_dead_jobs(Some(job_uuid)))?;
+    Ok(response)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmptyBody {}
+
+pub fn retry_dead(
+    req: &mut Request,
+    _: &EmptyBody,
+) -> Result<PostResponse, IronResult<Response>> {
+    let node_type_uuid = get_uuid_from_arg(req, "node_type_uuid")?;
+    let job_uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let current_node_type_uuid = store.get_node().node_type_uuid.unwrap();
+    option_translate(redis_error_translate(store.set_node_type_soft(node_type_uuid))?)?;
+    let response = requeue_dead_job(&mut store, job_uuid);
+    option_translate(redis_error_translate(store.set_node_type_soft(current_node_type_uuid))?)?;
+    response
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgeResponse {
+    status: String,
+    purged: u64,
+}
+
+pub fn purge_dead(
+    req: &mut Request,
+    _: &(),
+) -> Result<PurgeResponse, IronResult<Response>> {
+    let node_type_uuid = get_uuid_from_arg(req, "node_type_uuid")?;
+    // a single dead job is purged if a uuid is given, otherwise all of them
+    let job_uuid = match req.extensions.get::<Router>().unwrap().find("uuid") {
+        Some(_) => Some(get_uuid_from_arg(req, "uuid")?),
+        None => None,
+    };
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let current_node_type_uuid = store.get_node().node_type_uuid.unwrap();
+    option_translate(redis_error_translate(store.set_node_type_soft(node_type_uuid))?)?;
+    let purged = redis_error_translate(store.purge_dead_jobs(job_uuid));
+    option_translate(redis_error_translate(store.set_node_type_soft(current_node_type_uuid))?)?;
+    if purged.is_err() {
+        return Err(purged.err().unwrap());
+    }
+    Ok(PurgeResponse {
+        status: "ok".to_string(),
+        purged: purged.unwrap(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RerouteResponse {
+    status: String,
+    rerouted: u64,
+}
+
+pub fn reroute(
+    req: &mut Request,
+    _: &EmptyBody,
+) -> Result<RerouteResponse, IronResult<Response>> {
+    let node_type_uuid = get_uuid_from_arg(req, "node_type_uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let current_node_type_uuid = store.get_node().node_type_uuid.unwrap();
+    option_translate(redis_error_translate(store.set_node_type_soft(node_type_uuid))?)?;
+    let rerouted = redis_error_translate(store.reroute_jobs_waiting());
+    option_translate(redis_error_translate(store.set_node_type_soft(current_node_type_uuid))?)?;
+    if rerouted.is_err() {
+        return Err(rerouted.err().unwrap());
+    }
+    Ok(RerouteResponse {
+        status: "ok".to_string(),
+        rerouted: rerouted.unwrap(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelResponse {
+    status: String,
+    uuid: Uuid,
+}
+
+pub fn cancel(
+    req: &mut Request,
+    _: &EmptyBody,
+) -> Result<CancelResponse, IronResult<Response>> {
+    let job_uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    let status = match redis_error_translate(store.cancel_job(job_uuid))? {
+        CancelResult::Cancelled => "cancelled",
+        CancelResult::Cancelling => "cancelling",
+        CancelResult::NotFound => return Err(status_error(status::NotFound)),
+    };
+    Ok(CancelResponse {
+        status: status.to_string(),
+        uuid: job_uuid,
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -129,6 +387,12 @@ pub struct PostResponse {
 pub struct PostBody {
     pub job_type_uuid: Uuid,
     pub arguments: HashMap<String, Value>,
+    #[serde(default)]
+    pub run_at: Option<u64>, // epoch milliseconds
+    #[serde(default)]
+    pub delay_ms: Option<u64>,
+    #[serde(default)]
+    pub priority: Option<Priority>, // overrides the job type's priority
 }
 
 pub fn post(
@@ -140,38 +404,208 @@ pub fn post(
         .unwrap()
         .lock()
         .unwrap();
+    if body.run_at.is_some() && body.delay_ms.is_some() {
+        warn!("Only one of run_at and delay_ms may be given");
+        return Err(status_error(status::BadRequest));
+    }
+    let run_at = match body.delay_ms {
+        Some(delay_ms) => Some(epoch() + delay_ms),
+        None => body.run_at,
+    };
     let job_type = option_translate(redis_error_translate(store.get_job_type(body.job_type_uuid))?)?;
+    let node_types = redis_error_translate(store.get_node_types())?;
+    if job_node_type_uuid(&node_types, &job_type).is_err() {
+        warn!("Node type '{}' of job type '{}' does not exist", job_type.node_type, job_type.uuid.hyphenated());
+        return Err(status_error(status::BadRequest));
+    }
+    let priority = body.priority.unwrap_or(job_type.priority);
     let job_uuid = Uuid::new_v4();
     let job = Job {
         uuid: job_uuid,
+        status: JobStatus::Queued,
         job_type_uuid: body.job_type_uuid,
         job_type: Some(job_type),
         arguments: body.arguments.clone(),
+        priority,
         executing_node: None,
         enqueued_at: None,
+        run_at,
         started_at: None,
         ended_at: None,
         results: None,
         errors: None,
+        attempt: 0,
+        progress: None,
     };
-    redis_error_translate(store.enqueue_job(job))?;
-    Ok(PostResponse {
-        status: "ok".to_string(),
-        uuid: job_uuid,
-    })
+    let enqueued = redis_error_translate(store.enqueue_job(job))?;
+    return enqueue_response(body.job_type_uuid, job_uuid, enqueued);
+}
+
+fn enqueue_response(job_type_uuid: Uuid, job_uuid: Uuid, enqueued: EnqueueResult) -> Result<PostResponse, IronResult<Response>> {
+    match enqueued {
+        EnqueueResult::Enqueued => Ok(PostResponse {
+            status: "ok".to_string(),
+            uuid: job_uuid,
+        }),
+        EnqueueResult::Delayed => Ok(PostResponse {
+            status: "delayed".to_string(),
+            uuid: job_uuid,
+        }),
+        EnqueueResult::Deferred => Ok(PostResponse {
+            status: "deferred".to_string(),
+            uuid: job_uuid,
+        }),
+        EnqueueResult::Coalesced(existing_uuid) => Ok(PostResponse {
+            status: "coalesced".to_string(),
+            uuid: existing_uuid,
+        }),
+        EnqueueResult::Rejected(existing_uuid) => {
+            warn!("Rejected job for unique job type '{}', job '{}' is already queued or running", job_type_uuid.hyphenated(), existing_uuid.hyphenated());
+            Err(status_error(status::Conflict))
+        },
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LogsResponse {
+    output: String,
+    offset: u64, // offset of the start of `output`
+    end: u64,
+    closed: bool,
+}
+
+// how often followed logs are checked for new output
+const LOGS_FOLLOW_POLL_MS: u64 = 500;
+// how often followed logs without new output check whether the job has finished without closing them, i.e. when cancelled after its node died
+const LOGS_FOLLOW_STATUS_POLL_MS: u64 = 5000;
+// how long followed logs go without output before a keep-alive comment is sent, which also notices disconnected clients
+const LOGS_FOLLOW_KEEP_ALIVE_MS: u64 = 15000;
+// how long followed logs go without output before the stream is ended, e.g. as the job's node died and it is queued again
+const LOGS_FOLLOW_IDLE_MS: u64 = 600000;
+
+// whether a job has finished or is dead, on any node type
+fn is_job_finished(store: &mut StoreRef, job_uuid: Uuid) -> Result<bool, String> {
+    let current_node_type_uuid = store.get_node().node_type_uuid;
+    let finished = (|| {
+        for node_type in store.get_node_types()? {
+            store.set_node_type_soft(node_type.uuid)?;
+            if store.get_finished_job(job_uuid)?.is_some() || store.get_dead_job(job_uuid)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    })();
+    if let Some(current_node_type_uuid) = current_node_type_uuid {
+        store.set_node_type_soft(current_node_type_uuid)?;
+    }
+    return finished;
+}
+
+// streams a job's logs as server-sent events until they are closed, the job finishes, or they go idle
+struct LogsStream {
+    store: Arc<Mutex<StoreRef>>,
+    job_uuid: Uuid,
+    offset: u64,
 }
 
+impl WriteBody for LogsStream {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let mut output_at = Instant::now();
+        let mut written_at = Instant::now();
+        let mut checked_at: Option<Instant> = None;
+        loop {
+            let logs = match self.store.lock().unwrap().get_job_logs(self.job_uuid) {
+                Ok(Some(logs)) => logs,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    error!("Redis server failed while following job logs: {}", e);
+                    return Ok(());
+                },
+            };
+            let output = logs.since(self.offset);
+            let lines_end = output.rfind('\n').map(|end| end + 1).unwrap_or(0);
+            let mut ended = logs.closed;
+            if !ended && lines_end == 0 && checked_at.map(|checked_at| checked_at.elapsed() >= Duration::from_millis(LOGS_FOLLOW_STATUS_POLL_MS)).unwrap_or(true) {
+                checked_at = Some(Instant::now());
+                match is_job_finished(&mut self.store.lock().unwrap(), self.job_uuid) {
+                    Ok(finished) => ended = finished,
+                    Err(e) => {
+                        error!("Redis server failed while following job logs: {}", e);
+                        return Ok(());
+                    },
+                }
+            }
+            // a partial line is only sent once the rest of it is written, or the logs end
+            let sendable = if ended { output.len() } else { lines_end };
+            if sendable > 0 {
+                output_at = Instant::now();
+                written_at = Instant::now();
+                self.offset = logs.end - output.len() as u64 + sendable as u64;
+                let lines = output[..sendable].replace("\r\n", "\n");
+                let mut event = format!("id: {}\n", self.offset);
+                for line in lines.strip_suffix('\n').unwrap_or(&lines).split(['\n', '\r']) {
+                    event.push_str(&format!("data: {}\n", line));
+                }
+                event.push('\n');
+                res.write_all(event.as_bytes())?;
+                res.flush()?;
+            }
+            if ended {
+                res.write_all(b"event: end\ndata: \n\n")?;
+                return res.flush();
+            }
+            if output_at.elapsed() >= Duration::from_millis(LOGS_FOLLOW_IDLE_MS) {
+                res.write_all(b"event: idle\ndata: \n\n")?;
+                return res.flush();
+            }
+            // write errors end the stream once the client has gone
+            if written_at.elapsed() >= Duration::from_millis(LOGS_FOLLOW_KEEP_ALIVE_MS) {
+                written_at = Instant::now();
+                res.write_all(b": keep-alive\n\n")?;
+                res.flush()?;
+            }
+            thread::sleep(Duration::from_millis(LOGS_FOLLOW_POLL_MS));
+        }
+    }
+}
+
+// not wrapped, as following the logs streams them rather than responding with JSON
+pub fn logs(req: &mut Request) -> IronResult<Response> {
+    let job_uuid = match get_uuid_from_arg(req, "uuid") {
+        Ok(uuid) => uuid,
+        Err(response) => return response,
+    };
+    let offset = get_query_values(req, "offset").first().and_then(|offset| offset.parse::<u64>().ok()).unwrap_or(0);
+    let follow = get_query_values(req, "follow").first().map(|follow| follow == "true").unwrap_or(false);
+    let store = req.extensions.get::<IronRedis>().unwrap().clone();
+    let logs = store.lock().unwrap().get_job_logs(job_uuid);
+    let logs = match redis_error_translate(logs).and_then(option_translate) {
+        Ok(logs) => logs,
+        Err(response) => return response,
+    };
+    if follow {
+        let stream: Box<dyn WriteBody> = Box::new(LogsStream { store: store, job_uuid: job_uuid, offset: offset });
+        return Ok(Response::with((Mime(TopLevel::Text, SubLevel::Ext("event-stream".to_string()), vec![]), status::Ok, stream)));
+    }
+    let output = logs.since(offset);
+    let response = LogsResponse { output: output.to_string(), offset: logs.end - output.len() as u64, end: logs.end, closed: logs.closed };
+    return Ok(Response::with((Mime(TopLevel::Application, SubLevel::Json, vec![]), status::Ok, serde_json::to_string(&response).unwrap())));
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use iron_test::request::{ post, get };
+    use iron_test::request::{ post, get, delete };
     use iron::{ Headers, headers::ContentType };
     use crate::http::controllers::tests::*;
     use crate::config;
     use iron::status;
     use crate::http::tests::initialize_tests;
     use crate::store::{ self, StoreRef, tests::* };
+    use crate::exec::job_type::UniquePolicy;
+    use crate::exec::job::JobProgress;
+    use crate::exec::node_type::NodeType;
 
     #[test]
     fn test_jobs_index_queued() -> Result<(), String> {
@@ -203,6 +637,9 @@ mod tests {
         let test_job_type = make_job_type(&mut store)?;
         let mut test_job = make_job(&mut store, &test_job_type)?;
         store.dequeue_job()?;
+        let progress = JobProgress { percent: Some(42.0), message: Some("processed 420/1000 rows".to_string()), updated_at: epoch() };
+        store.set_job_progress(test_job.uuid, &progress)?;
+        test_job.progress = Some(progress);
         let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/in_progress", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
         assert_eq!(response.status, Some(status::Ok));
         let body: IndexResponse = parse_body(response.body)?;
@@ -211,6 +648,7 @@ mod tests {
         test_job.enqueued_at = body.jobs[0].enqueued_at;
         test_job.started_at = body.jobs[0].started_at;
         test_job.executing_node = body.jobs[0].executing_node;
+        test_job.status = JobStatus::Running;
         assert_eq!(body, IndexResponse {
             jobs: vec![test_job],
         });
@@ -226,8 +664,8 @@ mod tests {
         store.set_node_type(test_node_type.uuid)?;
         let test_job_type = make_job_type(&mut store)?;
         let mut test_job = make_job(&mut store, &test_job_type)?;
-        store.dequeue_job()?;
-        store.finish_job(test_job.clone(), Some(Value::String("output".to_string())), Some(Value::String("errors".to_string())))?;
+        let running_job = store.dequeue_job()?;
+        store.finish_job(running_job, Some(Value::String("output".to_string())), Some(Value::String("errors".to_string())))?;
 
         let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/finished", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
         assert_eq!(response.status, Some(status::Ok));
@@ -238,6 +676,7 @@ mod tests {
         test_job.started_at = body.jobs[0].started_at;
         test_job.ended_at = body.jobs[0].ended_at;
         test_job.executing_node = body.jobs[0].executing_node;
+        test_job.status = JobStatus::Failed;
         test_job.results = Some(Value::Bool(true));
         test_job.errors = Some(Value::Bool(true));
         assert_eq!(body, IndexResponse {
@@ -247,6 +686,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_jobs_index_finished_by_status() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        make_job(&mut store, &test_job_type)?;
+        make_job(&mut store, &test_job_type)?;
+        let succeeded_job = store.dequeue_job()?;
+        store.finish_job(succeeded_job.clone(), None, None)?;
+        let failed_job = store.dequeue_job()?;
+        store.finish_job(failed_job.clone(), None, Some(Value::String("errors".to_string())))?;
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/finished?status=failed", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: IndexResponse = parse_body(response.body)?;
+        assert_eq!(body.jobs.iter().map(|job| (job.uuid, job.status)).collect::<Vec<(Uuid, JobStatus)>>(), vec![(failed_job.uuid, JobStatus::Failed)]);
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/finished?status=succeeded,failed", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        let body: IndexResponse = parse_body(response.body)?;
+        assert_eq!(body.jobs.len(), 2);
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/finished?status=finished", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
+        Ok(())
+    }
+
     #[test]
     fn test_jobs_get() -> Result<(), String> {
         let mut store: StoreRef = store::init_store_untyped();
@@ -255,8 +722,8 @@ mod tests {
         store.set_node_type(test_node_type.uuid)?;
         let test_job_type = make_job_type(&mut store)?;
         let mut test_job = make_job(&mut store, &test_job_type)?;
-        store.dequeue_job()?;
-        store.finish_job(test_job.clone(), Some(Value::String("output".to_string())), Some(Value::String("errors".to_string())))?;
+        let running_job = store.dequeue_job()?;
+        store.finish_job(running_job, Some(Value::String("output".to_string())), Some(Value::String("errors".to_string())))?;
 
         let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/{}", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated(), test_job.uuid.hyphenated()), Headers::new(), &initialize_tests(store)))?;
         assert_eq!(response.status, Some(status::Ok));
@@ -266,6 +733,7 @@ mod tests {
         test_job.started_at = body.started_at;
         test_job.ended_at = body.ended_at;
         test_job.executing_node = body.executing_node;
+        test_job.status = JobStatus::Failed;
         test_job.results = Some(Value::String("output".to_string()));
         test_job.errors = Some(Value::String("errors".to_string()));
         assert_eq!(body, test_job);
@@ -283,6 +751,9 @@ mod tests {
         let test_job = PostBody {
             job_type_uuid: test_job_type.uuid,
             arguments: HashMap::new(),
+            priority: None,
+            run_at: None,
+            delay_ms: None,
         };
         let test_job_serialized = serde_json::to_string(&test_job).unwrap();
 
@@ -295,6 +766,268 @@ mod tests {
         assert_eq!(new_job.uuid, body.uuid);
         assert_eq!(new_job.job_type_uuid, test_job_type.uuid);
         assert_eq!(new_job.arguments, test_job.arguments);
+        assert_eq!(new_job.priority, test_job_type.priority);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_post_priority() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let normal_job = make_job(&mut store, &test_job_type)?;
+
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let body = format!("{{\"job_type_uuid\": \"{}\", \"arguments\": {{}}, \"priority\": \"high\"}}", test_job_type.uuid.hyphenated());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs", &*config::HTTP_BIND_ADDRESS), headers.clone(), &*body, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let high_job: PostResponse = parse_body(response.body)?;
+        let body = format!("{{\"job_type_uuid\": \"{}\", \"arguments\": {{}}, \"priority\": \"urgent\"}}", test_job_type.uuid.hyphenated());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs", &*config::HTTP_BIND_ADDRESS), headers, &*body, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
+
+        // queued jobs are listed highest priority first
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/queued", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        let body: IndexResponse = parse_body(response.body)?;
+        assert_eq!(body.jobs.iter().map(|job| (job.uuid, job.priority)).collect::<Vec<(Uuid, Priority)>>(), vec![(high_job.uuid, Priority::High), (normal_job.uuid, Priority::Normal)]);
+        assert_eq!(store.dequeue_job()?.uuid, high_job.uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_post_unique() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_unique_job_type(&mut store, UniquePolicy::Reject)?;
+        let existing_job = make_job(&mut store, &test_job_type)?;
+
+        let test_job = PostBody {
+            job_type_uuid: test_job_type.uuid,
+            arguments: HashMap::new(),
+            priority: None,
+            run_at: None,
+            delay_ms: None,
+        };
+        let test_job_serialized = serde_json::to_string(&test_job).unwrap();
+
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs", &*config::HTTP_BIND_ADDRESS), headers.clone(), &*test_job_serialized, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Conflict));
+
+        let mut coalescing_job_type = test_job_type.clone();
+        coalescing_job_type.unique_policy = UniquePolicy::Coalesce;
+        store.new_job_type(&coalescing_job_type)?;
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs", &*config::HTTP_BIND_ADDRESS), headers, &*test_job_serialized, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: PostResponse = parse_body(response.body)?;
+        assert_eq!(body.status, "coalesced");
+        assert_eq!(body.uuid, existing_job.uuid);
+        assert_eq!(store.get_all_jobs_waiting()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_dead() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let running_job = store.dequeue_job()?;
+        store.kill_job(Job { attempt: 2, ..running_job }, None, Some(Value::String("errors".to_string())))?;
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/dead", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: IndexResponse = parse_body(response.body)?;
+        assert_eq!(body.jobs.len(), 1);
+        assert_eq!(body.jobs[0].uuid, test_job.uuid);
+        assert_eq!(body.jobs[0].errors, Some(Value::Bool(true)));
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/dead/{}", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated(), test_job.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: Job = parse_body(response.body)?;
+        assert_eq!(body.errors, Some(Value::String("errors".to_string())));
+        assert_eq!(body.attempt, 2);
+
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs/{}/dead/{}/retry", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated(), test_job.uuid.hyphenated()), headers, "{}", &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: PostResponse = parse_body(response.body)?;
+        assert_eq!(body.status, "ok");
+        assert_eq!(store.get_all_jobs_dead()?, vec![]);
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, body.uuid);
+        assert_eq!(waiting_jobs[0].arguments, test_job.arguments);
+        assert_eq!(waiting_jobs[0].attempt, 0);
+        assert_eq!(waiting_jobs[0].errors, None);
+
+        let running_job = store.dequeue_job()?;
+        store.kill_job(running_job, None, None)?;
+        let response = iron_error_translate(delete(&*format!("http://{}/api/jobs/{}/dead", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: PurgeResponse = parse_body(response.body)?;
+        assert_eq!(body.purged, 1);
+        assert_eq!(store.get_all_jobs_dead()?, vec![]);
+        assert_eq!(store.get_node().node_type_uuid.unwrap(), test_node_type.uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_post_missing_node_type() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.node_type = "missing_node_type".to_string();
+        store.new_job_type(&test_job_type)?;
+
+        let test_job = PostBody {
+            job_type_uuid: test_job_type.uuid,
+            arguments: HashMap::new(),
+            priority: None,
+            run_at: None,
+            delay_ms: None,
+        };
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs", &*config::HTTP_BIND_ADDRESS), headers, &*serde_json::to_string(&test_job).unwrap(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_reroute() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let other_node_type = NodeType { name: "other_node_type".to_string(), uuid: Uuid::new_v4(), thread_count: 1, jobs_per_thread: None };
+        store.new_node_type(&other_node_type)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        make_job(&mut store, &test_job_type)?;
+        test_job_type.node_type = other_node_type.name.clone();
+        store.new_job_type(&test_job_type)?;
+
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs/{}/reroute", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), headers, "{}", &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: RerouteResponse = parse_body(response.body)?;
+        assert_eq!(body.rerouted, 1);
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+        assert_eq!(store.get_node().node_type_uuid.unwrap(), test_node_type.uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_cancel() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs/{}/cancel", &*config::HTTP_BIND_ADDRESS, test_job.uuid.hyphenated()), headers.clone(), "{}", &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: CancelResponse = parse_body(response.body)?;
+        assert_eq!(body.status, "cancelled");
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+        assert_eq!(store.get_finished_job(test_job.uuid)?.unwrap().errors.unwrap()["kind"], Value::String("cancelled".to_string()));
+
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs/{}/cancel", &*config::HTTP_BIND_ADDRESS, test_job.uuid.hyphenated()), headers, "{}", &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::NotFound));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_logs() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/logs", &*config::HTTP_BIND_ADDRESS, test_job.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::NotFound));
+
+        store.append_job_logs(test_job.uuid, "first\nsecond")?;
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/logs?offset=6", &*config::HTTP_BIND_ADDRESS, test_job.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: LogsResponse = parse_body(response.body)?;
+        assert_eq!(body, LogsResponse { output: "second".to_string(), offset: 6, end: 12, closed: false });
+
+        store.close_job_logs(test_job.uuid)?;
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/logs?follow=true", &*config::HTTP_BIND_ADDRESS, test_job.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(stringify_body(response.body), "id: 12\ndata: first\ndata: second\n\nevent: end\ndata: \n\n");
+
+        // a job finished without its logs being closed, i.e. cancelled after its node died, ends the stream too
+        let other_job = make_job(&mut store, &test_job_type)?;
+        store.dequeue_job()?;
+        let running_job = store.dequeue_job()?;
+        assert_eq!(running_job.uuid, other_job.uuid);
+        store.append_job_logs(other_job.uuid, "partial")?;
+        store.finish_job(running_job, None, None)?;
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/logs?follow=true", &*config::HTTP_BIND_ADDRESS, other_job.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(stringify_body(response.body), "id: 7\ndata: partial\n\nevent: end\ndata: \n\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_delayed() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+
+        let test_job = PostBody {
+            job_type_uuid: test_job_type.uuid,
+            arguments: HashMap::new(),
+            priority: None,
+            run_at: None,
+            delay_ms: Some(60000),
+        };
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs", &*config::HTTP_BIND_ADDRESS), headers.clone(), &*serde_json::to_string(&test_job).unwrap(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: PostResponse = parse_body(response.body)?;
+        assert_eq!(body.status, "delayed");
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+
+        let response = iron_error_translate(get(&*format!("http://{}/api/jobs/{}/delayed", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        let delayed: IndexResponse = parse_body(response.body)?;
+        assert_eq!(delayed.jobs.len(), 1);
+        assert_eq!(delayed.jobs[0].uuid, body.uuid);
+
+        let response = iron_error_translate(delete(&*format!("http://{}/api/jobs/{}/delayed/{}", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated(), body.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(store.get_all_jobs_delayed()?, vec![]);
+        let response = iron_error_translate(delete(&*format!("http://{}/api/jobs/{}/delayed/{}", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated(), body.uuid.hyphenated()), Headers::new(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::NotFound));
+
+        let test_job = PostBody {
+            run_at: Some(epoch() + 60000),
+            ..test_job
+        };
+        let response = iron_error_translate(post(&*format!("http://{}/api/jobs", &*config::HTTP_BIND_ADDRESS), headers, &*serde_json::to_string(&test_job).unwrap(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
         Ok(())
     }
 
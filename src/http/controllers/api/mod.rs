@@ -1,3 +1,4 @@
+pub mod executors;
 pub mod job_types;
 pub mod jobs;
 pub mod node_types;
@@ -24,6 +25,21 @@ pub fn get_uuid_from_arg(req: &Request, key: &str) -> Result<Uuid, IronResult<Re
     return Ok(uuid.unwrap());
 }
 
+// all values of a query string parameter, which may be repeated or comma separated
+pub fn get_query_values(req: &Request, key: &str) -> Vec<String> {
+    let query = req.url.query().unwrap_or("");
+    let mut values: Vec<String> = vec![];
+    for pair in query.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        if parts.next() != Some(key) {
+            continue;
+        }
+        let value = parts.next().unwrap_or("").replace("%2C", ",").replace("%2c", ",");
+        values.extend(value.split(',').filter(|value| !value.is_empty()).map(|value| value.to_string()));
+    }
+    return values;
+}
+
 pub fn redis_error_translate<T>(result: Result<T, String>) -> Result<T, IronResult<Response>> {
     match result {
         Err(e) => {
@@ -55,6 +55,10 @@ pub fn post(
         warn!("UUID in POST body and URL must match.");
         return Err(status_error(status::BadRequest));
     }
+    if body.jobs_per_thread == Some(0) {
+        warn!("jobs_per_thread must be at least 1");
+        return Err(status_error(status::BadRequest));
+    }
     let mut store = req.extensions
         .get::<IronRedis>()
         .unwrap()
@@ -118,15 +122,20 @@ mod tests {
             name: "test_node_type".to_string(),
             uuid: Uuid::new_v4(),
             thread_count: 1,
+            jobs_per_thread: None,
         };
         let node_type_serialized = serde_json::to_string(&test_node_type).unwrap();
 
         let mut headers = Headers::new();
         headers.set::<ContentType>(ContentType::json());
-        let response = iron_error_translate(post(&*format!("http://{}/api/node_types/{}", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), headers, &*node_type_serialized, &initialize_tests(store.replicate()?)))?;
+        let response = iron_error_translate(post(&*format!("http://{}/api/node_types/{}", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), headers.clone(), &*node_type_serialized, &initialize_tests(store.replicate()?)))?;
         assert_eq!(response.status, Some(status::Ok));
         store.set_node_type(test_node_type.uuid)?;
         assert_eq!(store.get_node().node_type.as_ref().unwrap().clone(), test_node_type);
+
+        let node_type_serialized = serde_json::to_string(&NodeType { jobs_per_thread: Some(0), ..test_node_type.clone() }).unwrap();
+        let response = iron_error_translate(post(&*format!("http://{}/api/node_types/{}", &*config::HTTP_BIND_ADDRESS, test_node_type.uuid.hyphenated()), headers, &*node_type_serialized, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
         Ok(())
     }
 
@@ -3,17 +3,26 @@
     router.get("/api/schedules/:uuid", serialize_wrap(api::schedule::get), "schedule#get");
     router.delete("/api/schedules/:uuid", serialize_wrap(api::schedule::get), "schedule#delete");
     router.post("/api/schedules", json_wrap(api::schedule::post), "schedule#post");
+    router.put("/api/schedules/:uuid", json_wrap(api::schedule::put), "schedule#put");
+    router.post("/api/schedules/:uuid/pause", json_wrap(api::schedule::pause), "schedule#pause");
+    router.post("/api/schedules/:uuid/resume", json_wrap(api::schedule::resume), "schedule#resume");
 
 */
 
 use crate::http::middleware::redis::IronRedis;
 use iron::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::scheduler::ScheduleItem;
+use crate::scheduler::{ ScheduleItem, MisfirePolicy };
 use uuid::Uuid;
 use std::collections::HashMap;
 use serde_json::Value;
 use super::{ get_uuid_from_arg, redis_error_translate, option_translate };
+use super::jobs::EmptyBody;
+use crate::StoreRef;
+use crate::util::time::epoch;
+use crate::http::helpers::control::status_error;
+use iron::status;
+use log::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct IndexResponse {
@@ -59,7 +68,16 @@ pub struct PostResponse {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostBody {
+    #[serde(default)]
     pub interval: u64,
+    #[serde(default)]
+    pub cron: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub misfire_policy: MisfirePolicy,
+    #[serde(default)]
+    pub misfire_limit: Option<u32>,
     pub job_type_uuid: Uuid,
     pub job_arguments: HashMap<String, Value>,
 }
@@ -79,11 +97,20 @@ pub fn post(
     let schedule_item = ScheduleItem {
         uuid: Uuid::new_v4(),
         interval: body.interval,
+        cron: body.cron.clone(),
+        timezone: body.timezone.clone(),
+        misfire_policy: body.misfire_policy,
+        misfire_limit: body.misfire_limit,
+        enabled: true,
         job_type_uuid: body.job_type_uuid,
         job_arguments: body.job_arguments.clone(),
         last_scheduled_at: None,
         last_scheduled_by: None,
     };
+    if let Err(e) = schedule_item.validate() {
+        warn!("Invalid schedule item: {}", e);
+        return Err(status_error(status::BadRequest));
+    }
     redis_error_translate(store.new_job_schedule_item(&schedule_item))?;
     Ok(PostResponse {
         status: "ok".to_string(),
@@ -91,6 +118,96 @@ pub fn post(
     })
 }
 
+// how many times an update is retried when the schedule item is concurrently modified (i.e. claimed by a scheduler)
+const UPDATE_ATTEMPTS: u32 = 5;
+
+// atomically replaces a schedule item with the result of `update`, keeping any fields it does not change
+fn update_schedule_item<F>(store: &mut StoreRef, uuid: Uuid, update: F) -> Result<PostResponse, IronResult<Response>>
+    where F: Fn(&ScheduleItem) -> ScheduleItem {
+    for _ in 0..UPDATE_ATTEMPTS {
+        let schedule_item = option_translate(
+            redis_error_translate(store.get_job_schedule_item(uuid))?
+        )?;
+        let new_schedule_item = update(&schedule_item);
+        if let Err(e) = new_schedule_item.validate() {
+            warn!("Invalid schedule item: {}", e);
+            return Err(status_error(status::BadRequest));
+        }
+        if redis_error_translate(store.update_job_schedule_item(&schedule_item, &new_schedule_item))? {
+            return Ok(PostResponse {
+                status: "ok".to_string(),
+                uuid: uuid,
+            });
+        }
+    }
+    warn!("Schedule item '{}' was modified concurrently {} times, giving up", uuid.hyphenated(), UPDATE_ATTEMPTS);
+    Err(status_error(status::Conflict))
+}
+
+pub fn put(
+    req: &mut Request,
+    body: &PostBody,
+) -> Result<PostResponse, IronResult<Response>> {
+    let uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    option_translate(
+        redis_error_translate(store.get_job_type(body.job_type_uuid))?
+    )?;
+    update_schedule_item(&mut store, uuid, |schedule_item| ScheduleItem {
+        interval: body.interval,
+        cron: body.cron.clone(),
+        timezone: body.timezone.clone(),
+        misfire_policy: body.misfire_policy,
+        misfire_limit: body.misfire_limit,
+        job_type_uuid: body.job_type_uuid,
+        job_arguments: body.job_arguments.clone(),
+        ..schedule_item.clone()
+    })
+}
+
+pub fn pause(
+    req: &mut Request,
+    _: &EmptyBody,
+) -> Result<PostResponse, IronResult<Response>> {
+    let uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    update_schedule_item(&mut store, uuid, |schedule_item| ScheduleItem {
+        enabled: false,
+        ..schedule_item.clone()
+    })
+}
+
+pub fn resume(
+    req: &mut Request,
+    _: &EmptyBody,
+) -> Result<PostResponse, IronResult<Response>> {
+    let uuid = get_uuid_from_arg(req, "uuid")?;
+    let mut store = req.extensions
+        .get::<IronRedis>()
+        .unwrap()
+        .lock()
+        .unwrap();
+    update_schedule_item(&mut store, uuid, |schedule_item| {
+        if schedule_item.enabled {
+            return schedule_item.clone();
+        }
+        // runs missed while paused are not caught up by the misfire policy
+        ScheduleItem {
+            enabled: true,
+            last_scheduled_at: schedule_item.last_scheduled_at.map(|_| epoch()),
+            ..schedule_item.clone()
+        }
+    })
+}
+
 pub fn delete(
     req: &mut Request,
     _: &(),
@@ -111,13 +228,13 @@ pub fn delete(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use iron_test::request::{ post, get, delete };
+    use iron_test::request::{ post, put, get, delete };
     use iron::{ Headers, headers::ContentType };
     use crate::http::controllers::tests::*;
     use crate::config;
     use iron::status;
     use crate::http::tests::initialize_tests;
-    use crate::store::{ self, StoreRef, tests::* };
+    use crate::store::{ self, tests::* };
 
     #[test]
     fn test_schedule_item_index() -> Result<(), String> {
@@ -163,6 +280,10 @@ mod tests {
 
         let test_schedule_item = PostBody {
             interval: 1000,
+            cron: None,
+            timezone: None,
+            misfire_policy: MisfirePolicy::Skip,
+            misfire_limit: None,
             job_type_uuid: test_job_type.uuid,
             job_arguments: HashMap::new(),
         };
@@ -177,6 +298,11 @@ mod tests {
         assert_eq!(new_job, ScheduleItem {
             uuid: body.uuid,
             interval: test_schedule_item.interval,
+            cron: None,
+            timezone: None,
+            misfire_policy: MisfirePolicy::Skip,
+            misfire_limit: None,
+            enabled: true,
             job_type_uuid: test_schedule_item.job_type_uuid,
             job_arguments: test_schedule_item.job_arguments,
             last_scheduled_at: None,
@@ -185,6 +311,94 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_schedule_item_post_cron() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let test_schedule_item = format!(r#"{{"cron": "0 0 9 * * Mon-Fri", "timezone": "Europe/Berlin", "job_type_uuid": "{}", "job_arguments": {{}}}}"#, test_job_type.uuid.hyphenated());
+        let response = iron_error_translate(post(&*format!("http://{}/api/schedules", &*config::HTTP_BIND_ADDRESS), headers.clone(), &*test_schedule_item, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let body: PostResponse = parse_body(response.body)?;
+        let new_job = store.get_job_schedule_item(body.uuid)?.unwrap();
+        assert_eq!(new_job.interval, 0);
+        assert_eq!(new_job.cron, Some("0 0 9 * * Mon-Fri".to_string()));
+        assert_eq!(new_job.timezone, Some("Europe/Berlin".to_string()));
+
+        let test_schedule_item = format!(r#"{{"cron": "0 0 9 * * Mon-Fri", "timezone": "Europe/Nowhere", "job_type_uuid": "{}", "job_arguments": {{}}}}"#, test_job_type.uuid.hyphenated());
+        let response = iron_error_translate(post(&*format!("http://{}/api/schedules", &*config::HTTP_BIND_ADDRESS), headers, &*test_schedule_item, &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule_item_put() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_schedule = make_schedule_item(&mut store, test_job_type.uuid, Some(Uuid::new_v4()), Some(1000))?;
+
+        let mut test_schedule_item = PostBody {
+            interval: 60000,
+            cron: None,
+            timezone: None,
+            misfire_policy: MisfirePolicy::FireAll,
+            misfire_limit: Some(5),
+            job_type_uuid: test_job_type.uuid,
+            job_arguments: HashMap::new(),
+        };
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(put(&*format!("http://{}/api/schedules/{}", &*config::HTTP_BIND_ADDRESS, test_schedule.uuid.hyphenated()), headers.clone(), &*serde_json::to_string(&test_schedule_item).unwrap(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        assert_eq!(store.get_job_schedule_item(test_schedule.uuid)?.unwrap(), ScheduleItem {
+            interval: 60000,
+            misfire_policy: MisfirePolicy::FireAll,
+            misfire_limit: Some(5),
+            job_arguments: HashMap::new(),
+            ..test_schedule.clone()
+        });
+
+        test_schedule_item.cron = Some("0 0 9 * * *".to_string());
+        let response = iron_error_translate(put(&*format!("http://{}/api/schedules/{}", &*config::HTTP_BIND_ADDRESS, test_schedule.uuid.hyphenated()), headers.clone(), &*serde_json::to_string(&test_schedule_item).unwrap(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::BadRequest));
+        let response = iron_error_translate(put(&*format!("http://{}/api/schedules/{}", &*config::HTTP_BIND_ADDRESS, Uuid::new_v4().hyphenated()), headers, &*serde_json::to_string(&test_schedule_item).unwrap(), &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::NotFound));
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule_item_pause_resume() -> Result<(), String> {
+        let mut store: StoreRef = store::init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_schedule = make_schedule_item(&mut store, test_job_type.uuid, Some(Uuid::new_v4()), Some(1000))?;
+
+        let mut headers = Headers::new();
+        headers.set::<ContentType>(ContentType::json());
+        let response = iron_error_translate(post(&*format!("http://{}/api/schedules/{}/pause", &*config::HTTP_BIND_ADDRESS, test_schedule.uuid.hyphenated()), headers.clone(), "{}", &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let paused_schedule = store.get_job_schedule_item(test_schedule.uuid)?.unwrap();
+        assert!(!paused_schedule.enabled);
+        assert_eq!(paused_schedule.last_scheduled_at, Some(1000));
+
+        let response = iron_error_translate(post(&*format!("http://{}/api/schedules/{}/resume", &*config::HTTP_BIND_ADDRESS, test_schedule.uuid.hyphenated()), headers, "{}", &initialize_tests(store.replicate()?)))?;
+        assert_eq!(response.status, Some(status::Ok));
+        let resumed_schedule = store.get_job_schedule_item(test_schedule.uuid)?.unwrap();
+        assert!(resumed_schedule.enabled);
+        assert!(resumed_schedule.last_scheduled_at.unwrap() > 1000);
+        Ok(())
+    }
+
     #[test]
     fn test_schedule_item_delete() -> Result<(), String> {
         let mut store: StoreRef = store::init_store_untyped();
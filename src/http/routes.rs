@@ -10,20 +10,36 @@ pub fn add_routes(router: &mut Router) {
     router.get("/api/nodes", serialize_wrap(api::nodes::index), "nodes#index");
     router.get("/api/nodes/:uuid", serialize_wrap(api::nodes::get), "nodes#get");
 
+    router.get("/api/executors", serialize_wrap(api::executors::index), "executors#index");
+
     router.get("/api/job_types", serialize_wrap(api::job_types::index), "job_types#index");
     router.get("/api/job_types/:uuid", serialize_wrap(api::job_types::get), "job_types#get");
+    router.get("/api/job_types/:uuid/rate_limit", serialize_wrap(api::job_types::rate_limit), "job_types#rate_limit");
     router.post("/api/job_types", json_wrap(api::job_types::post), "job_types#post");
 
     router.get("/api/jobs/:node_type_uuid/queued", serialize_wrap(api::jobs::index_queued), "jobs#index_queued");
     router.get("/api/jobs/:node_type_uuid/in_progress", serialize_wrap(api::jobs::index_in_progress), "jobs#index_in_progress");
     router.get("/api/jobs/:node_type_uuid/finished", serialize_wrap(api::jobs::index_finished), "jobs#index_finished");
+    router.get("/api/jobs/:node_type_uuid/delayed", serialize_wrap(api::jobs::index_delayed), "jobs#index_delayed");
+    router.delete("/api/jobs/:node_type_uuid/delayed/:uuid", serialize_wrap(api::jobs::cancel_delayed), "jobs#cancel_delayed");
+    router.get("/api/jobs/:node_type_uuid/dead", serialize_wrap(api::jobs::index_dead), "jobs#index_dead");
+    router.delete("/api/jobs/:node_type_uuid/dead", serialize_wrap(api::jobs::purge_dead), "jobs#purge_dead_all");
+    router.get("/api/jobs/:node_type_uuid/dead/:uuid", serialize_wrap(api::jobs::get_dead), "jobs#get_dead");
+    router.delete("/api/jobs/:node_type_uuid/dead/:uuid", serialize_wrap(api::jobs::purge_dead), "jobs#purge_dead");
+    router.post("/api/jobs/:node_type_uuid/dead/:uuid/retry", json_wrap(api::jobs::retry_dead), "jobs#retry_dead");
     router.get("/api/jobs/:node_type_uuid/:uuid", serialize_wrap(api::jobs::get), "jobs#get"); // gets only finished jobs, but includes all results/errors, not a boolean presence summary
+    router.post("/api/jobs/:node_type_uuid/reroute", json_wrap(api::jobs::reroute), "jobs#reroute");
+    router.post("/api/jobs/:uuid/cancel", json_wrap(api::jobs::cancel), "jobs#cancel");
+    router.get("/api/jobs/:uuid/logs", api::jobs::logs, "jobs#logs");
     router.post("/api/jobs", json_wrap(api::jobs::post), "jobs#post");
 
     router.get("/api/schedules", serialize_wrap(api::schedule::index), "schedule#index");
     router.get("/api/schedules/:uuid", serialize_wrap(api::schedule::get), "schedule#get");
     router.delete("/api/schedules/:uuid", serialize_wrap(api::schedule::delete), "schedule#delete");
     router.post("/api/schedules", json_wrap(api::schedule::post), "schedule#post");
+    router.put("/api/schedules/:uuid", json_wrap(api::schedule::put), "schedule#put");
+    router.post("/api/schedules/:uuid/pause", json_wrap(api::schedule::pause), "schedule#pause");
+    router.post("/api/schedules/:uuid/resume", json_wrap(api::schedule::resume), "schedule#resume");
 
     router.get("/health", health::handle, "health");
 }
@@ -0,0 +1,49 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate log;
+extern crate serde;
+extern crate uuid;
+extern crate redis;
+extern crate time;
+
+use log::*;
+use std::thread;
+use std::time::Duration;
+use util::config;
+
+pub mod util;
+pub mod store;
+pub mod exec;
+pub mod scheduler;
+pub mod threads;
+pub mod http;
+
+use store::*;
+use store::init_store;
+
+// runs a node until the process exits, executors must be registered before calling this
+pub fn run() {
+    let mut store = init_store();
+    let thread_count = store.get_node().node_type.as_ref().unwrap().thread_count;
+    info!("Started node '{}'", store.get_node().uuid.hyphenated().to_string());
+    // jobs used to be queued for the node type of the node enqueuing them, rather than the node type of their job type
+    match store.reroute_jobs_waiting() {
+        Err(e) => { error!("Failed to reroute misrouted jobs: {}", e); },
+        Ok(0) => {},
+        Ok(rerouted) => { info!("Rerouted {} jobs queued for the wrong node type", rerouted); },
+    };
+    threads::ping_thread::start_thread(store.replicate().expect("failed to reconnect to redis"));
+    threads::scheduler_thread::start_thread(store.replicate().expect("failed to reconnect to redis"));
+    threads::reaper_thread::start_thread(store.replicate().expect("failed to reconnect to redis"));
+    for _ in 0..thread_count {
+        threads::worker_thread::start_thread(store.replicate().expect("failed to reconnect to redis"));
+    }
+
+    if &*config::HTTP_SERVER_ENABLED == "true" {
+        http::start_thread(store.replicate().expect("failed to reconnect to redis"));
+    }
+
+    loop {
+        thread::sleep(Duration::from_millis(1000));
+    }
+}
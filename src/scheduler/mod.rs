@@ -1,14 +1,237 @@
 use serde_json::Value;
 use std::collections::HashMap;
+use std::str::FromStr;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use chrono::{ Duration, LocalResult, NaiveDateTime, Offset, TimeZone, Utc };
+use chrono_tz::Tz;
+use cron::Schedule;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MisfirePolicy {
+    Skip, // drop missed occurrences, only firing occurrences that are on time
+    #[default]
+    FireOnce, // fire a single job for any number of missed occurrences
+    FireAll, // fire a job for every missed occurrence, up to `misfire_limit`
+}
+
+impl MisfirePolicy {
+    fn is_default(&self) -> bool {
+        return *self == MisfirePolicy::default();
+    }
+}
+
+pub const DEFAULT_MISFIRE_LIMIT: u32 = 100;
+
+fn default_enabled() -> bool {
+    return true;
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    return *enabled;
+}
 
 #[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
 pub struct ScheduleItem {
     pub uuid: Uuid,
-    pub interval: u64,
+    #[serde(default)]
+    pub interval: u64, // 0 if `cron` is used instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>, // IANA name, defaults to UTC
+    #[serde(default, skip_serializing_if = "MisfirePolicy::is_default")]
+    pub misfire_policy: MisfirePolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub misfire_limit: Option<u32>, // defaults to DEFAULT_MISFIRE_LIMIT
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool, // paused schedule items are skipped by the scheduler
     pub last_scheduled_by: Option<Uuid>,
     pub last_scheduled_at: Option<u64>,
     pub job_type_uuid: Uuid,
     pub job_arguments: HashMap<String, Value>,
-}
\ No newline at end of file
+}
+
+impl ScheduleItem {
+    // checks that exactly one of `interval` and `cron` is set, and that `cron`/`timezone` parse
+    pub fn validate(&self) -> Result<(), String> {
+        if self.misfire_limit == Some(0) {
+            return Err("misfire_limit must be at least 1".to_string());
+        }
+        match &self.cron {
+            Some(_) if self.interval != 0 => return Err("only one of interval and cron may be set".to_string()),
+            Some(_) => {
+                self.parse_cron()?;
+                return Ok(());
+            },
+            None if self.timezone.is_some() => return Err("timezone requires cron to be set".to_string()),
+            None if self.interval == 0 => return Err("one of interval and cron must be set".to_string()),
+            None => return Ok(()),
+        }
+    }
+
+    fn parse_cron(&self) -> Result<(Schedule, Tz), String> {
+        let schedule = Schedule::from_str(self.cron.as_ref().unwrap());
+        if schedule.is_err() {
+            return Err(format!("invalid cron expression '{}': {}", self.cron.as_ref().unwrap(), schedule.err().unwrap()));
+        }
+        let timezone = match &self.timezone {
+            Some(timezone) => Tz::from_str(timezone).map_err(|_| format!("invalid timezone '{}'", timezone))?,
+            None => Tz::UTC,
+        };
+        return Ok((schedule.unwrap(), timezone));
+    }
+
+    // the first time (epoch milliseconds) strictly after `after` that the schedule fires, if any
+    pub fn next_fire_after(&self, after: u64) -> Result<Option<u64>, String> {
+        if self.cron.is_none() {
+            self.validate()?;
+            // intervals fire at every multiple of the interval since the epoch
+            return Ok(Some((after / self.interval + 1) * self.interval));
+        }
+        let (schedule, timezone) = self.parse_cron()?;
+        let after_utc = Utc.timestamp_millis_opt(after as i64).single();
+        if after_utc.is_none() {
+            return Err(format!("invalid time {}", after));
+        }
+        let local_after = after_utc.unwrap().with_timezone(&timezone).naive_local();
+        // cron fields are matched against wall clock time, so walk the schedule in naive local time (with UTC standing in for "no timezone")
+        // and resolve each candidate in the real timezone ourselves, as the cron crate skips times that are ambiguous or skipped by DST
+        for candidate in schedule.after(&Utc.from_utc_datetime(&local_after)) {
+            let fire_at = local_to_epoch(timezone, candidate.naive_utc());
+            if fire_at > after {
+                return Ok(Some(fire_at));
+            }
+        }
+        return Ok(None);
+    }
+
+    // how many jobs to enqueue at `now` according to the misfire policy, or None if nothing is due yet
+    // occurrences due more than `misfire_grace_ms` before `now` count as missed
+    pub fn jobs_due(&self, now: u64, misfire_grace_ms: u64) -> Result<Option<u32>, String> {
+        if self.last_scheduled_at.is_none() {
+            return Ok(Some(1));
+        }
+        let last_scheduled_at = self.last_scheduled_at.unwrap();
+        let next_fire_at = self.next_fire_after(last_scheduled_at)?;
+        if next_fire_at.is_none() || next_fire_at.unwrap() > now {
+            return Ok(None);
+        }
+        match self.misfire_policy {
+            MisfirePolicy::FireOnce => return Ok(Some(1)),
+            MisfirePolicy::Skip => {
+                let on_time_after = last_scheduled_at.max(now.saturating_sub(misfire_grace_ms));
+                let on_time = self.next_fire_after(on_time_after)?.map(|fire_at| fire_at <= now).unwrap_or(false);
+                return Ok(Some(if on_time { 1 } else { 0 }));
+            },
+            MisfirePolicy::FireAll => {
+                let limit = self.misfire_limit.unwrap_or(DEFAULT_MISFIRE_LIMIT);
+                let mut count = 1;
+                let mut fire_at = next_fire_at.unwrap();
+                while count < limit {
+                    match self.next_fire_after(fire_at)? {
+                        Some(next_fire_at) if next_fire_at <= now => {
+                            fire_at = next_fire_at;
+                            count += 1;
+                        },
+                        _ => break,
+                    }
+                }
+                return Ok(Some(count));
+            },
+        }
+    }
+}
+
+fn local_to_epoch(timezone: Tz, local: NaiveDateTime) -> u64 {
+    let time = match timezone.from_local_datetime(&local) {
+        LocalResult::Single(time) => time,
+        // times repeated when clocks go back fire once, on their first occurrence
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        // times skipped when clocks go forward fire shifted forward by the length of the gap
+        LocalResult::None => {
+            let offset_before = timezone.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+            timezone.from_utc_datetime(&(local - Duration::seconds(offset_before.local_minus_utc() as i64)))
+        },
+    };
+    return time.timestamp_millis() as u64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_cron_item(cron: &str, timezone: Option<&str>) -> ScheduleItem {
+        return ScheduleItem {
+            uuid: Uuid::new_v4(),
+            interval: 0,
+            cron: Some(cron.to_string()),
+            timezone: timezone.map(|timezone| timezone.to_string()),
+            misfire_policy: MisfirePolicy::FireOnce,
+            misfire_limit: None,
+            enabled: true,
+            last_scheduled_by: None,
+            last_scheduled_at: None,
+            job_type_uuid: Uuid::new_v4(),
+            job_arguments: HashMap::new(),
+        };
+    }
+
+    fn epoch_of(time: &str) -> u64 {
+        return chrono::DateTime::parse_from_rfc3339(time).unwrap().timestamp_millis() as u64;
+    }
+
+    #[test]
+    fn can_validate() {
+        assert!(make_cron_item("0 0 9 * * Mon-Fri", Some("Europe/Berlin")).validate().is_ok());
+        assert!(make_cron_item("0 0 9 * *", None).validate().is_err());
+        assert!(make_cron_item("0 0 9 * * Mon-Fri", Some("Europe/Nowhere")).validate().is_err());
+        assert!(ScheduleItem { interval: 1000, ..make_cron_item("0 0 9 * * *", None) }.validate().is_err());
+        assert!(ScheduleItem { interval: 1000, cron: None, timezone: None, ..make_cron_item("", None) }.validate().is_ok());
+        assert!(ScheduleItem { cron: None, timezone: None, ..make_cron_item("", None) }.validate().is_err());
+    }
+
+    #[test]
+    fn can_fire_intervals() -> Result<(), String> {
+        let item = ScheduleItem { interval: 1000, cron: None, ..make_cron_item("", None) };
+        assert_eq!(item.next_fire_after(0)?, Some(1000));
+        assert_eq!(item.next_fire_after(1500)?, Some(2000));
+        assert_eq!(item.next_fire_after(2000)?, Some(3000));
+        Ok(())
+    }
+
+    #[test]
+    fn can_apply_misfire_policies() -> Result<(), String> {
+        let item = ScheduleItem { interval: 60000, cron: None, last_scheduled_at: Some(0), ..make_cron_item("", None) };
+        assert_eq!(item.jobs_due(59999, 10000)?, None);
+        // three occurrences were missed, the last one 30 seconds ago
+        assert_eq!(item.jobs_due(210000, 10000)?, Some(1));
+        assert_eq!(ScheduleItem { misfire_policy: MisfirePolicy::Skip, ..item.clone() }.jobs_due(210000, 10000)?, Some(0));
+        assert_eq!(ScheduleItem { misfire_policy: MisfirePolicy::Skip, ..item.clone() }.jobs_due(185000, 10000)?, Some(1));
+        assert_eq!(ScheduleItem { misfire_policy: MisfirePolicy::FireAll, ..item.clone() }.jobs_due(210000, 10000)?, Some(3));
+        assert_eq!(ScheduleItem { misfire_policy: MisfirePolicy::FireAll, misfire_limit: Some(2), ..item.clone() }.jobs_due(210000, 10000)?, Some(2));
+        assert_eq!(ScheduleItem { misfire_policy: MisfirePolicy::FireAll, ..item.clone() }.jobs_due(60000, 10000)?, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn can_fire_weekdays_in_timezone() -> Result<(), String> {
+        let item = make_cron_item("0 0 9 * * Mon-Fri", Some("Europe/Berlin"));
+        // friday 2026-03-27 10:00 CET, the next weekday is monday, after clocks went forward
+        assert_eq!(item.next_fire_after(epoch_of("2026-03-27T10:00:00+01:00"))?, Some(epoch_of("2026-03-30T09:00:00+02:00")));
+        assert_eq!(item.next_fire_after(epoch_of("2026-03-30T08:59:59+02:00"))?, Some(epoch_of("2026-03-30T09:00:00+02:00")));
+        assert_eq!(item.next_fire_after(epoch_of("2026-03-30T09:00:00+02:00"))?, Some(epoch_of("2026-03-31T09:00:00+02:00")));
+        Ok(())
+    }
+
+    #[test]
+    fn can_fire_across_dst_transitions() -> Result<(), String> {
+        // 02:30 does not exist on 2026-03-29 in Berlin, and happens twice on 2026-10-25
+        let item = make_cron_item("0 30 2 * * *", Some("Europe/Berlin"));
+        assert_eq!(item.next_fire_after(epoch_of("2026-03-29T00:00:00+01:00"))?, Some(epoch_of("2026-03-29T03:30:00+02:00")));
+        assert_eq!(item.next_fire_after(epoch_of("2026-10-25T00:00:00+02:00"))?, Some(epoch_of("2026-10-25T02:30:00+02:00")));
+        assert_eq!(item.next_fire_after(epoch_of("2026-10-25T02:30:00+02:00"))?, Some(epoch_of("2026-10-26T02:30:00+01:00")));
+        Ok(())
+    }
+}
@@ -0,0 +1,815 @@
+use crate::store::*;
+use crate::exec::node::Node;
+use uuid::Uuid;
+use serde_json::Value;
+use crate::util::time::epoch;
+use std::collections::{ HashMap, HashSet, VecDeque };
+use std::sync::{ Arc, Mutex, MutexGuard, Condvar };
+
+// mirrors the layout of the redis store, keyed by node type uuid (or node uuid for processing lists)
+#[derive(Default)]
+struct MemoryState {
+    node_types: HashMap<Uuid, NodeType>,
+    job_types: HashMap<Uuid, JobType>,
+    schedule_items: HashMap<Uuid, ScheduleItem>,
+    nodes: HashMap<Uuid, Node>,
+    jobs_waiting: HashMap<Uuid, VecDeque<Job>>,
+    jobs_processing: HashMap<Uuid, Vec<Job>>,
+    jobs_in_progress: HashMap<Uuid, HashMap<Uuid, Job>>,
+    jobs_finished: HashMap<Uuid, HashMap<Uuid, Job>>,
+    unique_locks: HashMap<Uuid, UniqueLock>,
+    jobs_deferred: HashMap<Uuid, VecDeque<Job>>,
+    jobs_delayed: HashMap<Uuid, Vec<(u64, Job)>>, // kept sorted by due time
+    jobs_dead: HashMap<Uuid, HashMap<Uuid, Job>>,
+    jobs_cancelled: HashSet<Uuid>, // running jobs marked for cancellation
+    concurrency_leases: HashMap<Uuid, HashMap<Uuid, Uuid>>, // job type uuid -> running job uuid -> node uuid
+    jobs_throttled: HashMap<Uuid, VecDeque<Job>>, // held back by their job type's max_concurrency
+    rate_limit_buckets: HashMap<Uuid, RateLimitBucket>,
+    job_logs: HashMap<Uuid, JobLogs>,
+    job_logs_expiry: HashMap<Uuid, u64>, // closed logs -> when they are dropped
+}
+
+impl MemoryState {
+    fn attach_job_type(&self, mut job: Job) -> Result<Job, String> {
+        let job_type = self.job_types.get(&job.job_type_uuid);
+        if job_type.is_none() {
+            return Err(format!("invalid job type: '{}'", job.job_type_uuid.hyphenated()));
+        }
+        job.job_type = Some(job_type.unwrap().clone());
+        return Ok(job);
+    }
+
+    fn job_node_type_uuid(&self, job: &Job) -> Result<Uuid, String> {
+        let job_type = self.job_types.get(&job.job_type_uuid);
+        if job_type.is_none() {
+            return Err(format!("invalid job type: '{}'", job.job_type_uuid.hyphenated()));
+        }
+        let node_types: Vec<NodeType> = self.node_types.values().cloned().collect();
+        return job_node_type_uuid(&node_types, job_type.unwrap());
+    }
+
+    // removes a queued, delayed, deferred or throttled job
+    fn remove_queued(&mut self, uuid: Uuid) -> Option<Job> {
+        for queue in self.jobs_waiting.values_mut().chain(self.jobs_deferred.values_mut()).chain(self.jobs_throttled.values_mut()) {
+            if let Some(position) = queue.iter().position(|job| job.uuid == uuid) {
+                return queue.remove(position);
+            }
+        }
+        for delayed in self.jobs_delayed.values_mut() {
+            if let Some(position) = delayed.iter().position(|(_, job)| job.uuid == uuid) {
+                return Some(delayed.remove(position).1);
+            }
+        }
+        return None;
+    }
+
+    fn insert_delayed(&mut self, node_type_uuid: Uuid, job: Job) {
+        let run_at = job.run_at.unwrap_or(0);
+        let delayed = self.jobs_delayed.entry(node_type_uuid).or_default();
+        let position = delayed.iter().position(|(due, _)| *due > run_at).unwrap_or(delayed.len());
+        delayed.insert(position, (run_at, job));
+    }
+
+    // how many more of the job type's jobs may run, or None if it has no max_concurrency
+    fn concurrency_room(&self, job_type_uuid: Uuid) -> Option<usize> {
+        let max_concurrency = self.job_types.get(&job_type_uuid).and_then(|job_type| job_type.max_concurrency)?;
+        let leases = self.concurrency_leases.get(&job_type_uuid).map(|leases| leases.len()).unwrap_or(0);
+        return Some((max_concurrency as usize).saturating_sub(leases));
+    }
+
+    // takes a concurrency lease for the job, unless its job type is already running as many jobs as it may
+    fn try_lease(&mut self, job: &Job, node_uuid: Uuid) -> bool {
+        match self.concurrency_room(job.job_type_uuid) {
+            Some(0) => return false,
+            Some(_) => {
+                self.concurrency_leases.entry(job.job_type_uuid).or_default().insert(job.uuid, node_uuid);
+            },
+            None => (),
+        }
+        return true;
+    }
+
+    // moves throttled jobs (of the given job type, or all) back to the front of the queue, as far as their job types have room for them
+    fn requeue_throttled(&mut self, node_type_uuid: Uuid, job_type_uuid: Option<Uuid>) -> u64 {
+        let throttled = self.jobs_throttled.remove(&node_type_uuid).unwrap_or_default();
+        let mut rooms: HashMap<Uuid, Option<usize>> = HashMap::new();
+        let mut kept: VecDeque<Job> = VecDeque::new();
+        let mut requeued: Vec<Job> = vec![];
+        for job in throttled {
+            if job_type_uuid.map(|uuid| uuid != job.job_type_uuid).unwrap_or(false) {
+                kept.push_back(job);
+                continue;
+            }
+            let room = *rooms.entry(job.job_type_uuid).or_insert_with(|| self.concurrency_room(job.job_type_uuid));
+            if room == Some(0) {
+                kept.push_back(job);
+                continue;
+            }
+            rooms.insert(job.job_type_uuid, room.map(|room| room - 1));
+            requeued.push(Job { status: JobStatus::Queued, ..job });
+        }
+        self.jobs_throttled.insert(node_type_uuid, kept);
+        let count = requeued.len() as u64;
+        let queue = self.jobs_waiting.entry(node_type_uuid).or_default();
+        for job in requeued.into_iter().rev() {
+            queue.push_front(job);
+        }
+        return count;
+    }
+}
+
+// jobs and nodes are stored without their attached types, the same as when serialized into redis
+fn detach_job(job: Job) -> Job {
+    Job { job_type: None, ..job }
+}
+
+fn detach_node(node: &Node) -> Node {
+    Node { node_type: None, ..node.clone() }
+}
+
+pub struct MemoryStore {
+    state: Arc<(Mutex<MemoryState>, Condvar)>,
+    node: Node,
+    ping_interval: u32,
+    dequeue_count: u64, // position in the weighted priority cycle
+}
+
+impl MemoryStore {
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        return self.state.0.lock().unwrap();
+    }
+
+    fn node_type_uuid(&self) -> Uuid {
+        return self.node.node_type_uuid.unwrap();
+    }
+
+    // takes the first waiting job in `order` whose job type's concurrency limit allows it, holding back those it doesn't
+    fn take_waiting_job(&self, state: &mut MemoryState, order: &[Priority]) -> Option<Job> {
+        let node_type_uuid = self.node_type_uuid();
+        loop {
+            let queue = state.jobs_waiting.entry(node_type_uuid).or_default();
+            let position = order.iter().filter_map(|priority| queue.iter().position(|job| job.priority == *priority)).next()?;
+            let job = queue.remove(position).unwrap();
+            if state.try_lease(&job, self.node.uuid) {
+                return Some(job);
+            }
+            // held back until one of its job type's running jobs finishes
+            state.jobs_throttled.entry(node_type_uuid).or_default().push_back(Job { status: JobStatus::Scheduled, ..job });
+        }
+    }
+
+    // moves a taken job in progress on our node
+    fn start_job(&self, state: &mut MemoryState, mut job: Job) -> Result<Job, String> {
+        let node_type_uuid = self.node_type_uuid();
+        state.jobs_processing.entry(self.node.uuid).or_default().push(job.clone());
+        job.transition(JobStatus::Running)?;
+        job.started_at = Some(epoch());
+        job.executing_node = Some(self.node.uuid);
+        state.jobs_in_progress.entry(node_type_uuid).or_default().insert(job.uuid, job.clone());
+        if let Some(lock) = state.unique_locks.get_mut(&job.job_type_uuid).filter(|lock| lock.job_uuid == job.uuid) {
+            lock.node_uuid = Some(self.node.uuid);
+        }
+        return state.attach_job_type(job);
+    }
+
+    fn remove_in_progress(&self, state: &mut MemoryState, job: &Job) {
+        if let Some(processing) = state.jobs_processing.get_mut(&self.node.uuid) {
+            processing.retain(|item| item.uuid != job.uuid);
+        }
+        if let Some(in_progress) = state.jobs_in_progress.get_mut(&self.node_type_uuid()) {
+            in_progress.remove(&job.uuid);
+        }
+        state.jobs_cancelled.remove(&job.uuid);
+        // the job's concurrency lease goes to the first job held back by it
+        let released = state.concurrency_leases.get_mut(&job.job_type_uuid).map(|leases| leases.remove(&job.uuid).is_some()).unwrap_or(false);
+        if released && state.requeue_throttled(self.node_type_uuid(), Some(job.job_type_uuid)) > 0 {
+            self.state.1.notify_all();
+        }
+    }
+
+    // moves a job from our in progress jobs to the delayed jobs, until its `run_at`
+    fn delay_in_progress(&self, job: Job) {
+        let node_type_uuid = self.node_type_uuid();
+        let mut state = self.lock();
+        self.remove_in_progress(&mut state, &job);
+        // the unique lock stays with the job while it is delayed, but is no longer owned by this node
+        if let Some(lock) = state.unique_locks.get_mut(&job.job_type_uuid).filter(|lock| lock.job_uuid == job.uuid) {
+            lock.node_uuid = None;
+        }
+        state.insert_delayed(node_type_uuid, detach_job(job));
+    }
+
+    // releases the unique lock held by the job, handing it to the next deferred job if there is one
+    fn release_unique_lock(&self, state: &mut MemoryState, job: &Job) {
+        if !state.unique_locks.get(&job.job_type_uuid).map(|lock| lock.job_uuid == job.uuid).unwrap_or(false) {
+            return;
+        }
+        let deferred = state.jobs_deferred.get_mut(&job.job_type_uuid).and_then(|deferred| deferred.pop_front());
+        match deferred {
+            Some(deferred) => {
+                state.unique_locks.insert(job.job_type_uuid, UniqueLock { job_uuid: deferred.uuid, node_uuid: None });
+                let node_type_uuid = state.job_node_type_uuid(&deferred).unwrap_or(self.node_type_uuid());
+                if is_handed_off_delayed(&deferred) {
+                    state.insert_delayed(node_type_uuid, deferred);
+                    return;
+                }
+                let deferred = Job { status: JobStatus::Queued, ..deferred };
+                state.jobs_waiting.entry(node_type_uuid).or_default().push_back(deferred);
+                self.state.1.notify_all();
+            },
+            None => {
+                state.unique_locks.remove(&job.job_type_uuid);
+            },
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn connect() -> Result<MemoryStore, String> {
+        let new_node = Node { uuid: Uuid::new_v4(), last_ping: epoch(), node_type_uuid: None, node_type: None };
+        let mut state = MemoryState::default();
+        state.nodes.insert(new_node.uuid, new_node.clone());
+        return Ok(MemoryStore { state: Arc::new((Mutex::new(state), Condvar::new())), node: new_node, ping_interval: 5000, dequeue_count: 0 });
+    }
+
+    fn get_node_types(&mut self) -> Result<Vec<NodeType>, String> {
+        return Ok(self.lock().node_types.values().cloned().collect());
+    }
+
+    fn get_node_type(&mut self, node_type_uuid: Uuid) -> Result<Option<NodeType>, String> {
+        return Ok(self.lock().node_types.get(&node_type_uuid).cloned());
+    }
+
+    fn new_node_type(&mut self, node_type: &NodeType) -> Result<(), String> {
+        self.lock().node_types.insert(node_type.uuid, node_type.clone());
+        return Ok(());
+    }
+
+    fn set_node_type(&mut self, node_type_uuid: Uuid) -> Result<Option<()>, String> {
+        if self.set_node_type_soft(node_type_uuid)?.is_none() {
+            return Ok(None);
+        }
+        let node = detach_node(&self.node);
+        self.lock().nodes.insert(node.uuid, node);
+        return Ok(Some(()));
+    }
+
+    fn set_node_type_soft(&mut self, node_type_uuid: Uuid) -> Result<Option<()>, String> {
+        let node_type = self.get_node_type(node_type_uuid)?;
+        if node_type.is_none() {
+            return Ok(None);
+        }
+        self.node.node_type_uuid = Some(node_type_uuid);
+        self.node.node_type = node_type;
+        return Ok(Some(()));
+    }
+
+    fn get_job_types(&mut self) -> Result<Vec<JobType>, String> {
+        return Ok(self.lock().job_types.values().cloned().collect());
+    }
+
+    fn get_job_type(&mut self, uuid: Uuid) -> Result<Option<JobType>, String> {
+        return Ok(self.lock().job_types.get(&uuid).cloned());
+    }
+
+    fn new_job_type(&mut self, job_type: &JobType) -> Result<(), String> {
+        self.lock().job_types.insert(job_type.uuid, job_type.clone());
+        return Ok(());
+    }
+
+    fn get_job_schedule(&mut self) -> Result<Vec<ScheduleItem>, String> {
+        return Ok(self.lock().schedule_items.values().cloned().collect());
+    }
+
+    fn get_job_schedule_item(&mut self, uuid: Uuid) -> Result<Option<ScheduleItem>, String> {
+        return Ok(self.lock().schedule_items.get(&uuid).cloned());
+    }
+
+    fn delete_job_schedule_item(&mut self, uuid: Uuid) -> Result<(), String> {
+        self.lock().schedule_items.remove(&uuid);
+        return Ok(());
+    }
+
+    fn new_job_schedule_item(&mut self, schedule_item: &ScheduleItem) -> Result<(), String> {
+        self.lock().schedule_items.insert(schedule_item.uuid, schedule_item.clone());
+        return Ok(());
+    }
+
+    fn update_job_schedule_item(&mut self, old_schedule_item: &ScheduleItem, new_schedule_item: &ScheduleItem) -> Result<bool, String> {
+        let mut state = self.lock();
+        if state.schedule_items.get(&old_schedule_item.uuid) != Some(old_schedule_item) {
+            return Ok(false);
+        }
+        state.schedule_items.insert(old_schedule_item.uuid, new_schedule_item.clone());
+        return Ok(true);
+    }
+
+    fn claim_job_scheduled(&mut self, schedule_item: &ScheduleItem) -> Result<Option<ScheduleItem>, String> {
+        let mut new_schedule_item = schedule_item.clone();
+        new_schedule_item.last_scheduled_by = Some(self.node.uuid);
+        new_schedule_item.last_scheduled_at = Some(epoch());
+        if !self.update_job_schedule_item(schedule_item, &new_schedule_item)? {
+            return Ok(None); // someone else modified/claimed it
+        }
+        return Ok(Some(new_schedule_item)); // we claimed it
+    }
+
+    fn enqueue_job(&mut self, mut job: Job) -> Result<EnqueueResult, String> {
+        check_new_job(&job)?;
+        job.enqueued_at = Some(epoch());
+        let mut state = self.lock();
+        let job_type = match job.job_type.as_ref() {
+            Some(job_type) => job_type.clone(),
+            None => state.attach_job_type(job.clone())?.job_type.unwrap(),
+        };
+        let node_types: Vec<NodeType> = state.node_types.values().cloned().collect();
+        let node_type_uuid = job_node_type_uuid(&node_types, &job_type)?;
+        if job_type.unique {
+            let existing = state.unique_locks.get(&job_type.uuid).map(|lock| lock.job_uuid);
+            if existing.is_some() {
+                let result = EnqueueResult::unique_conflict(job_type.unique_policy, existing.unwrap());
+                if result == EnqueueResult::Deferred {
+                    job.transition(JobStatus::Scheduled)?;
+                    state.jobs_deferred.entry(job_type.uuid).or_default().push_back(detach_job(job));
+                }
+                return Ok(result);
+            }
+            state.unique_locks.insert(job_type.uuid, UniqueLock { job_uuid: job.uuid, node_uuid: None });
+        }
+        if is_delayed(&job) {
+            job.transition(JobStatus::Scheduled)?;
+            state.insert_delayed(node_type_uuid, detach_job(job));
+            return Ok(EnqueueResult::Delayed);
+        }
+        state.jobs_waiting.entry(node_type_uuid).or_default().push_back(detach_job(job));
+        self.state.1.notify_all();
+        return Ok(EnqueueResult::Enqueued);
+    }
+
+    fn dequeue_job(&mut self) -> Result<Job, String> {
+        let order = Priority::dequeue_order(self.dequeue_count);
+        self.dequeue_count += 1;
+        let mut state = self.lock();
+        let job = loop {
+            let job = self.take_waiting_job(&mut state, &order);
+            if job.is_some() {
+                break job.unwrap();
+            }
+            state = self.state.1.wait(state).unwrap();
+        };
+        return self.start_job(&mut state, job);
+    }
+
+    fn try_dequeue_job(&mut self) -> Result<Option<Job>, String> {
+        let order = Priority::dequeue_order(self.dequeue_count);
+        let mut state = self.lock();
+        let job = self.take_waiting_job(&mut state, &order);
+        if job.is_none() {
+            return Ok(None);
+        }
+        let job = self.start_job(&mut state, job.unwrap());
+        drop(state);
+        self.dequeue_count += 1;
+        return job.map(Some);
+    }
+
+    fn finish_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String> {
+        let node_type_uuid = self.node_type_uuid();
+        job.transition(JobStatus::from_outcome(&results, &errors))?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        let mut state = self.lock();
+        self.remove_in_progress(&mut state, &job);
+        self.release_unique_lock(&mut state, &job);
+        state.jobs_finished.entry(node_type_uuid).or_default().insert(job.uuid, detach_job(job));
+        return Ok(());
+    }
+
+    fn retry_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>, retry_at: u64) -> Result<(), String> {
+        job.transition(JobStatus::Retrying)?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        job.attempt += 1;
+        job.run_at = Some(retry_at);
+        self.delay_in_progress(job);
+        return Ok(());
+    }
+
+    fn delay_job(&mut self, mut job: Job, run_at: u64) -> Result<(), String> {
+        job.transition(JobStatus::Scheduled)?;
+        job.started_at = None;
+        job.executing_node = None;
+        job.run_at = Some(run_at);
+        self.delay_in_progress(job);
+        return Ok(());
+    }
+
+    fn kill_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String> {
+        let node_type_uuid = self.node_type_uuid();
+        job.transition(JobStatus::Dead)?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        let mut state = self.lock();
+        self.remove_in_progress(&mut state, &job);
+        self.release_unique_lock(&mut state, &job);
+        state.jobs_dead.entry(node_type_uuid).or_default().insert(job.uuid, detach_job(job));
+        return Ok(());
+    }
+
+    fn promote_delayed_jobs(&mut self) -> Result<u64, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let now = epoch();
+        let mut state = self.lock();
+        let delayed = state.jobs_delayed.entry(node_type_uuid).or_default();
+        let due_count = delayed.iter().take_while(|(due, _)| *due <= now).count();
+        let due: Vec<(u64, Job)> = delayed.drain(..due_count).collect();
+        state.jobs_waiting.entry(node_type_uuid).or_default().extend(due.into_iter().map(|(_, job)| Job { status: JobStatus::Queued, ..job }));
+        // throttled jobs are usually requeued when a lease is released, this catches any left behind, e.g. by a cancelled job
+        let promoted = due_count as u64 + state.requeue_throttled(node_type_uuid, None);
+        if promoted > 0 {
+            self.state.1.notify_all();
+        }
+        return Ok(promoted);
+    }
+
+    fn get_all_jobs_delayed(&mut self) -> Result<Vec<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let state = self.lock();
+        let jobs = state.jobs_delayed.get(&node_type_uuid).map(|delayed| delayed.iter().map(|(_, job)| job.clone()).collect()).unwrap_or(vec![]);
+        return jobs.into_iter().map(|job| state.attach_job_type(job)).collect();
+    }
+
+    fn cancel_delayed_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let mut state = self.lock();
+        let delayed = state.jobs_delayed.entry(node_type_uuid).or_default();
+        let position = delayed.iter().position(|(_, job)| job.uuid == uuid);
+        if position.is_none() {
+            return Ok(None);
+        }
+        let (_, mut job) = delayed.remove(position.unwrap());
+        job.transition(JobStatus::Cancelled)?;
+        self.release_unique_lock(&mut state, &job);
+        return state.attach_job_type(job).map(Some);
+    }
+
+    fn get_all_jobs_dead(&mut self) -> Result<Vec<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let state = self.lock();
+        let jobs = state.jobs_dead.get(&node_type_uuid).map(|jobs| jobs.values().cloned().collect()).unwrap_or(vec![]);
+        return jobs.into_iter().map(|job| state.attach_job_type(job)).collect();
+    }
+
+    fn get_dead_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let state = self.lock();
+        let job = state.jobs_dead.get(&node_type_uuid).and_then(|jobs| jobs.get(&uuid)).cloned();
+        return job.map(|job| state.attach_job_type(job)).transpose();
+    }
+
+    fn purge_dead_jobs(&mut self, uuid: Option<Uuid>) -> Result<u64, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let mut state = self.lock();
+        let dead = state.jobs_dead.entry(node_type_uuid).or_default();
+        let purged: Vec<Uuid> = match uuid {
+            Some(uuid) => dead.remove(&uuid).into_iter().map(|job| job.uuid).collect(),
+            None => dead.drain().map(|(uuid, _)| uuid).collect(),
+        };
+        for uuid in purged.iter() {
+            state.job_logs.remove(uuid);
+            state.job_logs_expiry.remove(uuid);
+        }
+        return Ok(purged.len() as u64);
+    }
+
+    fn get_all_jobs_waiting(&mut self) -> Result<Vec<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let state = self.lock();
+        let mut jobs: Vec<Job> = state.jobs_waiting.get(&node_type_uuid).map(|queue| queue.iter().cloned().collect()).unwrap_or(vec![]);
+        // highest priority first, the same as the separate priority queues of the other stores
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.priority));
+        return jobs.into_iter().map(|job| state.attach_job_type(job)).collect();
+    }
+
+    fn reroute_jobs_waiting(&mut self) -> Result<u64, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let mut state = self.lock();
+        let node_types: Vec<NodeType> = state.node_types.values().cloned().collect();
+        let waiting = state.jobs_waiting.remove(&node_type_uuid).unwrap_or_default();
+        let mut staying: VecDeque<Job> = VecDeque::new();
+        let mut rerouted: u64 = 0;
+        for job in waiting {
+            let job_type = state.attach_job_type(job.clone())?.job_type.unwrap();
+            let target_node_type_uuid = job_node_type_uuid(&node_types, &job_type);
+            if target_node_type_uuid.is_err() {
+                warn!("Leaving job in queue of node type '{}': {}", node_type_uuid.hyphenated(), target_node_type_uuid.err().unwrap());
+                staying.push_back(job);
+                continue;
+            }
+            let target_node_type_uuid = target_node_type_uuid.unwrap();
+            if target_node_type_uuid == node_type_uuid {
+                staying.push_back(job);
+                continue;
+            }
+            state.jobs_waiting.entry(target_node_type_uuid).or_default().push_back(job);
+            rerouted += 1;
+        }
+        state.jobs_waiting.insert(node_type_uuid, staying);
+        if rerouted > 0 {
+            self.state.1.notify_all();
+        }
+        return Ok(rerouted);
+    }
+
+    fn get_all_jobs_in_progress(&mut self) -> Result<Vec<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let state = self.lock();
+        let jobs = state.jobs_in_progress.get(&node_type_uuid).map(|jobs| jobs.values().cloned().collect()).unwrap_or(vec![]);
+        return jobs.into_iter().map(|job| state.attach_job_type(job)).collect();
+    }
+
+    fn get_all_jobs_finished(&mut self) -> Result<Vec<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let state = self.lock();
+        let jobs = state.jobs_finished.get(&node_type_uuid).map(|jobs| jobs.values().cloned().collect()).unwrap_or(vec![]);
+        return jobs.into_iter().map(|job| state.attach_job_type(job)).collect();
+    }
+
+    fn get_finished_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let state = self.lock();
+        let job = state.jobs_finished.get(&node_type_uuid).and_then(|jobs| jobs.get(&uuid)).cloned();
+        return job.map(|job| state.attach_job_type(job)).transpose();
+    }
+
+    fn ping(&mut self) -> Result<(), String> {
+        self.node.last_ping = epoch();
+        let node = detach_node(&self.node);
+        self.lock().nodes.insert(node.uuid, node);
+        return Ok(());
+    }
+
+    fn get_ping_interval_ms(&self) -> u32 {
+        return self.ping_interval;
+    }
+
+    fn get_node(&mut self) -> &mut Node {
+        return &mut self.node;
+    }
+
+    fn get_nodes(&mut self) -> Result<Vec<Node>, String> {
+        let request_finish_epoch = epoch();
+        return Ok(self.lock().nodes.values().filter(|node| node.last_ping + NODE_EXPIRY_MS >= request_finish_epoch).cloned().collect());
+    }
+
+    fn get_other_node(&mut self, uuid: Uuid) -> Result<Option<Node>, String> {
+        let request_finish_epoch = epoch();
+        return Ok(self.lock().nodes.get(&uuid).filter(|node| node.last_ping + NODE_EXPIRY_MS >= request_finish_epoch).cloned());
+    }
+
+    fn reap_dead_nodes(&mut self) -> Result<u64, String> {
+        let request_finish_epoch = epoch();
+        let mut state = self.lock();
+        let dead_nodes: Vec<Node> = state.nodes.values().filter(|node| node.uuid != self.node.uuid && node.last_ping + NODE_EXPIRY_MS < request_finish_epoch).cloned().collect();
+        let dead_node_uuids: Vec<Uuid> = dead_nodes.iter().map(|node| node.uuid).collect();
+        let mut recovered: u64 = 0;
+        for node in dead_nodes {
+            state.nodes.remove(&node.uuid);
+            let processing = state.jobs_processing.remove(&node.uuid).unwrap_or(vec![]);
+            if node.node_type_uuid.is_none() {
+                continue;
+            }
+            let node_type_uuid = node.node_type_uuid.unwrap();
+            for job in processing.into_iter().rev() {
+                if let Some(in_progress) = state.jobs_in_progress.get_mut(&node_type_uuid) {
+                    in_progress.remove(&job.uuid);
+                }
+                // unique locks stay with recovered jobs, but are no longer owned by the dead node
+                if let Some(lock) = state.unique_locks.get_mut(&job.job_type_uuid).filter(|lock| lock.job_uuid == job.uuid) {
+                    lock.node_uuid = None;
+                }
+                state.jobs_waiting.entry(node_type_uuid).or_default().push_front(job);
+                recovered += 1;
+            }
+        }
+        state.unique_locks.retain(|_, lock| lock.node_uuid.map(|node_uuid| !dead_node_uuids.contains(&node_uuid)).unwrap_or(true));
+        for leases in state.concurrency_leases.values_mut() {
+            leases.retain(|_, node_uuid| !dead_node_uuids.contains(node_uuid));
+        }
+        let node_type_uuids: HashSet<Uuid> = state.jobs_throttled.keys().cloned().collect();
+        let mut requeued: u64 = 0;
+        for node_type_uuid in node_type_uuids {
+            requeued += state.requeue_throttled(node_type_uuid, None);
+        }
+        if recovered + requeued > 0 {
+            self.state.1.notify_all();
+        }
+        return Ok(recovered);
+    }
+
+    fn replicate(&self) -> Result<StoreRef, String> {
+        return Ok(Box::new(MemoryStore {
+            state: self.state.clone(),
+            node: self.node.clone(),
+            ping_interval: self.ping_interval,
+            dequeue_count: 0,
+        }));
+    }
+
+    fn cancel_job(&mut self, uuid: Uuid) -> Result<CancelResult, String> {
+        let mut state = self.lock();
+        // running jobs are killed by their node, which polls for cancellation
+        if state.jobs_in_progress.values().any(|in_progress| in_progress.contains_key(&uuid)) {
+            state.jobs_cancelled.insert(uuid);
+            return Ok(CancelResult::Cancelling);
+        }
+        let job = state.remove_queued(uuid);
+        if job.is_none() {
+            return Ok(CancelResult::NotFound);
+        }
+        let mut job = job.unwrap();
+        job.transition(JobStatus::Cancelled)?;
+        job.ended_at = Some(epoch());
+        job.errors = Some(cancelled_errors());
+        let node_type_uuid = state.job_node_type_uuid(&job)?;
+        self.release_unique_lock(&mut state, &job);
+        state.jobs_finished.entry(node_type_uuid).or_default().insert(job.uuid, job);
+        return Ok(CancelResult::Cancelled);
+    }
+
+    fn is_job_cancelled(&mut self, uuid: Uuid) -> Result<bool, String> {
+        return Ok(self.lock().jobs_cancelled.contains(&uuid));
+    }
+
+    fn get_rate_limit_bucket(&mut self, job_type_uuid: Uuid) -> Result<Option<RateLimitBucket>, String> {
+        return Ok(self.lock().rate_limit_buckets.get(&job_type_uuid).cloned());
+    }
+
+    fn update_rate_limit_bucket(&mut self, job_type_uuid: Uuid, old_bucket: Option<&RateLimitBucket>, new_bucket: &RateLimitBucket) -> Result<bool, String> {
+        let mut state = self.lock();
+        if state.rate_limit_buckets.get(&job_type_uuid) != old_bucket {
+            return Ok(false);
+        }
+        state.rate_limit_buckets.insert(job_type_uuid, new_bucket.clone());
+        return Ok(true);
+    }
+
+    fn append_job_logs(&mut self, job_uuid: Uuid, output: &str) -> Result<(), String> {
+        let mut state = self.lock();
+        state.job_logs.entry(job_uuid).or_default().append(output);
+        state.job_logs_expiry.remove(&job_uuid);
+        return Ok(());
+    }
+
+    fn close_job_logs(&mut self, job_uuid: Uuid) -> Result<(), String> {
+        let now = epoch();
+        let mut state = self.lock();
+        if let Some(logs) = state.job_logs.get_mut(&job_uuid) {
+            logs.closed = true;
+            state.job_logs_expiry.insert(job_uuid, now + JOB_LOGS_RETENTION_MS);
+        }
+        // drops the logs of other jobs whose retention has passed
+        let expired: Vec<Uuid> = state.job_logs_expiry.iter().filter(|(_, expires_at)| **expires_at <= now).map(|(uuid, _)| *uuid).collect();
+        for uuid in expired {
+            state.job_logs.remove(&uuid);
+            state.job_logs_expiry.remove(&uuid);
+        }
+        return Ok(());
+    }
+
+    fn get_job_logs(&mut self, job_uuid: Uuid) -> Result<Option<JobLogs>, String> {
+        let state = self.lock();
+        if state.job_logs_expiry.get(&job_uuid).map(|expires_at| *expires_at <= epoch()).unwrap_or(false) {
+            return Ok(None);
+        }
+        return Ok(state.job_logs.get(&job_uuid).cloned());
+    }
+
+    fn set_job_progress(&mut self, job_uuid: Uuid, progress: &JobProgress) -> Result<bool, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let mut state = self.lock();
+        let job = state.jobs_in_progress.get_mut(&node_type_uuid).and_then(|in_progress| in_progress.get_mut(&job_uuid));
+        if job.is_none() {
+            return Ok(false);
+        }
+        job.unwrap().progress = Some(progress.clone());
+        return Ok(true);
+    }
+
+    fn clean(&mut self) {
+        *self.lock() = MemoryState::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn can_share_replicated_state() -> Result<(), String> {
+        let mut store: StoreRef = Box::new(MemoryStore::connect()?);
+        let mut other_store = store.replicate()?;
+        let test_node_type = make_node_type(&mut store)?;
+        assert_eq!(other_store.get_node_type(test_node_type.uuid)?, Some(test_node_type.clone()));
+        let mut unrelated_store: StoreRef = Box::new(MemoryStore::connect()?);
+        assert_eq!(unrelated_store.get_node_type(test_node_type.uuid)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn can_enqueue_dequeue_finish_jobs() -> Result<(), String> {
+        let mut store: StoreRef = Box::new(MemoryStore::connect()?);
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let mut test_job = make_job(&mut store, &test_job_type)?;
+        let dequeued_job = store.dequeue_job()?;
+        test_job.enqueued_at = dequeued_job.enqueued_at;
+        test_job.started_at = dequeued_job.started_at;
+        test_job.executing_node = Some(store.get_node().uuid);
+        test_job.status = JobStatus::Running;
+        assert_eq!(dequeued_job, test_job);
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![test_job.clone()]);
+        store.finish_job(dequeued_job, Some(Value::Bool(true)), None)?;
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![]);
+        let finished_job = store.get_finished_job(test_job.uuid)?.unwrap();
+        assert_eq!(finished_job.results, Some(Value::Bool(true)));
+        assert_eq!(finished_job.job_type, Some(test_job_type));
+        Ok(())
+    }
+
+    #[test]
+    fn can_block_on_dequeue() -> Result<(), String> {
+        let mut store: StoreRef = Box::new(MemoryStore::connect()?);
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let mut worker_store = store.replicate()?;
+        let worker = thread::spawn(move || worker_store.dequeue_job());
+        thread::sleep(Duration::from_millis(100));
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let dequeued_job = worker.join().unwrap()?;
+        assert_eq!(dequeued_job.uuid, test_job.uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn can_reap_dead_nodes() -> Result<(), String> {
+        let memory_store = MemoryStore::connect()?;
+        let mut store = memory_store.replicate()?;
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        store.dequeue_job()?;
+
+        // a replicated store with a different identity stands in for another node
+        let mut other_store = memory_store.replicate()?;
+        other_store.get_node().uuid = Uuid::new_v4();
+        other_store.set_node_type(test_node_type.uuid)?;
+        assert_eq!(other_store.reap_dead_nodes()?, 0);
+
+        let dead_node_uuid = store.get_node().uuid;
+        memory_store.lock().nodes.get_mut(&dead_node_uuid).unwrap().last_ping = 0;
+        assert_eq!(other_store.reap_dead_nodes()?, 1);
+        let waiting_jobs = other_store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, test_job.uuid);
+        assert_eq!(waiting_jobs[0].executing_node, None);
+        assert_eq!(other_store.get_all_jobs_in_progress()?, vec![]);
+        assert_eq!(other_store.get_other_node(dead_node_uuid)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn releases_concurrency_leases_of_dead_nodes() -> Result<(), String> {
+        let memory_store = MemoryStore::connect()?;
+        let mut store = memory_store.replicate()?;
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut limited_job_type = make_job_type(&mut store)?;
+        limited_job_type.max_concurrency = Some(1);
+        store.new_job_type(&limited_job_type)?;
+        let test_job = make_job(&mut store, &limited_job_type)?;
+        store.dequeue_job()?;
+        // keeps the dequeue below from blocking if the recovered job is held back
+        let test_job_type = make_job_type(&mut store)?;
+        make_job(&mut store, &test_job_type)?;
+
+        let mut other_store = memory_store.replicate()?;
+        other_store.get_node().uuid = Uuid::new_v4();
+        other_store.set_node_type(test_node_type.uuid)?;
+        let dead_node_uuid = store.get_node().uuid;
+        memory_store.lock().nodes.get_mut(&dead_node_uuid).unwrap().last_ping = 0;
+        assert_eq!(other_store.reap_dead_nodes()?, 1);
+        // the recovered job would be held back if the dead node still held its lease
+        assert_eq!(other_store.dequeue_job()?.uuid, test_job.uuid);
+        Ok(())
+    }
+}
@@ -1,15 +1,22 @@
 pub mod redis;
+pub mod memory;
+pub mod sql;
+pub mod sqlite;
+#[cfg(feature = "postgres")]
+pub mod postgres;
 
 use crate::exec::node_type::NodeType;
-use crate::exec::job_type::JobType;
-use crate::exec::job::Job;
+use crate::exec::job_type::{ JobType, UniquePolicy, Priority, RateLimitBucket };
+use crate::exec::job::{ Job, JobStatus, JobLogs, JobProgress, JOB_LOGS_CAPACITY, JOB_LOGS_RETENTION_MS };
 use crate::scheduler::ScheduleItem;
 use uuid::Uuid;
 use crate::exec::node::Node;
-use serde_json::Value;
+use serde_json::{ Value, json };
 use log::*;
 use crate::util::config;
 use std::process::exit;
+use serde::{ Deserialize, Serialize };
+use crate::util::time::epoch;
 
 pub trait Store {
     fn connect() -> Result<Self, String> where Self: std::marker::Sized;
@@ -27,24 +34,153 @@ pub trait Store {
     fn get_job_schedule_item(&mut self, uuid: Uuid) -> Result<Option<ScheduleItem>, String>;
     fn delete_job_schedule_item(&mut self, uuid: Uuid) -> Result<(), String>;
     fn new_job_schedule_item(&mut self, schedule_item: &ScheduleItem) -> Result<(), String>;
+    // replaces a schedule item only if it is unchanged from `old_schedule_item`, returning whether it was replaced
+    fn update_job_schedule_item(&mut self, old_schedule_item: &ScheduleItem, new_schedule_item: &ScheduleItem) -> Result<bool, String>;
     fn claim_job_scheduled(&mut self, schedule_item: &ScheduleItem) -> Result<Option<ScheduleItem>, String>;
-    fn enqueue_job(&mut self, job: Job) -> Result<(), String>;
+    fn enqueue_job(&mut self, job: Job) -> Result<EnqueueResult, String>;
     fn dequeue_job(&mut self) -> Result<Job, String>;
+    // dequeues a job only if one can be started now, rather than waiting for one
+    fn try_dequeue_job(&mut self) -> Result<Option<Job>, String>;
     fn get_all_jobs_waiting(&mut self) -> Result<Vec<Job>, String>;
+    // moves jobs in our node type's queue that belong to another node type onto that node type's queue, returning the number of jobs moved
+    fn reroute_jobs_waiting(&mut self) -> Result<u64, String>;
     fn get_all_jobs_in_progress(&mut self) -> Result<Vec<Job>, String>;
     fn get_all_jobs_finished(&mut self) -> Result<Vec<Job>, String>;
     fn get_finished_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String>;
     fn finish_job(&mut self, job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String>;
+    // finishes a failed attempt, holding the job back in the delayed jobs until `retry_at` (epoch milliseconds)
+    fn retry_job(&mut self, job: Job, results: Option<Value>, errors: Option<Value>, retry_at: u64) -> Result<(), String>;
+    // finishes a job that has run out of attempts, moving it to the dead jobs rather than the finished jobs
+    fn kill_job(&mut self, job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String>;
+    // moves a dequeued job back to the delayed jobs until `run_at`, without counting an attempt
+    fn delay_job(&mut self, job: Job, run_at: u64) -> Result<(), String>;
+    // moves delayed jobs that are due onto the waiting queue, returning the number of jobs moved
+    fn promote_delayed_jobs(&mut self) -> Result<u64, String>;
+    fn get_all_jobs_delayed(&mut self) -> Result<Vec<Job>, String>;
+    // removes a delayed job before it is due, returning it if it was still delayed
+    fn cancel_delayed_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String>;
+    fn get_all_jobs_dead(&mut self) -> Result<Vec<Job>, String>;
+    fn get_dead_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String>;
+    // deletes a single dead job, or all of them if no uuid is given, along with their logs, returning the number deleted
+    fn purge_dead_jobs(&mut self, uuid: Option<Uuid>) -> Result<u64, String>;
+    // cancels a job of any node type. queued jobs are finished as cancelled immediately, running jobs are marked for their node to kill
+    fn cancel_job(&mut self, uuid: Uuid) -> Result<CancelResult, String>;
+    // whether a running job has been marked as cancelled
+    fn is_job_cancelled(&mut self, uuid: Uuid) -> Result<bool, String>;
+    fn get_rate_limit_bucket(&mut self, job_type_uuid: Uuid) -> Result<Option<RateLimitBucket>, String>;
+    // replaces the job type's bucket if it is still `old_bucket`, returning false if it was changed in the meantime
+    fn update_rate_limit_bucket(&mut self, job_type_uuid: Uuid, old_bucket: Option<&RateLimitBucket>, new_bucket: &RateLimitBucket) -> Result<bool, String>;
+    // appends a running job's output to its logs, creating them if needed, and reopening them if closed
+    fn append_job_logs(&mut self, job_uuid: Uuid, output: &str) -> Result<(), String>;
+    // marks a job's logs as complete once an attempt at it finishes, after which they are kept for `JOB_LOGS_RETENTION_MS`
+    fn close_job_logs(&mut self, job_uuid: Uuid) -> Result<(), String>;
+    fn get_job_logs(&mut self, job_uuid: Uuid) -> Result<Option<JobLogs>, String>;
+    // sets the progress of one of our node type's jobs in progress, returning false if it is not in progress
+    fn set_job_progress(&mut self, job_uuid: Uuid, progress: &JobProgress) -> Result<bool, String>;
     fn ping(&mut self) -> Result<(), String>;
     fn get_ping_interval_ms(&self) -> u32;
     fn get_node(&mut self) -> &mut Node;
     fn get_nodes(&mut self) -> Result<Vec<Node>, String>;
     fn get_other_node(&mut self, uuid: Uuid) -> Result<Option<Node>, String>;
+    // re-enqueues jobs claimed by nodes that have stopped pinging, returning the number of jobs recovered
+    fn reap_dead_nodes(&mut self) -> Result<u64, String>;
     fn clean(&mut self);
 }
 
 pub type StoreRef = Box<dyn Store + Send>;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnqueueResult {
+    Enqueued,
+    Delayed, // held back until its `run_at`
+    Deferred, // held back until the job holding the unique lock finishes
+    Rejected(Uuid), // uuid of the job holding the unique lock
+    Coalesced(Uuid), // uuid of the job holding the unique lock
+}
+
+impl EnqueueResult {
+    pub fn unique_conflict(policy: UniquePolicy, existing_job_uuid: Uuid) -> EnqueueResult {
+        match policy {
+            UniquePolicy::Reject => EnqueueResult::Rejected(existing_job_uuid),
+            UniquePolicy::Coalesce => EnqueueResult::Coalesced(existing_job_uuid),
+            UniquePolicy::Defer => EnqueueResult::Deferred,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CancelResult {
+    Cancelled, // the job was queued, and has been finished as cancelled
+    Cancelling, // the job is running (or about to), and will be killed by its node
+    NotFound, // the job does not exist or has already finished
+}
+
+// how long since its last ping before a node is reaped as dead: 20 seconds leeway (pings are every 5 seconds)
+pub const NODE_EXPIRY_MS: u64 = 20000;
+
+// how many times cancelling a job looks for it again, if it moved while being cancelled
+pub const CANCEL_ATTEMPTS: u32 = 3;
+
+// the errors recorded on a job that was cancelled before it started
+pub fn cancelled_errors() -> Value {
+    return json!({ "kind": "cancelled" });
+}
+
+// held per unique job type by its queued or running job. `node_uuid` is the executing node, if running.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct UniqueLock {
+    pub job_uuid: Uuid,
+    pub node_uuid: Option<Uuid>,
+}
+
+// new jobs start out queued, and are moved to scheduled by the store if they are delayed or deferred
+pub fn check_new_job(job: &Job) -> Result<(), String> {
+    if job.status != JobStatus::Queued {
+        return Err(format!("cannot enqueue job '{}' with status {:?}", job.uuid.hyphenated(), job.status));
+    }
+    return Ok(());
+}
+
+// whether an enqueued job should be held back in the delayed jobs rather than queued immediately
+pub fn is_delayed(job: &Job) -> bool {
+    return job.run_at.is_some() && job.run_at > job.enqueued_at;
+}
+
+// whether a deferred job handed its job type's unique lock should be held back in the delayed jobs until its `run_at`
+pub fn is_handed_off_delayed(job: &Job) -> bool {
+    return job.run_at.map(|run_at| run_at > epoch()).unwrap_or(false);
+}
+
+// jobs are queued for the node type named by their job type, regardless of which node enqueues them
+pub fn job_node_type_uuid(node_types: &[NodeType], job_type: &JobType) -> Result<Uuid, String> {
+    let node_type = node_types.iter().find(|node_type| node_type.name == job_type.node_type);
+    if node_type.is_none() {
+        return Err(format!("node type '{}' of job type '{}' does not exist", job_type.node_type, job_type.uuid.hyphenated()));
+    }
+    return Ok(node_type.unwrap().uuid);
+}
+
+// takes a token from the job type's rate limit bucket, returning None if one was taken (or the job type has no rate limit), or how long until one is available
+pub fn take_rate_limit_token(store: &mut StoreRef, job_type: &JobType) -> Result<Option<u64>, String> {
+    if job_type.rate_limit.is_none() {
+        return Ok(None);
+    }
+    let rate_limit = job_type.rate_limit.as_ref().unwrap();
+    loop {
+        let bucket = store.get_rate_limit_bucket(job_type.uuid)?;
+        let mut new_bucket = rate_limit.refill(bucket.as_ref(), epoch());
+        let wait_ms = rate_limit.wait_ms(&new_bucket);
+        if wait_ms > 0 {
+            return Ok(Some(wait_ms));
+        }
+        new_bucket.tokens -= 1.0;
+        if store.update_rate_limit_bucket(job_type.uuid, bucket.as_ref(), &new_bucket)? {
+            return Ok(None);
+        }
+        // another node took a token since we read the bucket, so try again
+    }
+}
+
 pub fn init_store_untyped() -> StoreRef {
     let store: StoreRef;
     if &*config::STORE_TYPE == "redis" {
@@ -58,6 +194,30 @@ pub fn init_store_untyped() -> StoreRef {
                 store = Box::new(redis_store);
             }
         }
+    } else if &*config::STORE_TYPE == "memory" {
+        store = Box::new(memory::MemoryStore::connect().unwrap());
+    } else if &*config::STORE_TYPE == "sqlite" {
+        let sqlite_connected = sqlite::SqliteStore::connect();
+        match sqlite_connected {
+            Err(e) => {
+                error!("Error connecting to sqlite: {}", e);
+                exit(1);
+            }
+            Ok(sqlite_store) => {
+                store = Box::new(sqlite_store);
+            }
+        }
+    } else if &*config::STORE_TYPE == "postgres" {
+        let postgres_connected = init_postgres_store();
+        match postgres_connected {
+            Err(e) => {
+                error!("Error connecting to postgres: {}", e);
+                exit(1);
+            }
+            Ok(postgres_store) => {
+                store = postgres_store;
+            }
+        }
     } else {
         error!("Invalid STORE_TYPE configuration option: {}", &*config::STORE_TYPE);
         exit(1);
@@ -65,6 +225,17 @@ pub fn init_store_untyped() -> StoreRef {
     return store;
 }
 
+#[cfg(feature = "postgres")]
+fn init_postgres_store() -> Result<StoreRef, String> {
+    let postgres_store = postgres::PostgresStore::connect()?;
+    return Ok(Box::new(postgres_store));
+}
+
+#[cfg(not(feature = "postgres"))]
+fn init_postgres_store() -> Result<StoreRef, String> {
+    return Err("postgres store support not compiled in, build with the `postgres` feature".to_string());
+}
+
 pub fn init_store() -> StoreRef {
     let mut store = init_store_untyped();
     let node_types = store.get_node_types();
@@ -94,12 +265,15 @@ pub fn init_store() -> StoreRef {
 pub mod tests {
     use super::*;
     use std::collections::HashMap;
+    use crate::util::time::epoch;
+    use crate::scheduler::MisfirePolicy;
 
     pub fn make_node_type(store: &mut StoreRef) -> Result<NodeType, String> {
         let test_node_type = NodeType {
             name: "test_node_type".to_string(),
             uuid: Uuid::new_v4(),
             thread_count: 1,
+            jobs_per_thread: None,
         };
         store.new_node_type(&test_node_type)?;
         return Ok(test_node_type);
@@ -109,9 +283,15 @@ pub mod tests {
         let mut test_job_type = JobType {
             executor: "bash".to_string(),
             name: "test".to_string(),
-            node_type: "default".to_string(),
+            node_type: "test_node_type".to_string(),
             timeout: None,
+            retry: None,
+            priority: Priority::Normal,
+            max_concurrency: None,
+            rate_limit: None,
+            output_limit: None,
             unique: false,
+            unique_policy: UniquePolicy::Reject,
             uuid: Uuid::new_v4(),
             metadata: HashMap::new(),
         };
@@ -123,15 +303,20 @@ pub mod tests {
     pub fn make_job(store: &mut StoreRef, job_type: &JobType) -> Result<Job, String> {
         let job = Job {
             uuid: Uuid::new_v4(),
+            status: JobStatus::Queued,
             job_type_uuid: job_type.uuid,
             job_type: Some(job_type.clone()),
             arguments: HashMap::new(),
+            priority: job_type.priority,
             executing_node: None,
             enqueued_at: None,
+            run_at: None,
             started_at: None,
             ended_at: None,
             results: None,
             errors: None,
+            attempt: 0,
+            progress: None,
         };
         store.enqueue_job(job.clone())?;
         return Ok(job);
@@ -141,6 +326,11 @@ pub mod tests {
         let mut test_schedule_item = ScheduleItem {
             uuid: Uuid::new_v4(),
             interval: 500,
+            cron: None,
+            timezone: None,
+            misfire_policy: MisfirePolicy::FireOnce,
+            misfire_limit: None,
+            enabled: true,
             last_scheduled_by: last_scheduled_by,
             last_scheduled_at: last_scheduled_at,
             job_type_uuid: job_type_uuid,
@@ -151,4 +341,574 @@ pub mod tests {
         return Ok(test_schedule_item);
     }
 
+    pub fn make_unique_job_type(store: &mut StoreRef, unique_policy: UniquePolicy) -> Result<JobType, String> {
+        let mut test_job_type = make_job_type(store)?;
+        test_job_type.unique = true;
+        test_job_type.unique_policy = unique_policy;
+        store.new_job_type(&test_job_type)?;
+        return Ok(test_job_type);
+    }
+
+    #[test]
+    fn can_reject_and_coalesce_unique_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let rejecting_job_type = make_unique_job_type(&mut store, UniquePolicy::Reject)?;
+        let coalescing_job_type = make_unique_job_type(&mut store, UniquePolicy::Coalesce)?;
+        let rejecting_job = make_job(&mut store, &rejecting_job_type)?;
+        let coalescing_job = make_job(&mut store, &coalescing_job_type)?;
+        let mut new_job = rejecting_job.clone();
+        new_job.uuid = Uuid::new_v4();
+        assert_eq!(store.enqueue_job(new_job.clone())?, EnqueueResult::Rejected(rejecting_job.uuid));
+        new_job.job_type_uuid = coalescing_job_type.uuid;
+        new_job.job_type = Some(coalescing_job_type.clone());
+        assert_eq!(store.enqueue_job(new_job.clone())?, EnqueueResult::Coalesced(coalescing_job.uuid));
+        assert_eq!(store.get_all_jobs_waiting()?.len(), 2);
+
+        // the lock is held while running, and released once finished
+        let running_job = store.dequeue_job()?;
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), status: JobStatus::Queued, ..running_job.clone() })?, EnqueueResult::Rejected(running_job.uuid));
+        store.finish_job(running_job.clone(), None, None)?;
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), status: JobStatus::Queued, ..running_job })?, EnqueueResult::Enqueued);
+        Ok(())
+    }
+
+    #[test]
+    fn can_defer_unique_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_unique_job_type(&mut store, UniquePolicy::Defer)?;
+        let first_job = make_job(&mut store, &test_job_type)?;
+        let second_job = Job { uuid: Uuid::new_v4(), ..first_job.clone() };
+        assert_eq!(store.enqueue_job(second_job.clone())?, EnqueueResult::Deferred);
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, first_job.uuid);
+
+        let running_job = store.dequeue_job()?;
+        store.finish_job(running_job, None, None)?;
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, second_job.uuid);
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), ..first_job })?, EnqueueResult::Deferred);
+        Ok(())
+    }
+
+    #[test]
+    fn can_store_job_logs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let job_uuid = Uuid::new_v4();
+        assert_eq!(store.get_job_logs(job_uuid)?, None);
+        store.close_job_logs(job_uuid)?;
+        assert_eq!(store.get_job_logs(job_uuid)?, None);
+        store.append_job_logs(job_uuid, "")?;
+        store.append_job_logs(job_uuid, "first\n")?;
+        store.append_job_logs(job_uuid, "second\n")?;
+        assert_eq!(store.get_job_logs(job_uuid)?, Some(JobLogs { output: "first\nsecond\n".to_string(), end: 13, closed: false }));
+        store.close_job_logs(job_uuid)?;
+        assert!(store.get_job_logs(job_uuid)?.unwrap().closed);
+        // a retried job's next attempt reopens them
+        store.append_job_logs(job_uuid, "third\n")?;
+        let logs = store.get_job_logs(job_uuid)?.unwrap();
+        assert!(!logs.closed);
+        assert_eq!(logs.since(13), "third\n");
+        Ok(())
+    }
+
+    #[test]
+    fn removes_job_logs_of_purged_dead_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let running_job = store.dequeue_job()?;
+        store.append_job_logs(test_job.uuid, "failing\n")?;
+        store.close_job_logs(test_job.uuid)?;
+        store.kill_job(running_job, None, Some(Value::String("failed".to_string())))?;
+        let other_job_uuid = Uuid::new_v4();
+        store.append_job_logs(other_job_uuid, "running\n")?;
+        assert!(store.get_job_logs(test_job.uuid)?.unwrap().closed);
+        assert_eq!(store.purge_dead_jobs(Some(test_job.uuid))?, 1);
+        assert_eq!(store.get_job_logs(test_job.uuid)?, None);
+        assert_eq!(store.get_job_logs(other_job_uuid)?.unwrap().output, "running\n");
+        Ok(())
+    }
+
+    #[test]
+    fn can_set_job_progress() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let progress = JobProgress { percent: Some(42.0), message: Some("processed 420/1000 rows".to_string()), updated_at: 1000 };
+        assert!(!store.set_job_progress(test_job.uuid, &progress)?);
+
+        let running_job = store.dequeue_job()?;
+        assert!(store.set_job_progress(test_job.uuid, &progress)?);
+        assert_eq!(store.get_all_jobs_in_progress()?[0].progress, Some(progress.clone()));
+        store.finish_job(running_job, None, None)?;
+        assert!(!store.set_job_progress(test_job.uuid, &progress)?);
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_try_dequeue_job() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        assert_eq!(store.try_dequeue_job()?, None);
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.max_concurrency = Some(1);
+        store.new_job_type(&test_job_type)?;
+        let first_job = make_job(&mut store, &test_job_type)?;
+        let second_job = make_job(&mut store, &test_job_type)?;
+
+        let running_job = store.try_dequeue_job()?.unwrap();
+        assert_eq!(running_job.uuid, first_job.uuid);
+        assert_eq!(running_job.status, JobStatus::Running);
+        assert_eq!(running_job.job_type, Some(test_job_type.clone()));
+        assert_eq!(store.get_all_jobs_in_progress()?.len(), 1);
+        // the second job is held back by the concurrency limit, rather than waited for
+        assert_eq!(store.try_dequeue_job()?, None);
+        store.finish_job(running_job, None, None)?;
+        assert_eq!(store.try_dequeue_job()?.unwrap().uuid, second_job.uuid);
+        Ok(())
+    }
+
+
+    #[test]
+    fn can_update_schedule_items() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_schedule_item = make_schedule_item(&mut store, test_job_type.uuid, None, None)?;
+        let paused_schedule_item = ScheduleItem { enabled: false, ..test_schedule_item.clone() };
+        assert!(store.update_job_schedule_item(&test_schedule_item, &paused_schedule_item)?);
+        // the schedule item no longer matches, i.e. it was claimed in the meantime
+        assert!(!store.update_job_schedule_item(&test_schedule_item, &paused_schedule_item)?);
+        assert_eq!(store.get_job_schedule_item(test_schedule_item.uuid)?, Some(paused_schedule_item));
+        Ok(())
+    }
+
+    #[test]
+    fn can_retry_and_kill_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        let running_job = store.dequeue_job()?;
+        store.retry_job(running_job, None, Some(Value::Null), epoch() + 60000)?;
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![]);
+        assert_eq!(store.promote_delayed_jobs()?, 0);
+        let delayed_jobs = store.get_all_jobs_delayed()?;
+        assert_eq!(delayed_jobs.len(), 1);
+        assert_eq!(delayed_jobs[0].attempt, 1);
+
+        let other_job = make_job(&mut store, &test_job_type)?;
+        let running_job = store.dequeue_job()?;
+        assert_eq!(running_job.uuid, other_job.uuid);
+        store.retry_job(running_job, None, Some(Value::Null), 0)?;
+        assert_eq!(store.promote_delayed_jobs()?, 1);
+        assert_eq!(store.get_all_jobs_delayed()?.len(), 1);
+
+        let running_job = store.dequeue_job()?;
+        assert_eq!(running_job.uuid, other_job.uuid);
+        assert_eq!(running_job.attempt, 1);
+        store.kill_job(running_job, None, Some(Value::String("failed".to_string())))?;
+        assert_eq!(store.get_all_jobs_finished()?, vec![]);
+        let dead_jobs = store.get_all_jobs_dead()?;
+        assert_eq!(dead_jobs.len(), 1);
+        assert_eq!(dead_jobs[0].uuid, other_job.uuid);
+        assert_eq!(store.get_dead_job(other_job.uuid)?.unwrap().errors, Some(Value::String("failed".to_string())));
+        assert_eq!(store.get_dead_job(test_job.uuid)?, None);
+        assert_eq!(store.purge_dead_jobs(Some(test_job.uuid))?, 0);
+        assert_eq!(store.purge_dead_jobs(None)?, 1);
+        assert_eq!(store.get_all_jobs_dead()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_unique_lock_while_retrying() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_unique_job_type(&mut store, UniquePolicy::Reject)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let running_job = store.dequeue_job()?;
+        store.retry_job(running_job, None, Some(Value::Null), 0)?;
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), ..test_job.clone() })?, EnqueueResult::Rejected(test_job.uuid));
+        store.promote_delayed_jobs()?;
+        let running_job = store.dequeue_job()?;
+        store.kill_job(running_job, None, Some(Value::Null))?;
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), ..test_job })?, EnqueueResult::Enqueued);
+        Ok(())
+    }
+
+    #[test]
+    fn can_delay_and_cancel_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        store.dequeue_job()?;
+
+        let delayed_job = Job { uuid: Uuid::new_v4(), run_at: Some(epoch() + 60000), ..test_job.clone() };
+        assert_eq!(store.enqueue_job(delayed_job.clone())?, EnqueueResult::Delayed);
+        let due_job = Job { uuid: Uuid::new_v4(), run_at: Some(epoch() + 100), ..test_job.clone() };
+        assert_eq!(store.enqueue_job(due_job.clone())?, EnqueueResult::Delayed);
+        // a run_at in the past is queued immediately
+        let past_job = Job { uuid: Uuid::new_v4(), run_at: Some(1), ..test_job.clone() };
+        assert_eq!(store.enqueue_job(past_job.clone())?, EnqueueResult::Enqueued);
+        assert_eq!(store.dequeue_job()?.uuid, past_job.uuid);
+        assert_eq!(store.get_all_jobs_delayed()?.len(), 2);
+        assert_eq!(store.get_all_jobs_delayed()?[0].status, JobStatus::Scheduled);
+
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(store.promote_delayed_jobs()?, 1);
+        assert_eq!(store.dequeue_job()?.uuid, due_job.uuid);
+        assert_eq!(store.cancel_delayed_job(due_job.uuid)?, None);
+        assert_eq!(store.cancel_delayed_job(delayed_job.uuid)?.unwrap().uuid, delayed_job.uuid);
+        assert_eq!(store.get_all_jobs_delayed()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn releases_unique_lock_when_cancelling_delayed_job() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_unique_job_type(&mut store, UniquePolicy::Reject)?;
+        let test_job = Job {
+            uuid: Uuid::new_v4(),
+            status: JobStatus::Queued,
+            job_type_uuid: test_job_type.uuid,
+            job_type: Some(test_job_type.clone()),
+            arguments: HashMap::new(),
+            priority: test_job_type.priority,
+            executing_node: None,
+            enqueued_at: None,
+            run_at: Some(epoch() + 60000),
+            started_at: None,
+            ended_at: None,
+            results: None,
+            errors: None,
+            attempt: 0,
+            progress: None,
+        };
+        assert_eq!(store.enqueue_job(test_job.clone())?, EnqueueResult::Delayed);
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), run_at: None, ..test_job.clone() })?, EnqueueResult::Rejected(test_job.uuid));
+        assert!(store.cancel_delayed_job(test_job.uuid)?.is_some());
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), run_at: None, ..test_job })?, EnqueueResult::Enqueued);
+        Ok(())
+    }
+
+    #[test]
+    fn can_dequeue_by_priority() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut job_types: Vec<JobType> = vec![];
+        for priority in Priority::ALL.iter() {
+            let mut job_type = make_job_type(&mut store)?;
+            job_type.priority = *priority;
+            store.new_job_type(&job_type)?;
+            job_types.push(job_type);
+        }
+        let (high, normal, low) = (&job_types[0], &job_types[1], &job_types[2]);
+        let mut jobs: Vec<Job> = vec![];
+        for job_type in [low, normal, high, low, normal, high].iter() {
+            jobs.push(make_job(&mut store, job_type)?);
+        }
+        let waiting_uuids: Vec<Uuid> = store.get_all_jobs_waiting()?.iter().map(|job| job.uuid).collect();
+        assert_eq!(waiting_uuids, vec![jobs[2].uuid, jobs[5].uuid, jobs[1].uuid, jobs[4].uuid, jobs[0].uuid, jobs[3].uuid]);
+
+        // high priority is preferred most often, falling back to the highest priority queued
+        let dequeued_uuids: Vec<Uuid> = (0..6).map(|_| store.dequeue_job().unwrap().uuid).collect();
+        assert_eq!(dequeued_uuids, vec![jobs[2].uuid, jobs[1].uuid, jobs[5].uuid, jobs[4].uuid, jobs[0].uuid, jobs[3].uuid]);
+        Ok(())
+    }
+
+    #[test]
+    fn doesnt_starve_low_priority_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut high_job_type = make_job_type(&mut store)?;
+        high_job_type.priority = Priority::High;
+        store.new_job_type(&high_job_type)?;
+        let mut low_job_type = make_job_type(&mut store)?;
+        low_job_type.priority = Priority::Low;
+        store.new_job_type(&low_job_type)?;
+        let low_job = make_job(&mut store, &low_job_type)?;
+        for _ in 0..10 {
+            make_job(&mut store, &high_job_type)?;
+        }
+        let dequeued_uuids: Vec<Uuid> = (0..10).map(|_| store.dequeue_job().unwrap().uuid).collect();
+        assert!(dequeued_uuids.contains(&low_job.uuid));
+        Ok(())
+    }
+
+    #[test]
+    fn can_limit_concurrency() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut limited_job_type = make_job_type(&mut store)?;
+        limited_job_type.max_concurrency = Some(2);
+        store.new_job_type(&limited_job_type)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let mut limited_jobs: Vec<Job> = vec![];
+        for _ in 0..4 {
+            limited_jobs.push(make_job(&mut store, &limited_job_type)?);
+        }
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        let first_job = store.dequeue_job()?;
+        assert_eq!(first_job.uuid, limited_jobs[0].uuid);
+        assert_eq!(store.dequeue_job()?.uuid, limited_jobs[1].uuid);
+        // the other limited jobs are held back rather than dropped
+        assert_eq!(store.dequeue_job()?.uuid, test_job.uuid);
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+        assert_eq!(store.cancel_job(limited_jobs[3].uuid)?, CancelResult::Cancelled);
+
+        store.finish_job(first_job, None, None)?;
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, limited_jobs[2].uuid);
+        assert_eq!(waiting_jobs[0].status, JobStatus::Queued);
+        assert_eq!(store.dequeue_job()?.uuid, limited_jobs[2].uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn requeues_throttled_jobs_when_limit_raised() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut limited_job_type = make_job_type(&mut store)?;
+        limited_job_type.max_concurrency = Some(1);
+        store.new_job_type(&limited_job_type)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let first_job = make_job(&mut store, &limited_job_type)?;
+        let second_job = make_job(&mut store, &limited_job_type)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        assert_eq!(store.dequeue_job()?.uuid, first_job.uuid);
+        assert_eq!(store.dequeue_job()?.uuid, test_job.uuid);
+        assert_eq!(store.promote_delayed_jobs()?, 0);
+        limited_job_type.max_concurrency = Some(2);
+        store.new_job_type(&limited_job_type)?;
+        assert_eq!(store.promote_delayed_jobs()?, 1);
+        assert_eq!(store.dequeue_job()?.uuid, second_job.uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn can_track_job_status() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_unique_job_type(&mut store, UniquePolicy::Defer)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let deferred_job = Job { uuid: Uuid::new_v4(), ..test_job.clone() };
+        store.enqueue_job(deferred_job.clone())?;
+        assert_eq!(store.get_all_jobs_waiting()?[0].status, JobStatus::Queued);
+        assert!(store.enqueue_job(Job { uuid: Uuid::new_v4(), status: JobStatus::Succeeded, ..test_job.clone() }).is_err());
+
+        // finishing requires the job to be running
+        assert!(store.finish_job(test_job.clone(), None, None).is_err());
+        let running_job = store.dequeue_job()?;
+        assert_eq!(running_job.status, JobStatus::Running);
+        assert_eq!(store.get_all_jobs_in_progress()?[0].status, JobStatus::Running);
+        store.retry_job(running_job, None, Some(Value::String("errors".to_string())), 0)?;
+        assert_eq!(store.get_all_jobs_delayed()?[0].status, JobStatus::Retrying);
+        store.promote_delayed_jobs()?;
+        assert_eq!(store.get_all_jobs_waiting()?[0].status, JobStatus::Queued);
+        let running_job = store.dequeue_job()?;
+        store.kill_job(running_job.clone(), None, Some(Value::String("errors".to_string())))?;
+        let dead_job = store.get_dead_job(running_job.uuid)?.unwrap();
+        assert_eq!(dead_job.status, JobStatus::Dead);
+        assert!(store.finish_job(dead_job, None, None).is_err());
+
+        // the deferred job was handed the unique lock
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs[0].uuid, deferred_job.uuid);
+        assert_eq!(waiting_jobs[0].status, JobStatus::Queued);
+        let running_job = store.dequeue_job()?;
+        store.finish_job(running_job.clone(), Some(json!({ "exit_code": 1 })), None)?;
+        assert_eq!(store.get_finished_job(running_job.uuid)?.unwrap().status, JobStatus::Failed);
+        Ok(())
+    }
+
+    #[test]
+    fn can_cancel_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let running_job = make_job(&mut store, &test_job_type)?;
+        let waiting_job = make_job(&mut store, &test_job_type)?;
+        let delayed_job = Job { uuid: Uuid::new_v4(), run_at: Some(epoch() + 60000), ..waiting_job.clone() };
+        store.enqueue_job(delayed_job.clone())?;
+        let running_job = Job { job_type: running_job.job_type.clone(), ..store.dequeue_job()? };
+
+        assert_eq!(store.cancel_job(waiting_job.uuid)?, CancelResult::Cancelled);
+        assert_eq!(store.cancel_job(delayed_job.uuid)?, CancelResult::Cancelled);
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+        assert_eq!(store.get_all_jobs_delayed()?, vec![]);
+        let cancelled_job = store.get_finished_job(waiting_job.uuid)?.unwrap();
+        assert_eq!(cancelled_job.errors, Some(cancelled_errors()));
+        assert_eq!(cancelled_job.status, JobStatus::Cancelled);
+        assert!(cancelled_job.ended_at.is_some());
+        assert_eq!(store.cancel_job(waiting_job.uuid)?, CancelResult::NotFound);
+        assert_eq!(store.cancel_job(Uuid::new_v4())?, CancelResult::NotFound);
+
+        assert!(!store.is_job_cancelled(running_job.uuid)?);
+        assert_eq!(store.cancel_job(running_job.uuid)?, CancelResult::Cancelling);
+        assert!(store.is_job_cancelled(running_job.uuid)?);
+        store.finish_job(running_job.clone(), None, Some(cancelled_errors()))?;
+        assert_eq!(store.cancel_job(running_job.uuid)?, CancelResult::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn clears_cancellation_of_retried_job() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let running_job = Job { job_type: test_job.job_type.clone(), ..store.dequeue_job()? };
+
+        assert_eq!(store.cancel_job(running_job.uuid)?, CancelResult::Cancelling);
+        store.retry_job(running_job.clone(), None, None, epoch() + 60000)?;
+        assert!(!store.is_job_cancelled(running_job.uuid)?);
+        assert_eq!(store.cancel_job(running_job.uuid)?, CancelResult::Cancelled);
+        Ok(())
+    }
+
+    #[test]
+    fn delays_deferred_job_handed_unique_lock_until_run_at() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_unique_job_type(&mut store, UniquePolicy::Defer)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let deferred_job = Job { uuid: Uuid::new_v4(), run_at: Some(epoch() + 60000), ..test_job.clone() };
+        assert_eq!(store.enqueue_job(deferred_job.clone())?, EnqueueResult::Deferred);
+
+        let running_job = store.dequeue_job()?;
+        store.finish_job(running_job, None, None)?;
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+        let delayed_jobs = store.get_all_jobs_delayed()?;
+        assert_eq!(delayed_jobs.len(), 1);
+        assert_eq!(delayed_jobs[0].uuid, deferred_job.uuid);
+        assert_eq!(delayed_jobs[0].status, JobStatus::Scheduled);
+        assert_eq!(store.promote_delayed_jobs()?, 0);
+
+        // the delayed job holds the lock until it runs
+        assert_eq!(store.enqueue_job(Job { uuid: Uuid::new_v4(), ..test_job.clone() })?, EnqueueResult::Deferred);
+        Ok(())
+    }
+
+    #[test]
+    fn releases_unique_lock_when_cancelling_job() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_unique_job_type(&mut store, UniquePolicy::Defer)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        let deferred_job = Job { uuid: Uuid::new_v4(), ..test_job.clone() };
+        assert_eq!(store.enqueue_job(deferred_job.clone())?, EnqueueResult::Deferred);
+        let other_deferred_job = Job { uuid: Uuid::new_v4(), ..test_job.clone() };
+        assert_eq!(store.enqueue_job(other_deferred_job.clone())?, EnqueueResult::Deferred);
+
+        // cancelling a deferred job leaves the lock alone, cancelling the holder hands it over
+        assert_eq!(store.cancel_job(deferred_job.uuid)?, CancelResult::Cancelled);
+        assert_eq!(store.cancel_job(test_job.uuid)?, CancelResult::Cancelled);
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, other_deferred_job.uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn can_route_jobs_by_job_type() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let other_node_type = NodeType { name: "other_node_type".to_string(), uuid: Uuid::new_v4(), thread_count: 1, jobs_per_thread: None };
+        store.new_node_type(&other_node_type)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.node_type = other_node_type.name.clone();
+        store.new_job_type(&test_job_type)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        assert_eq!(store.get_all_jobs_waiting()?, vec![]);
+        store.set_node_type_soft(other_node_type.uuid)?;
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, test_job.uuid);
+
+        test_job_type.node_type = "missing_node_type".to_string();
+        assert!(store.enqueue_job(Job { uuid: Uuid::new_v4(), job_type: Some(test_job_type), ..test_job }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn can_reroute_jobs_waiting() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let other_node_type = NodeType { name: "other_node_type".to_string(), uuid: Uuid::new_v4(), thread_count: 1, jobs_per_thread: None };
+        store.new_node_type(&other_node_type)?;
+        let mut moving_job_type = make_job_type(&mut store)?;
+        let staying_job_type = make_job_type(&mut store)?;
+        let moving_job = make_job(&mut store, &moving_job_type)?;
+        let staying_job = make_job(&mut store, &staying_job_type)?;
+        assert_eq!(store.reroute_jobs_waiting()?, 0);
+
+        // stands in for a job enqueued onto the wrong queue before enqueue routed by job type
+        moving_job_type.node_type = other_node_type.name.clone();
+        store.new_job_type(&moving_job_type)?;
+        assert_eq!(store.reroute_jobs_waiting()?, 1);
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, staying_job.uuid);
+        store.set_node_type_soft(other_node_type.uuid)?;
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, moving_job.uuid);
+        Ok(())
+    }
 }
\ No newline at end of file
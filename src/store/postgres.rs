@@ -0,0 +1,105 @@
+use crate::store::sql::*;
+use crate::util::config;
+use ::postgres::{ Client, NoTls };
+use ::postgres::types::{ ToSql, Type };
+
+// the advisory lock taken while migrating the schema, "synchron" as a big-endian integer
+const SCHEMA_LOCK_KEY: i64 = 0x73796e6368726f6e;
+
+pub struct PostgresConnection {
+    url: String,
+    client: Client,
+}
+
+impl PostgresConnection {
+    pub fn open_url(url: &str) -> Result<PostgresConnection, String> {
+        let client = Client::connect(url, NoTls);
+        if client.is_err() {
+            return Err(format!("{:?}", client.err().unwrap()));
+        }
+        return Ok(PostgresConnection { url: url.to_string(), client: client.unwrap() });
+    }
+
+    // nulls need to be typed to match the column they are bound to
+    fn bind(param_types: &[Type], params: &[SqlValue]) -> Vec<Box<dyn ToSql + Sync>> {
+        return params.iter().zip(param_types.iter()).map(|(param, param_type)| -> Box<dyn ToSql + Sync> {
+            match param {
+                SqlValue::Null if *param_type == Type::INT8 => Box::new(None::<i64>),
+                SqlValue::Null => Box::new(None::<String>),
+                SqlValue::Integer(i) => Box::new(*i),
+                SqlValue::Text(s) => Box::new(s.clone()),
+            }
+        }).collect();
+    }
+}
+
+impl SqlConnection for PostgresConnection {
+    fn open() -> Result<PostgresConnection, String> {
+        return PostgresConnection::open_url(&*config::POSTGRES_URL);
+    }
+
+    fn reopen(&self) -> Result<PostgresConnection, String> {
+        return PostgresConnection::open_url(&*self.url);
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        return self.client.batch_execute("BEGIN").map_err(|e| format!("{:?}", e));
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        return self.client.batch_execute("COMMIT").map_err(|e| format!("{:?}", e));
+    }
+
+    fn rollback(&mut self) -> Result<(), String> {
+        return self.client.batch_execute("ROLLBACK").map_err(|e| format!("{:?}", e));
+    }
+
+    fn skip_locked(&self) -> &'static str {
+        return " FOR UPDATE SKIP LOCKED";
+    }
+
+    fn lock_schema(&mut self) -> Result<(), String> {
+        return self.client.batch_execute(&*format!("SELECT pg_advisory_xact_lock({})", SCHEMA_LOCK_KEY)).map_err(|e| format!("{:?}", e));
+    }
+
+    fn execute(&mut self, query: &str, params: &[SqlValue]) -> Result<u64, String> {
+        let statement = self.client.prepare(query);
+        if statement.is_err() {
+            return Err(format!("{:?}", statement.err().unwrap()));
+        }
+        let statement = statement.unwrap();
+        let bound = PostgresConnection::bind(statement.params(), params);
+        let bound_refs: Vec<&(dyn ToSql + Sync)> = bound.iter().map(|param| &**param).collect();
+        return self.client.execute(&statement, &*bound_refs).map_err(|e| format!("{:?}", e));
+    }
+
+    fn query(&mut self, query: &str, params: &[SqlValue]) -> Result<Vec<Vec<SqlValue>>, String> {
+        let statement = self.client.prepare(query);
+        if statement.is_err() {
+            return Err(format!("{:?}", statement.err().unwrap()));
+        }
+        let statement = statement.unwrap();
+        let bound = PostgresConnection::bind(statement.params(), params);
+        let bound_refs: Vec<&(dyn ToSql + Sync)> = bound.iter().map(|param| &**param).collect();
+        let rows = self.client.query(&statement, &*bound_refs);
+        if rows.is_err() {
+            return Err(format!("{:?}", rows.err().unwrap()));
+        }
+        let mut output: Vec<Vec<SqlValue>> = vec![];
+        for row in rows.unwrap() {
+            let mut values: Vec<SqlValue> = vec![];
+            for (i, column) in row.columns().iter().enumerate() {
+                let value: SqlValue = if *column.type_() == Type::INT8 {
+                    row.get::<_, Option<i64>>(i).map(SqlValue::Integer).unwrap_or(SqlValue::Null)
+                } else {
+                    row.get::<_, Option<String>>(i).into()
+                };
+                values.push(value);
+            }
+            output.push(values);
+        }
+        return Ok(output);
+    }
+}
+
+pub type PostgresStore = SqlStore<PostgresConnection>;
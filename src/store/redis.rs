@@ -7,12 +7,141 @@ use serde_json::Value;
 use crate::util::time::epoch;
 use std::collections::HashMap;
 
+// seconds an idle dequeue blocks on the normal priority queue before checking the others again
+const DEQUEUE_BLOCK_SECONDS: u64 = 1;
+
+// prepended to scripts that move serialized jobs onto a queue. Jobs are moved between queues as they are, as re-encoding them
+// in Lua would lose precision in large numbers in their arguments, so the status of a job read from a queue is set by the queue
+const QUEUE_LUA: &str = "
+    -- a node type's queues by priority, passed as keys from `first` in the order of `waiting_keys`
+    local function queues_at(first)
+        return { high = KEYS[first], normal = KEYS[first + 1], low = KEYS[first + 2] };
+    end
+    -- the queue for the item's priority, of the given queues
+    local function waiting_key(queues, item)
+        return queues[cjson.decode(item).priority or 'normal'] or queues.normal;
+    end
+    local function push_queued(queues, item)
+        redis.call('rpush', waiting_key(queues, item), item);
+    end
+    -- queues a deferred job handed its job type's unique lock, or delays it if its run_at is still to come
+    local function push_handed_off(queues, delayed_key, item, now)
+        local run_at = cjson.decode(item).run_at;
+        if type(run_at) == 'number' and run_at > tonumber(now) then
+            redis.call('zadd', delayed_key, run_at, item);
+        else
+            push_queued(queues, item);
+        end
+    end
+";
+
+// prepended to scripts that mark running jobs as cancelled. A job is claimed from when a node moves it to its processing list,
+// as its node checks for the mark before starting it, until it is finished. Scripts pass the processing lists as their last keys
+const CLAIMED_LUA: &str = "
+    local function is_claimed(in_progress_key, first_processing_key, uuid)
+        if redis.call('hexists', in_progress_key, uuid) == 1 then
+            return true;
+        end
+        for i = first_processing_key, #KEYS do
+            for _, item in ipairs(redis.call('lrange', KEYS[i], 0, -1)) do
+                if cjson.decode(item).uuid == uuid then
+                    return true;
+                end
+            end
+        end
+        return false;
+    end
+";
+
+// prepended (after `QUEUE_LUA`) to scripts that take or release concurrency leases, held in `concurrency_leases_<job_type_uuid>` hashes of job UUID to node UUID.
+// scripts call `load_leases` with where the keys and arguments from `RedisStore::concurrency_leases` follow their own
+const LEASE_LUA: &str = "
+    local leases = {};
+    local function load_leases(first_key, first_arg)
+        for i = first_key, #KEYS do
+            local arg = first_arg + (i - first_key) * 2;
+            leases[ARGV[arg]] = { key = KEYS[i], max_concurrency = tonumber(ARGV[arg + 1]) };
+        end
+    end
+    -- how many more of the job type's jobs may run, or nil if it has no max_concurrency
+    local function concurrency_room(job_type_uuid)
+        local lease = leases[job_type_uuid];
+        if not lease or not lease.max_concurrency then
+            return nil;
+        end
+        return lease.max_concurrency - redis.call('hlen', lease.key);
+    end
+    -- takes a concurrency lease for the item, or holds it back on the throttled list if its job type is at its limit
+    local function try_lease(item, throttled_key, node_uuid)
+        local job = cjson.decode(item);
+        local room = concurrency_room(job.job_type_uuid);
+        if room == nil then
+            return true;
+        end
+        if room <= 0 then
+            redis.call('rpush', throttled_key, item);
+            return false;
+        end
+        redis.call('hset', leases[job.job_type_uuid].key, job.uuid, node_uuid);
+        return true;
+    end
+    -- moves throttled items (of the given job type, or all) back to the front of the queue, as far as their job types have room for them
+    local function requeue_throttled(throttled_key, queues, job_type_uuid)
+        local rooms = {};
+        local requeued = {};
+        for _, item in ipairs(redis.call('lrange', throttled_key, 0, -1)) do
+            local item_job_type_uuid = cjson.decode(item).job_type_uuid;
+            if not job_type_uuid or item_job_type_uuid == job_type_uuid then
+                if rooms[item_job_type_uuid] == nil then
+                    rooms[item_job_type_uuid] = concurrency_room(item_job_type_uuid) or math.huge;
+                end
+                if rooms[item_job_type_uuid] > 0 then
+                    rooms[item_job_type_uuid] = rooms[item_job_type_uuid] - 1;
+                    table.insert(requeued, item);
+                end
+            end
+        end
+        for i = #requeued, 1, -1 do
+            redis.call('lrem', throttled_key, 1, requeued[i]);
+            redis.call('lpush', waiting_key(queues, requeued[i]), requeued[i]);
+        end
+        return #requeued;
+    end
+    -- releases the job's concurrency lease, if it holds one, queueing the first job held back by it
+    local function release_lease(job_uuid, job_type_uuid, throttled_key, queues)
+        local lease = leases[job_type_uuid];
+        if lease and redis.call('hdel', lease.key, job_uuid) > 0 then
+            requeue_throttled(throttled_key, queues, job_type_uuid);
+        end
+    end
+";
+
+// the queue of the given priority, normal priority using the queue from before priorities existed
+fn waiting_key(node_type_uuid: &str, priority: Priority) -> String {
+    if priority == Priority::Normal {
+        return format!("jobs_waiting_{}", node_type_uuid);
+    }
+    return format!("jobs_waiting_{}_{}", node_type_uuid, priority.as_str());
+}
+
+// the queues of every priority of a node type, highest first, as `queues_at` expects them
+fn waiting_keys(node_type_uuid: &str) -> Vec<String> {
+    return Priority::ALL.iter().map(|priority| waiting_key(node_type_uuid, *priority)).collect();
+}
+
+// a job's logs: its retained output, appended to as a string, and a hash of its `end` offset and whether it is `closed`
+fn job_logs_keys(job_uuid: Uuid) -> Vec<String> {
+    let job_uuid = job_uuid.hyphenated().to_string();
+    return vec![format!("job_logs_{}", job_uuid), format!("job_logs_state_{}", job_uuid)];
+}
+
 pub struct RedisStore {
     client: Client,
     connection: Connection,
     node: Node,
     ping_interval: u32,
     job_types: HashMap<Uuid, JobType>,
+    dequeue_count: u64, // position in the weighted priority cycle
 }
 
 fn redis_hcheck_set(connection: &mut Connection, key: String, hkey: String, old_value: Option<String>, new_value: Option<String>) -> Result<bool, String> {
@@ -55,7 +184,7 @@ impl Store for RedisStore {
         if redis_result.is_err() {
             return Err(format!("{:?}", redis_result.err().unwrap()));
         }
-        return Ok(RedisStore { client: client.unwrap(), connection: connection.unwrap(), node: new_node, ping_interval: 5000, job_types: HashMap::new() });
+        return Ok(RedisStore { client: client.unwrap(), connection: connection.unwrap(), node: new_node, ping_interval: 5000, job_types: HashMap::new(), dequeue_count: 0 });
     }
 
     fn get_node_types(&mut self) -> Result<Vec<NodeType>, String> {
@@ -239,11 +368,15 @@ impl Store for RedisStore {
         return Ok(());
     }
     
+    fn update_job_schedule_item(&mut self, old_schedule_item: &ScheduleItem, new_schedule_item: &ScheduleItem) -> Result<bool, String> {
+        return redis_hcheck_set(&mut self.connection, "schedule_items".to_string(), old_schedule_item.uuid.hyphenated().to_string(), Some(serde_json::to_string(old_schedule_item).unwrap()), Some(serde_json::to_string(new_schedule_item).unwrap()));
+    }
+
     fn claim_job_scheduled(&mut self, schedule_item: &ScheduleItem) -> Result<Option<ScheduleItem>, String> {
         let mut new_schedule_item = schedule_item.clone();
         new_schedule_item.last_scheduled_by = Some(self.node.uuid);
         new_schedule_item.last_scheduled_at = Some(epoch());
-        let updated = redis_hcheck_set(&mut self.connection, "schedule_items".to_string(), schedule_item.uuid.hyphenated().to_string(), Some(serde_json::to_string(schedule_item).unwrap()), Some(serde_json::to_string(&new_schedule_item).unwrap()))?;
+        let updated = self.update_job_schedule_item(schedule_item, &new_schedule_item)?;
         if updated {
             return Ok(Some(new_schedule_item)); // we claimed it
         } else {
@@ -251,60 +384,342 @@ impl Store for RedisStore {
         }
     }
     
-    fn enqueue_job(&mut self, mut job: Job) -> Result<(), String> {
+    fn enqueue_job(&mut self, mut job: Job) -> Result<EnqueueResult, String> {
+        check_new_job(&job)?;
         job.enqueued_at = Some(epoch());
-        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
-        let redis_result: Result<u32, ::redis::RedisError> = self.connection.rpush(format!("jobs_waiting_{}", node_type_uuid), serde_json::to_string(&job).unwrap());
+        let job_type = match job.job_type.as_ref() {
+            Some(job_type) => job_type.clone(),
+            None => self.get_cached_job_type(job.job_type_uuid)?,
+        };
+        let node_types = self.get_node_types()?;
+        let node_type_uuid = job_node_type_uuid(&node_types, &job_type)?.hyphenated().to_string();
+        let delayed = is_delayed(&job);
+        if delayed {
+            job.transition(JobStatus::Scheduled)?;
+        }
+        if !job_type.unique {
+            let redis_result: Result<u32, ::redis::RedisError> = if delayed {
+                self.connection.zadd(format!("jobs_delayed_{}", node_type_uuid), serde_json::to_string(&job).unwrap(), job.run_at.unwrap())
+            } else {
+                self.connection.rpush(waiting_key(&*node_type_uuid, job.priority), serde_json::to_string(&job).unwrap())
+            };
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            return Ok(if delayed { EnqueueResult::Delayed } else { EnqueueResult::Enqueued });
+        }
+        let lock = UniqueLock { job_uuid: job.uuid, node_uuid: None };
+        let redis_result: Result<String, ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+            local existing = redis.call('hget', KEYS[1], ARGV[1]);
+            if not existing then
+                redis.call('hset', KEYS[1], ARGV[1], ARGV[2]);
+                if ARGV[5] ~= '' then
+                    redis.call('zadd', KEYS[4], ARGV[5], ARGV[3]);
+                    return 'delayed';
+                end
+                redis.call('rpush', KEYS[2], ARGV[3]);
+                return 'enqueued';
+            end
+            if ARGV[4] == 'defer' then
+                redis.call('rpush', KEYS[3], ARGV[6]);
+                return 'deferred';
+            end
+            return cjson.decode(existing).job_uuid;
+        ").arg(4)
+            .arg("unique_locks")
+            .arg(waiting_key(&*node_type_uuid, job.priority))
+            .arg(format!("jobs_deferred_{}", job_type.uuid.hyphenated()))
+            .arg(format!("jobs_delayed_{}", node_type_uuid))
+            .arg(job_type.uuid.hyphenated().to_string())
+            .arg(serde_json::to_string(&lock).unwrap())
+            .arg(serde_json::to_string(&job).unwrap())
+            .arg(serde_json::to_value(job_type.unique_policy).unwrap().as_str().unwrap())
+            .arg(if delayed { job.run_at.unwrap().to_string() } else { "".to_string() })
+            .arg(serde_json::to_string(&Job { status: JobStatus::Scheduled, ..job.clone() }).unwrap())
+            .query(&mut self.connection);
         if redis_result.is_err() {
             return Err(format!("{:?}", redis_result.err().unwrap()));
         }
-        return Ok(());
+        return match &*redis_result.unwrap() {
+            "enqueued" => Ok(EnqueueResult::Enqueued),
+            "delayed" => Ok(EnqueueResult::Delayed),
+            "deferred" => Ok(EnqueueResult::Deferred),
+            existing => {
+                let existing_uuid = Uuid::parse_str(existing);
+                if existing_uuid.is_err() {
+                    return Err(format!("redis consistency error: invalid unique lock for job type '{}': '{}'", job_type.uuid.hyphenated(), existing));
+                }
+                Ok(EnqueueResult::unique_conflict(job_type.unique_policy, existing_uuid.unwrap()))
+            },
+        };
     }
     
     fn dequeue_job(&mut self) -> Result<Job, String> {
         let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
-        let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.blpop(format!("jobs_waiting_{}", node_type_uuid), 0);
-        if redis_result.is_err() {
-            return Err(format!("{:?}", redis_result.err().unwrap()));
+        let processing_key = format!("jobs_processing_{}", self.node.uuid.hyphenated());
+        let throttled_key = format!("jobs_throttled_{}", node_type_uuid);
+        let node_uuid = self.node.uuid.hyphenated().to_string();
+        let order = Priority::dequeue_order(self.dequeue_count);
+        self.dequeue_count += 1;
+        let raw_job = loop {
+            let taken = self.take_waiting_job(&order)?;
+            if taken.is_some() {
+                break taken.unwrap();
+            }
+            // nothing is queued, so wait for a normal priority job, checking the other priorities again every so often
+            let redis_result: Result<Option<String>, ::redis::RedisError> = ::redis::cmd("BLMOVE")
+                .arg(waiting_key(&*node_type_uuid, Priority::Normal))
+                .arg(&*processing_key)
+                .arg("LEFT")
+                .arg("RIGHT")
+                .arg(DEQUEUE_BLOCK_SECONDS)
+                .query(&mut self.connection);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            if redis_result.as_ref().unwrap().is_none() {
+                continue;
+            }
+            // the blocking move can't check the job's concurrency limit, so check it now, holding the job back if needed
+            let raw_job = redis_result.unwrap().unwrap();
+            let leases = self.concurrency_leases()?;
+            let mut command = ::redis::cmd("EVAL");
+            let builder = command.arg([QUEUE_LUA, LEASE_LUA, "
+                load_leases(3, 3);
+                if try_lease(ARGV[1], KEYS[2], ARGV[2]) then
+                    return 1;
+                end
+                redis.call('lrem', KEYS[1], 1, ARGV[1]);
+                return 0;
+            "].concat()).arg(2 + leases.len())
+                .arg(&*processing_key)
+                .arg(&*throttled_key);
+            for (key, _, _) in leases.iter() {
+                builder.arg(key);
+            }
+            builder.arg(&*raw_job).arg(&*node_uuid);
+            for (_, job_type_uuid, max_concurrency) in leases.iter() {
+                builder.arg(job_type_uuid).arg(max_concurrency);
+            }
+            let redis_result: Result<u64, ::redis::RedisError> = builder.query(&mut self.connection);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            if redis_result.unwrap() == 1 {
+                break raw_job;
+            }
+        };
+        return self.start_job(raw_job);
+    }
+
+    fn try_dequeue_job(&mut self) -> Result<Option<Job>, String> {
+        let order = Priority::dequeue_order(self.dequeue_count);
+        let taken = self.take_waiting_job(&order)?;
+        if taken.is_none() {
+            return Ok(None);
         }
-        let raw_job: Result<Job, serde_json::Error> = serde_json::from_str(&*(redis_result.unwrap())[1]);
-        if raw_job.is_err() {
-            return Err(format!("{:?}", raw_job.err().unwrap()));
+        self.dequeue_count += 1;
+        return self.start_job(taken.unwrap()).map(Some);
+    }
+
+    
+    fn finish_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        job.transition(JobStatus::from_outcome(&results, &errors))?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        return self.finish_job_into(job, format!("jobs_finished_{}", node_type_uuid));
+    }
+
+    fn retry_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>, retry_at: u64) -> Result<(), String> {
+        job.transition(JobStatus::Retrying)?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        job.attempt += 1;
+        job.run_at = Some(retry_at);
+        return self.delay_in_progress(job);
+    }
+
+    fn delay_job(&mut self, mut job: Job, run_at: u64) -> Result<(), String> {
+        job.transition(JobStatus::Scheduled)?;
+        job.started_at = None;
+        job.executing_node = None;
+        job.run_at = Some(run_at);
+        return self.delay_in_progress(job);
+    }
+
+    fn kill_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        job.transition(JobStatus::Dead)?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        return self.finish_job_into(job, format!("jobs_dead_{}", node_type_uuid));
+    }
+
+    fn promote_delayed_jobs(&mut self) -> Result<u64, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let leases = self.concurrency_leases()?;
+        let mut command = ::redis::cmd("EVAL");
+        let builder = command.arg([QUEUE_LUA, LEASE_LUA, "
+            load_leases(6, 2);
+            local due = redis.call('zrangebyscore', KEYS[1], '-inf', ARGV[1]);
+            for _, item in ipairs(due) do
+                redis.call('zrem', KEYS[1], item);
+                push_queued(queues_at(3), item);
+            end
+            -- throttled jobs are usually requeued when a lease is released, this catches any left behind, e.g. by a cancelled job
+            return #due + requeue_throttled(KEYS[2], queues_at(3), nil);
+        "].concat()).arg(5 + leases.len())
+            .arg(format!("jobs_delayed_{}", node_type_uuid))
+            .arg(format!("jobs_throttled_{}", node_type_uuid))
+            .arg(waiting_keys(&*node_type_uuid));
+        for (key, _, _) in leases.iter() {
+            builder.arg(key);
         }
-        let mut job = raw_job.unwrap();
-        job.started_at = Some(epoch());
-        job.executing_node = Some(self.node.node_type_uuid.unwrap());
-        let redis_result: Result<(), ::redis::RedisError> = self.connection.hset(format!("jobs_in_progress_{}", node_type_uuid), job.uuid.hyphenated().to_string(), serde_json::to_string(&job).unwrap());
+        builder.arg(epoch());
+        for (_, job_type_uuid, max_concurrency) in leases.iter() {
+            builder.arg(job_type_uuid).arg(max_concurrency);
+        }
+        let redis_result: Result<u64, ::redis::RedisError> = builder.query(&mut self.connection);
         if redis_result.is_err() {
             return Err(format!("{:?}", redis_result.err().unwrap()));
         }
-        job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
-        return Ok(job);
+        return Ok(redis_result.unwrap());
     }
-    
-    fn finish_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String> {
+
+    fn get_all_jobs_delayed(&mut self) -> Result<Vec<Job>, String> {
         let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
-        let redis_result: Result<(), ::redis::RedisError> = self.connection.hdel(format!("jobs_in_progress_{}", node_type_uuid), job.uuid.hyphenated().to_string());
+        let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.zrange(format!("jobs_delayed_{}", node_type_uuid), 0, -1);
         if redis_result.is_err() {
             return Err(format!("{:?}", redis_result.err().unwrap()));
         }
-        job.ended_at = Some(epoch());
-        job.results = results;
-        job.errors = errors;
-        let redis_result: Result<(), ::redis::RedisError> = self.connection.hset(format!("jobs_finished_{}", node_type_uuid), job.uuid.hyphenated().to_string(), serde_json::to_string(&job).unwrap());
+        let raw_jobs = redis_result.unwrap();
+        let mut jobs: Vec<Job> = vec![];
+        for job in raw_jobs {
+            let raw_job: Result<Job, serde_json::Error> = serde_json::from_str(&*job);
+            if raw_job.is_err() {
+                return Err(format!("{:?}", raw_job.err().unwrap()));
+            }
+            let mut job = raw_job.unwrap();
+            job.status = JobStatus::Scheduled;
+            job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+            jobs.push(job);
+        }
+        return Ok(jobs);
+    }
+
+    fn cancel_delayed_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.zrange(format!("jobs_delayed_{}", node_type_uuid), 0, -1);
         if redis_result.is_err() {
             return Err(format!("{:?}", redis_result.err().unwrap()));
         }
-        return Ok(());
+        for raw_job in redis_result.unwrap() {
+            let job: Result<Job, serde_json::Error> = serde_json::from_str(&*raw_job);
+            if job.is_err() {
+                return Err(format!("{:?}", job.err().unwrap()));
+            }
+            let mut job = job.unwrap();
+            if job.uuid != uuid {
+                continue;
+            }
+            let (handoff_queues, handoff_delayed_key) = self.handoff_keys(job.job_type_uuid, self.node.node_type_uuid.unwrap())?;
+            // the job may have been promoted since we read it, in which case it is left alone
+            let redis_result: Result<u64, ::redis::RedisError> = ::redis::cmd("EVAL").arg([QUEUE_LUA, "
+                if redis.call('zrem', KEYS[1], ARGV[1]) == 0 then
+                    return 0;
+                end
+                -- release the unique lock, handing it to the next deferred job if there is one
+                local lock = redis.call('hget', KEYS[2], ARGV[3]);
+                if lock and cjson.decode(lock).job_uuid == ARGV[2] then
+                    local deferred = redis.call('lpop', KEYS[3]);
+                    if deferred then
+                        redis.call('hset', KEYS[2], ARGV[3], cjson.encode({ job_uuid = cjson.decode(deferred).uuid }));
+                        push_handed_off(queues_at(4), KEYS[7], deferred, ARGV[4]);
+                    else
+                        redis.call('hdel', KEYS[2], ARGV[3]);
+                    end
+                end
+                return 1;
+            "].concat()).arg(7)
+                .arg(format!("jobs_delayed_{}", node_type_uuid))
+                .arg("unique_locks")
+                .arg(format!("jobs_deferred_{}", job.job_type_uuid.hyphenated()))
+                .arg(handoff_queues)
+                .arg(handoff_delayed_key)
+                .arg(&*raw_job)
+                .arg(uuid.hyphenated().to_string())
+                .arg(job.job_type_uuid.hyphenated().to_string())
+                .arg(epoch())
+                .query(&mut self.connection);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            if redis_result.unwrap() == 0 {
+                return Ok(None);
+            }
+            job.transition(JobStatus::Cancelled)?;
+            job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+            return Ok(Some(job));
+        }
+        return Ok(None);
     }
 
-    fn get_all_jobs_waiting(&mut self) -> Result<Vec<Job>, String> {
+    fn get_all_jobs_dead(&mut self) -> Result<Vec<Job>, String> {
         let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
-        let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.lrange(format!("jobs_waiting_{}", node_type_uuid), 0, -1);
+        return self.get_all_jobs_in(format!("jobs_dead_{}", node_type_uuid));
+    }
+
+    fn get_dead_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        return self.get_job_in(format!("jobs_dead_{}", node_type_uuid), uuid);
+    }
+
+    fn purge_dead_jobs(&mut self, uuid: Option<Uuid>) -> Result<u64, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let redis_result: Result<Vec<String>, ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+            local uuids = {};
+            if ARGV[1] == '' then
+                uuids = redis.call('hkeys', KEYS[1]);
+                redis.call('del', KEYS[1]);
+            elseif redis.call('hdel', KEYS[1], ARGV[1]) == 1 then
+                uuids = { ARGV[1] };
+            end
+            return uuids;
+        ").arg(1)
+            .arg(format!("jobs_dead_{}", node_type_uuid))
+            .arg(uuid.map(|uuid| uuid.hyphenated().to_string()).unwrap_or("".to_string()))
+            .query(&mut self.connection);
         if redis_result.is_err() {
             return Err(format!("{:?}", redis_result.err().unwrap()));
         }
-        let raw_jobs = redis_result.unwrap();
+        let purged = redis_result.unwrap();
+        for raw_uuid in purged.iter() {
+            let job_uuid = Uuid::parse_str(raw_uuid);
+            if job_uuid.is_err() {
+                continue;
+            }
+            let redis_result: Result<(), ::redis::RedisError> = self.connection.del(job_logs_keys(job_uuid.unwrap()));
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+        }
+        return Ok(purged.len() as u64);
+    }
+
+    fn get_all_jobs_waiting(&mut self) -> Result<Vec<Job>, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let mut raw_jobs: Vec<String> = vec![];
+        for priority in Priority::ALL.iter() {
+            let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.lrange(waiting_key(&*node_type_uuid, *priority), 0, -1);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            raw_jobs.extend(redis_result.unwrap());
+        }
         let mut jobs: Vec<Job> = vec![];
         for job in raw_jobs {
             let raw_job: Result<Job, serde_json::Error> = serde_json::from_str(&*job);
@@ -312,12 +727,61 @@ impl Store for RedisStore {
                 return Err(format!("{:?}", raw_job.err().unwrap()));
             }
             let mut job = raw_job.unwrap();
+            job.status = JobStatus::Queued;
             job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
             jobs.push(job);
         }
         return Ok(jobs);
     }
 
+    fn reroute_jobs_waiting(&mut self) -> Result<u64, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap();
+        let node_types = self.get_node_types()?;
+        let mut raw_jobs: Vec<String> = vec![];
+        for priority in Priority::ALL.iter() {
+            let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.lrange(waiting_key(&*node_type_uuid.hyphenated().to_string(), *priority), 0, -1);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            raw_jobs.extend(redis_result.unwrap());
+        }
+        let mut rerouted: u64 = 0;
+        for raw_job in raw_jobs {
+            let job: Result<Job, serde_json::Error> = serde_json::from_str(&*raw_job);
+            if job.is_err() {
+                return Err(format!("{:?}", job.err().unwrap()));
+            }
+            let job = job.unwrap();
+            let job_type = self.get_cached_job_type(job.job_type_uuid)?;
+            let target_node_type_uuid = job_node_type_uuid(&node_types, &job_type);
+            if target_node_type_uuid.is_err() {
+                warn!("Leaving job in queue of node type '{}': {}", node_type_uuid.hyphenated(), target_node_type_uuid.err().unwrap());
+                continue;
+            }
+            let target_node_type_uuid = target_node_type_uuid.unwrap();
+            if target_node_type_uuid == node_type_uuid {
+                continue;
+            }
+            // only moves the job if it has not been dequeued since we read the queue
+            let redis_result: Result<u64, ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+                if redis.call('lrem', KEYS[1], 1, ARGV[1]) == 0 then
+                    return 0;
+                end
+                redis.call('rpush', KEYS[2], ARGV[1]);
+                return 1;
+            ").arg(2)
+                .arg(waiting_key(&*node_type_uuid.hyphenated().to_string(), job.priority))
+                .arg(waiting_key(&*target_node_type_uuid.hyphenated().to_string(), job.priority))
+                .arg(&*raw_job)
+                .query(&mut self.connection);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            rerouted += redis_result.unwrap();
+        }
+        return Ok(rerouted);
+    }
+
     fn get_all_jobs_in_progress(&mut self) -> Result<Vec<Job>, String> {
         let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
         return self.get_all_jobs_in(format!("jobs_in_progress_{}", node_type_uuid));
@@ -330,21 +794,7 @@ impl Store for RedisStore {
 
     fn get_finished_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
         let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
-        let redis_result: Result<Option<String>, ::redis::RedisError> = self.connection.hget(format!("jobs_finished_{}", node_type_uuid), uuid.hyphenated().to_string());
-        if redis_result.is_err() {
-            return Err(format!("{:?}", redis_result.err().unwrap()));
-        }
-        let redis_result_maybe = redis_result.unwrap();
-        if redis_result_maybe.is_none() {
-            return Ok(None);
-        }
-        let raw_job: Result<Job, serde_json::Error> = serde_json::from_str(&*redis_result_maybe.unwrap());
-        if raw_job.is_err() {
-            return Err(format!("{:?}", raw_job.err().unwrap()));
-        }
-        let mut job = raw_job.unwrap();
-        job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
-        return Ok(Some(job));
+        return self.get_job_in(format!("jobs_finished_{}", node_type_uuid), uuid);
     }
 
     fn ping(&mut self) -> Result<(), String> {
@@ -385,8 +835,7 @@ impl Store for RedisStore {
                 if node.uuid.hyphenated().to_string() != *current_uuid {
                     return Err(format!("redis consistency error: hash key '{}' not equal data given uuid '{}'", node.uuid.hyphenated(), *current_uuid));
                 }
-                // 20 seconds leeway (pings are every 5 seconds)
-                if node.last_ping + 20000 < request_finish_epoch {
+                if node.last_ping + NODE_EXPIRY_MS < request_finish_epoch {
                     continue;
                 }
                 output.push(node);
@@ -409,12 +858,96 @@ impl Store for RedisStore {
             return Err(format!("{:?}", raw_node.err().unwrap()));
         }
         let node = raw_node.unwrap();
-        if node.last_ping + 20000 < request_finish_epoch {
+        if node.last_ping + NODE_EXPIRY_MS < request_finish_epoch {
             return Ok(None);
         }
         return Ok(Some(node));
     }
 
+    fn reap_dead_nodes(&mut self) -> Result<u64, String> {
+        let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.hgetall("nodes");
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        let request_finish_epoch = epoch();
+        let raw_redis = redis_result.unwrap();
+        let mut recovered: u64 = 0;
+        for raw_node in raw_redis.iter().skip(1).step_by(2) {
+            let raw_node: Result<Node, serde_json::Error> = serde_json::from_str(raw_node);
+            if raw_node.is_err() {
+                return Err(format!("{:?}", raw_node.err().unwrap()));
+            }
+            let node = raw_node.unwrap();
+            if node.uuid == self.node.uuid || node.last_ping + NODE_EXPIRY_MS >= request_finish_epoch {
+                continue;
+            }
+            let node_uuid = node.uuid.hyphenated().to_string();
+            // a node without a node type never dequeued anything, so only its registration needs cleaning up
+            let node_type_uuid = node.node_type_uuid.map(|uuid| uuid.hyphenated().to_string()).unwrap_or("".to_string());
+            // re-checks the ping inside the script, as the node may have come back or been reaped by someone else since we read it
+            let leases = self.concurrency_leases()?;
+            let mut command = ::redis::cmd("EVAL");
+            let builder = command.arg([QUEUE_LUA, LEASE_LUA, "
+                load_leases(9, 3);
+                local raw_node = redis.call('hget', KEYS[1], ARGV[1]);
+                if not raw_node or cjson.decode(raw_node).last_ping ~= tonumber(ARGV[2]) then
+                    return 0;
+                end
+                -- concurrency leases are only held by the jobs in a node's processing list, so expire with them
+                for _, item in ipairs(redis.call('lrange', KEYS[2], 0, -1)) do
+                    local job = cjson.decode(item);
+                    release_lease(job.uuid, job.job_type_uuid, KEYS[8], queues_at(3));
+                end
+                local recovered = 0;
+                local recovered_uuids = {};
+                while true do
+                    local item = redis.call('rpop', KEYS[2]);
+                    if not item then
+                        break;
+                    end
+                    redis.call('lpush', waiting_key(queues_at(3), item), item);
+                    local uuid = cjson.decode(item).uuid;
+                    redis.call('hdel', KEYS[6], uuid);
+                    recovered_uuids[uuid] = true;
+                    recovered = recovered + 1;
+                end
+                -- unique locks stay with recovered jobs, but are no longer owned by the dead node
+                local locks = redis.call('hgetall', KEYS[7]);
+                for i = 1, #locks, 2 do
+                    local lock = cjson.decode(locks[i + 1]);
+                    if lock.node_uuid == ARGV[1] then
+                        if recovered_uuids[lock.job_uuid] then
+                            redis.call('hset', KEYS[7], locks[i], cjson.encode({ job_uuid = lock.job_uuid }));
+                        else
+                            redis.call('hdel', KEYS[7], locks[i]);
+                        end
+                    end
+                end
+                redis.call('hdel', KEYS[1], ARGV[1]);
+                return recovered;
+            "].concat()).arg(8 + leases.len())
+                .arg("nodes")
+                .arg(format!("jobs_processing_{}", node_uuid))
+                .arg(waiting_keys(&*node_type_uuid))
+                .arg(format!("jobs_in_progress_{}", node_type_uuid))
+                .arg("unique_locks")
+                .arg(format!("jobs_throttled_{}", node_type_uuid));
+            for (key, _, _) in leases.iter() {
+                builder.arg(key);
+            }
+            builder.arg(&*node_uuid).arg(node.last_ping);
+            for (_, job_type_uuid, max_concurrency) in leases.iter() {
+                builder.arg(job_type_uuid).arg(max_concurrency);
+            }
+            let redis_result: Result<u64, ::redis::RedisError> = builder.query(&mut self.connection);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            recovered += redis_result.unwrap();
+        }
+        return Ok(recovered);
+    }
+
     fn replicate(&self) -> Result<StoreRef, String> {
         let new_client = self.client.clone();
         let connection = new_client.get_connection();
@@ -427,15 +960,552 @@ impl Store for RedisStore {
             ping_interval: self.ping_interval,
             node: self.node.clone(),
             job_types: self.job_types.clone(),
+            dequeue_count: 0,
         }));
     }
     
+    fn cancel_job(&mut self, uuid: Uuid) -> Result<CancelResult, String> {
+        for _ in 0..CANCEL_ATTEMPTS {
+            let cancelled = self.try_cancel_job(uuid)?;
+            if cancelled.is_some() {
+                return Ok(cancelled.unwrap());
+            }
+        }
+        return Ok(CancelResult::NotFound);
+    }
+
+    fn get_rate_limit_bucket(&mut self, job_type_uuid: Uuid) -> Result<Option<RateLimitBucket>, String> {
+        let redis_result: Result<Option<String>, ::redis::RedisError> = self.connection.hget("rate_limit_buckets", job_type_uuid.hyphenated().to_string());
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        let raw_bucket = redis_result.unwrap();
+        if raw_bucket.is_none() {
+            return Ok(None);
+        }
+        let bucket: Result<RateLimitBucket, serde_json::Error> = serde_json::from_str(&*raw_bucket.unwrap());
+        if bucket.is_err() {
+            return Err(format!("{:?}", bucket.err().unwrap()));
+        }
+        return Ok(Some(bucket.unwrap()));
+    }
+
+    fn update_rate_limit_bucket(&mut self, job_type_uuid: Uuid, old_bucket: Option<&RateLimitBucket>, new_bucket: &RateLimitBucket) -> Result<bool, String> {
+        let redis_result: Result<u64, ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+            if (redis.call('hget', KEYS[1], ARGV[1]) or '') ~= ARGV[2] then
+                return 0;
+            end
+            redis.call('hset', KEYS[1], ARGV[1], ARGV[3]);
+            return 1;
+        ").arg(1)
+            .arg("rate_limit_buckets")
+            .arg(job_type_uuid.hyphenated().to_string())
+            .arg(old_bucket.map(|bucket| serde_json::to_string(bucket).unwrap()).unwrap_or("".to_string()))
+            .arg(serde_json::to_string(new_bucket).unwrap())
+            .query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(redis_result.unwrap() == 1);
+    }
+
+    fn append_job_logs(&mut self, job_uuid: Uuid, output: &str) -> Result<(), String> {
+        // output is appended in place and only cut back once well past the capacity, which reads then apply exactly
+        let redis_result: Result<(), ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+            local length = redis.call('append', KEYS[1], ARGV[1]);
+            redis.call('hincrby', KEYS[2], 'end', string.len(ARGV[1]));
+            redis.call('hset', KEYS[2], 'closed', 0);
+            redis.call('persist', KEYS[1]);
+            redis.call('persist', KEYS[2]);
+            local capacity = tonumber(ARGV[2]);
+            if length > 2 * capacity then
+                redis.call('set', KEYS[1], redis.call('getrange', KEYS[1], length - capacity, -1));
+            end
+        ").arg(2)
+            .arg(job_logs_keys(job_uuid))
+            .arg(output)
+            .arg(JOB_LOGS_CAPACITY)
+            .query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(());
+    }
+
+    fn close_job_logs(&mut self, job_uuid: Uuid) -> Result<(), String> {
+        let redis_result: Result<(), ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+            if redis.call('exists', KEYS[2]) == 0 then
+                return;
+            end
+            redis.call('hset', KEYS[2], 'closed', 1);
+            redis.call('pexpire', KEYS[1], ARGV[1]);
+            redis.call('pexpire', KEYS[2], ARGV[1]);
+        ").arg(2)
+            .arg(job_logs_keys(job_uuid))
+            .arg(JOB_LOGS_RETENTION_MS)
+            .query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(());
+    }
+
+    fn get_job_logs(&mut self, job_uuid: Uuid) -> Result<Option<JobLogs>, String> {
+        let redis_result: Result<Option<(Vec<u8>, u64, u64)>, ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+            local state = redis.call('hmget', KEYS[2], 'end', 'closed');
+            if not state[1] then
+                return nil;
+            end
+            return { redis.call('get', KEYS[1]) or '', tonumber(state[1]), tonumber(state[2]) };
+        ").arg(2)
+            .arg(job_logs_keys(job_uuid))
+            .query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        let raw_logs = redis_result.unwrap();
+        if raw_logs.is_none() {
+            return Ok(None);
+        }
+        let (raw_output, end, closed) = raw_logs.unwrap();
+        // cutting back may have split a character, whose remaining bytes are dropped
+        let start = raw_output.iter().position(|byte| byte & 0xC0 != 0x80).unwrap_or(raw_output.len());
+        let output = String::from_utf8(raw_output[start..].to_vec());
+        if output.is_err() {
+            return Err(format!("{:?}", output.err().unwrap()));
+        }
+        let mut logs = JobLogs { output: output.unwrap(), end, closed: closed != 0 };
+        logs.trim();
+        return Ok(Some(logs));
+    }
+
+    fn is_job_cancelled(&mut self, uuid: Uuid) -> Result<bool, String> {
+        let redis_result: Result<bool, ::redis::RedisError> = self.connection.sismember("jobs_cancelled", uuid.hyphenated().to_string());
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(redis_result.unwrap());
+    }
+
+    fn set_job_progress(&mut self, job_uuid: Uuid, progress: &JobProgress) -> Result<bool, String> {
+        let key = format!("jobs_in_progress_{}", self.node.node_type_uuid.unwrap().hyphenated());
+        let redis_result: Result<Option<String>, ::redis::RedisError> = self.connection.hget(&*key, job_uuid.hyphenated().to_string());
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        let raw_job = redis_result.unwrap();
+        if raw_job.is_none() {
+            return Ok(false);
+        }
+        let raw_job = raw_job.unwrap();
+        let job: Result<Job, serde_json::Error> = serde_json::from_str(&*raw_job);
+        if job.is_err() {
+            return Err(format!("{:?}", job.err().unwrap()));
+        }
+        let job = Job { progress: Some(progress.clone()), ..job.unwrap() };
+        // only updates the job if it has not finished or been requeued since we read it
+        let redis_result: Result<u64, ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+            if redis.call('hget', KEYS[1], ARGV[1]) ~= ARGV[2] then
+                return 0;
+            end
+            redis.call('hset', KEYS[1], ARGV[1], ARGV[3]);
+            return 1;
+        ").arg(1)
+            .arg(&*key)
+            .arg(job_uuid.hyphenated().to_string())
+            .arg(&*raw_job)
+            .arg(serde_json::to_string(&job).unwrap())
+            .query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(redis_result.unwrap() == 1);
+    }
+
     fn clean(&mut self) {
         let _: Result<(), ::redis::RedisError> = ::redis::cmd("FLUSHDB").query(&mut self.connection);
     }
 }
 
+// finds a job by uuid in a list of serialized jobs, returning it along with its exact serialized form
+fn find_raw_job(raw_jobs: Vec<String>, uuid: Uuid) -> Result<Option<(String, Job)>, String> {
+    for raw_job in raw_jobs {
+        let job: Result<Job, serde_json::Error> = serde_json::from_str(&*raw_job);
+        if job.is_err() {
+            return Err(format!("{:?}", job.err().unwrap()));
+        }
+        let job = job.unwrap();
+        if job.uuid == uuid {
+            return Ok(Some((raw_job, job)));
+        }
+    }
+    return Ok(None);
+}
+
 impl RedisStore {
+    // the concurrency lease hash, UUID and max_concurrency (empty if unlimited) of every job type, for scripts using `LEASE_LUA`.
+    // read ahead of the script, as scripts may only touch the keys they are given
+    fn concurrency_leases(&mut self) -> Result<Vec<(String, String, String)>, String> {
+        return Ok(self.get_job_types()?.iter().map(|job_type| {
+            let uuid = job_type.uuid.hyphenated().to_string();
+            (format!("concurrency_leases_{}", uuid), uuid, job_type.max_concurrency.map(|max_concurrency| max_concurrency.to_string()).unwrap_or("".to_string()))
+        }).collect());
+    }
+
+    // the queues and delayed jobs that the next deferred job of a unique job type is moved to when handed its lock,
+    // those of the node type its job type names, as `enqueue_job` routes jobs, or else the given one
+    fn handoff_keys(&mut self, job_type_uuid: Uuid, node_type_uuid: Uuid) -> Result<(Vec<String>, String), String> {
+        let node_type_uuid = match self.get_cached_job_type(job_type_uuid) {
+            Ok(job_type) if job_type.unique => job_node_type_uuid(&self.get_node_types()?, &job_type).unwrap_or(node_type_uuid),
+            _ => node_type_uuid,
+        }.hyphenated().to_string();
+        return Ok((waiting_keys(&*node_type_uuid), format!("jobs_delayed_{}", node_type_uuid)));
+    }
+
+    // atomically moves the first waiting job in `order` whose job type's concurrency limit allows it into our processing list,
+    // so it can be recovered if we die before finishing it, holding back those it doesn't
+    fn take_waiting_job(&mut self, order: &[Priority]) -> Result<Option<String>, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let processing_key = format!("jobs_processing_{}", self.node.uuid.hyphenated());
+        let throttled_key = format!("jobs_throttled_{}", node_type_uuid);
+        let node_uuid = self.node.uuid.hyphenated().to_string();
+        let leases = self.concurrency_leases()?;
+        let mut command = ::redis::cmd("EVAL");
+        let builder = command.arg([QUEUE_LUA, LEASE_LUA, "
+            load_leases(3 + tonumber(ARGV[2]), 3);
+            for i = 3, 2 + tonumber(ARGV[2]) do
+                while true do
+                    local item = redis.call('lpop', KEYS[i]);
+                    if not item then
+                        break;
+                    end
+                    if try_lease(item, KEYS[2], ARGV[1]) then
+                        redis.call('rpush', KEYS[1], item);
+                        return item;
+                    end
+                end
+            end
+            return false;
+        "].concat()).arg(2 + order.len() + leases.len()).arg(&*processing_key).arg(&*throttled_key);
+        for priority in order.iter() {
+            builder.arg(waiting_key(&*node_type_uuid, *priority));
+        }
+        for (key, _, _) in leases.iter() {
+            builder.arg(key);
+        }
+        builder.arg(&*node_uuid).arg(order.len());
+        for (_, job_type_uuid, max_concurrency) in leases.iter() {
+            builder.arg(job_type_uuid).arg(max_concurrency);
+        }
+        let redis_result: Result<Option<String>, ::redis::RedisError> = builder.query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(redis_result.unwrap());
+    }
+
+    // marks a job moved to our processing list as in progress on our node
+    fn start_job(&mut self, raw_job: String) -> Result<Job, String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let raw_job: Result<Job, serde_json::Error> = serde_json::from_str(&*raw_job);
+        if raw_job.is_err() {
+            return Err(format!("{:?}", raw_job.err().unwrap()));
+        }
+        let mut job = raw_job.unwrap();
+        job.status = JobStatus::Queued;
+        job.transition(JobStatus::Running)?;
+        job.started_at = Some(epoch());
+        job.executing_node = Some(self.node.uuid);
+        let redis_result: Result<(), ::redis::RedisError> = self.connection.hset(format!("jobs_in_progress_{}", node_type_uuid), job.uuid.hyphenated().to_string(), serde_json::to_string(&job).unwrap());
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+        if job.job_type.as_ref().unwrap().unique {
+            let lock = UniqueLock { job_uuid: job.uuid, node_uuid: Some(self.node.uuid) };
+            let redis_result: Result<(), ::redis::RedisError> = ::redis::cmd("EVAL").arg("
+                local lock = redis.call('hget', KEYS[1], ARGV[1]);
+                if lock and cjson.decode(lock).job_uuid == ARGV[2] then
+                    redis.call('hset', KEYS[1], ARGV[1], ARGV[3]);
+                end
+            ").arg(1)
+                .arg("unique_locks")
+                .arg(job.job_type_uuid.hyphenated().to_string())
+                .arg(job.uuid.hyphenated().to_string())
+                .arg(serde_json::to_string(&lock).unwrap())
+                .query(&mut self.connection);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+        }
+        return Ok(job);
+    }
+
+
+    // looks for the job wherever it may be and cancels it there, returning None if it moved since it was found
+    fn try_cancel_job(&mut self, uuid: Uuid) -> Result<Option<CancelResult>, String> {
+        let node_types = self.get_node_types()?;
+        for node_type in node_types.iter() {
+            let node_type_uuid = node_type.uuid.hyphenated().to_string();
+            // running jobs are killed by their node, which polls for cancellation
+            if self.mark_job_cancelled(uuid, node_type.uuid)? {
+                return Ok(Some(CancelResult::Cancelling));
+            }
+            for priority in Priority::ALL.iter() {
+                let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.lrange(waiting_key(&*node_type_uuid, *priority), 0, -1);
+                if redis_result.is_err() {
+                    return Err(format!("{:?}", redis_result.err().unwrap()));
+                }
+                if let Some((raw_job, job)) = find_raw_job(redis_result.unwrap(), uuid)? {
+                    return self.cancel_queued_job(raw_job, job, waiting_key(&*node_type_uuid, *priority), false, node_type.uuid);
+                }
+            }
+            let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.zrange(format!("jobs_delayed_{}", node_type_uuid), 0, -1);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            if let Some((raw_job, job)) = find_raw_job(redis_result.unwrap(), uuid)? {
+                return self.cancel_queued_job(raw_job, job, format!("jobs_delayed_{}", node_type_uuid), true, node_type.uuid);
+            }
+            let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.lrange(format!("jobs_throttled_{}", node_type_uuid), 0, -1);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            if let Some((raw_job, job)) = find_raw_job(redis_result.unwrap(), uuid)? {
+                return self.cancel_queued_job(raw_job, job, format!("jobs_throttled_{}", node_type_uuid), false, node_type.uuid);
+            }
+        }
+        for job_type in self.get_job_types()?.iter().filter(|job_type| job_type.unique) {
+            let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.lrange(format!("jobs_deferred_{}", job_type.uuid.hyphenated()), 0, -1);
+            if redis_result.is_err() {
+                return Err(format!("{:?}", redis_result.err().unwrap()));
+            }
+            if let Some((raw_job, job)) = find_raw_job(redis_result.unwrap(), uuid)? {
+                let node_type_uuid = job_node_type_uuid(&node_types, job_type)?;
+                return self.cancel_queued_job(raw_job, job, format!("jobs_deferred_{}", job_type.uuid.hyphenated()), false, node_type_uuid);
+            }
+        }
+        return Ok(Some(CancelResult::NotFound));
+    }
+
+    // the processing lists of every node, including ones that stopped pinging but have not been reaped yet
+    fn processing_keys(&mut self) -> Result<Vec<String>, String> {
+        let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.hkeys("nodes");
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(redis_result.unwrap().iter().map(|node_uuid| format!("jobs_processing_{}", node_uuid)).collect());
+    }
+
+    // marks the job as cancelled if a node of the node type has claimed it, returning whether it did
+    fn mark_job_cancelled(&mut self, uuid: Uuid, node_type_uuid: Uuid) -> Result<bool, String> {
+        let processing_keys = self.processing_keys()?;
+        let redis_result: Result<u64, ::redis::RedisError> = ::redis::cmd("EVAL").arg([CLAIMED_LUA, "
+            if not is_claimed(KEYS[2], 3, ARGV[1]) then
+                return 0;
+            end
+            redis.call('sadd', KEYS[1], ARGV[1]);
+            return 1;
+        "].concat()).arg(2 + processing_keys.len())
+            .arg("jobs_cancelled")
+            .arg(format!("jobs_in_progress_{}", node_type_uuid.hyphenated()))
+            .arg(processing_keys)
+            .arg(uuid.hyphenated().to_string())
+            .query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(redis_result.unwrap() == 1);
+    }
+
+    // removes a queued job from `key` (a list, or a sorted set if `sorted`) and finishes it as cancelled
+    fn cancel_queued_job(&mut self, raw_job: String, mut job: Job, key: String, sorted: bool, node_type_uuid: Uuid) -> Result<Option<CancelResult>, String> {
+        job.transition(JobStatus::Cancelled)?;
+        job.ended_at = Some(epoch());
+        job.errors = Some(cancelled_errors());
+        let (handoff_queues, handoff_delayed_key) = self.handoff_keys(job.job_type_uuid, node_type_uuid)?;
+        let processing_keys = self.processing_keys()?;
+        let node_type_uuid = node_type_uuid.hyphenated().to_string();
+        let redis_result: Result<u64, ::redis::RedisError> = ::redis::cmd("EVAL").arg([QUEUE_LUA, CLAIMED_LUA, "
+            local removed;
+            if ARGV[4] == 'sorted' then
+                removed = redis.call('zrem', KEYS[1], ARGV[1]);
+            else
+                removed = redis.call('lrem', KEYS[1], 1, ARGV[1]);
+            end
+            if removed == 0 then
+                -- the job was picked up since we read it, so leave it to its node
+                if is_claimed(KEYS[10], 11, ARGV[2]) then
+                    redis.call('sadd', KEYS[8], ARGV[2]);
+                    return 0;
+                end
+                -- or it moved elsewhere, where it is looked for again
+                return 2;
+            end
+            redis.call('hset', KEYS[2], ARGV[2], ARGV[3]);
+            -- release the unique lock, handing it to the next deferred job if there is one
+            local lock = redis.call('hget', KEYS[3], ARGV[5]);
+            if lock and cjson.decode(lock).job_uuid == ARGV[2] then
+                local deferred = redis.call('lpop', KEYS[4]);
+                if deferred then
+                    redis.call('hset', KEYS[3], ARGV[5], cjson.encode({ job_uuid = cjson.decode(deferred).uuid }));
+                    push_handed_off(queues_at(5), KEYS[9], deferred, ARGV[6]);
+                else
+                    redis.call('hdel', KEYS[3], ARGV[5]);
+                end
+            end
+            return 1;
+        "].concat()).arg(10 + processing_keys.len())
+            .arg(key)
+            .arg(format!("jobs_finished_{}", node_type_uuid))
+            .arg("unique_locks")
+            .arg(format!("jobs_deferred_{}", job.job_type_uuid.hyphenated()))
+            .arg(handoff_queues)
+            .arg("jobs_cancelled")
+            .arg(handoff_delayed_key)
+            .arg(format!("jobs_in_progress_{}", node_type_uuid))
+            .arg(processing_keys)
+            .arg(&*raw_job)
+            .arg(job.uuid.hyphenated().to_string())
+            .arg(serde_json::to_string(&job).unwrap())
+            .arg(if sorted { "sorted" } else { "list" })
+            .arg(job.job_type_uuid.hyphenated().to_string())
+            .arg(epoch())
+            .query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return match redis_result.unwrap() {
+            0 => Ok(Some(CancelResult::Cancelling)),
+            1 => Ok(Some(CancelResult::Cancelled)),
+            _ => Ok(None),
+        };
+    }
+
+    // moves the job from our processing list and the in progress jobs to the delayed jobs, until its `run_at`
+    fn delay_in_progress(&mut self, job: Job) -> Result<(), String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let leases = self.concurrency_leases()?;
+        let mut command = ::redis::cmd("EVAL");
+        let builder = command.arg([QUEUE_LUA, LEASE_LUA, "
+            load_leases(10, 5);
+            local items = redis.call('lrange', KEYS[1], 0, -1);
+            for _, item in ipairs(items) do
+                if cjson.decode(item).uuid == ARGV[1] then
+                    redis.call('lrem', KEYS[1], 1, item);
+                    break;
+                end
+            end
+            redis.call('hdel', KEYS[2], ARGV[1]);
+            redis.call('zadd', KEYS[3], ARGV[3], ARGV[2]);
+            release_lease(ARGV[1], ARGV[4], KEYS[5], queues_at(6));
+            -- the unique lock stays with the job while it is delayed, but is no longer owned by this node
+            local lock = redis.call('hget', KEYS[4], ARGV[4]);
+            if lock and cjson.decode(lock).job_uuid == ARGV[1] then
+                redis.call('hset', KEYS[4], ARGV[4], cjson.encode({ job_uuid = ARGV[1] }));
+            end
+            -- a mark left from the attempt that ended would cancel the next one as it starts
+            redis.call('srem', KEYS[9], ARGV[1]);
+        "].concat()).arg(9 + leases.len())
+            .arg(format!("jobs_processing_{}", self.node.uuid.hyphenated()))
+            .arg(format!("jobs_in_progress_{}", node_type_uuid))
+            .arg(format!("jobs_delayed_{}", node_type_uuid))
+            .arg("unique_locks")
+            .arg(format!("jobs_throttled_{}", node_type_uuid))
+            .arg(waiting_keys(&*node_type_uuid))
+            .arg("jobs_cancelled");
+        for (key, _, _) in leases.iter() {
+            builder.arg(key);
+        }
+        builder.arg(job.uuid.hyphenated().to_string())
+            .arg(serde_json::to_string(&job).unwrap())
+            .arg(job.run_at.unwrap_or(0))
+            .arg(job.job_type_uuid.hyphenated().to_string());
+        for (_, job_type_uuid, max_concurrency) in leases.iter() {
+            builder.arg(job_type_uuid).arg(max_concurrency);
+        }
+        let redis_result: Result<(), ::redis::RedisError> = builder.query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(());
+    }
+
+    // removes the job from our processing list and the in progress jobs, storing it in the given hash
+    fn finish_job_into(&mut self, job: Job, key: String) -> Result<(), String> {
+        let node_type_uuid = self.node.node_type_uuid.unwrap().hyphenated().to_string();
+        let (handoff_queues, handoff_delayed_key) = self.handoff_keys(job.job_type_uuid, self.node.node_type_uuid.unwrap())?;
+        let leases = self.concurrency_leases()?;
+        let mut command = ::redis::cmd("EVAL");
+        let builder = command.arg([QUEUE_LUA, LEASE_LUA, "
+            load_leases(15, 5);
+            local items = redis.call('lrange', KEYS[1], 0, -1);
+            for _, item in ipairs(items) do
+                if cjson.decode(item).uuid == ARGV[1] then
+                    redis.call('lrem', KEYS[1], 1, item);
+                    break;
+                end
+            end
+            redis.call('hdel', KEYS[2], ARGV[1]);
+            redis.call('hset', KEYS[3], ARGV[1], ARGV[2]);
+            release_lease(ARGV[1], ARGV[3], KEYS[10], queues_at(6));
+            -- release the unique lock, handing it to the next deferred job if there is one
+            local lock = redis.call('hget', KEYS[4], ARGV[3]);
+            if lock and cjson.decode(lock).job_uuid == ARGV[1] then
+                local deferred = redis.call('lpop', KEYS[5]);
+                if deferred then
+                    redis.call('hset', KEYS[4], ARGV[3], cjson.encode({ job_uuid = cjson.decode(deferred).uuid }));
+                    push_handed_off(queues_at(11), KEYS[14], deferred, ARGV[4]);
+                else
+                    redis.call('hdel', KEYS[4], ARGV[3]);
+                end
+            end
+            redis.call('srem', KEYS[9], ARGV[1]);
+        "].concat()).arg(14 + leases.len())
+            .arg(format!("jobs_processing_{}", self.node.uuid.hyphenated()))
+            .arg(format!("jobs_in_progress_{}", node_type_uuid))
+            .arg(key)
+            .arg("unique_locks")
+            .arg(format!("jobs_deferred_{}", job.job_type_uuid.hyphenated()))
+            .arg(waiting_keys(&*node_type_uuid))
+            .arg("jobs_cancelled")
+            .arg(format!("jobs_throttled_{}", node_type_uuid))
+            .arg(handoff_queues)
+            .arg(handoff_delayed_key);
+        for (key, _, _) in leases.iter() {
+            builder.arg(key);
+        }
+        builder.arg(job.uuid.hyphenated().to_string())
+            .arg(serde_json::to_string(&job).unwrap())
+            .arg(job.job_type_uuid.hyphenated().to_string())
+            .arg(epoch());
+        for (_, job_type_uuid, max_concurrency) in leases.iter() {
+            builder.arg(job_type_uuid).arg(max_concurrency);
+        }
+        let redis_result: Result<(), ::redis::RedisError> = builder.query(&mut self.connection);
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        return Ok(());
+    }
+
+    fn get_job_in(&mut self, key: String, uuid: Uuid) -> Result<Option<Job>, String> {
+        let redis_result: Result<Option<String>, ::redis::RedisError> = self.connection.hget(key, uuid.hyphenated().to_string());
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+        let redis_result_maybe = redis_result.unwrap();
+        if redis_result_maybe.is_none() {
+            return Ok(None);
+        }
+        let raw_job: Result<Job, serde_json::Error> = serde_json::from_str(&*redis_result_maybe.unwrap());
+        if raw_job.is_err() {
+            return Err(format!("{:?}", raw_job.err().unwrap()));
+        }
+        let mut job = raw_job.unwrap();
+        job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+        return Ok(Some(job));
+    }
 
     fn get_all_jobs_in(&mut self, key: String) -> Result<Vec<Job>, String> {
         let redis_result: Result<Vec<String>, ::redis::RedisError> = self.connection.hgetall(key);
@@ -596,6 +1666,7 @@ mod tests {
         assert_eq!(enqueued_jobs[0], test_job);
         test_job.started_at = dequeued_job.started_at;
         test_job.executing_node = dequeued_job.executing_node;
+        test_job.status = JobStatus::Running;
         let all_jobs_waiting = store.get_all_jobs_in_progress()?;
         assert_eq!(all_jobs_waiting, vec![test_job.clone()]);
         assert_eq!(dequeued_job, test_job);
@@ -618,11 +1689,47 @@ mod tests {
         theoretical_finished_job.ended_at = all_jobs_finished[0].ended_at;
         theoretical_finished_job.results = Some(Value::Bool(true));
         theoretical_finished_job.errors = Some(Value::Bool(false));
+        theoretical_finished_job.status = JobStatus::Failed;
         assert_eq!(all_jobs_finished, vec![theoretical_finished_job.clone()]);
         assert_eq!(store.get_finished_job(theoretical_finished_job.uuid), Ok(Some(theoretical_finished_job)));
         Ok(())
     }
 
+    #[test]
+    fn can_reap_dead_nodes() -> Result<(), String> {
+        let mut dead_store = RedisStore::connect()?;
+        let mut boxed_store: StoreRef = dead_store.replicate()?;
+        boxed_store.clean();
+        let test_node_type = make_node_type(&mut boxed_store)?;
+        boxed_store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut boxed_store)?;
+        let test_job = make_job(&mut boxed_store, &test_job_type)?;
+        boxed_store.dequeue_job()?;
+        assert_eq!(boxed_store.get_all_jobs_waiting()?.len(), 0);
+
+        // pretend the dequeuing node died a long time ago
+        let mut dead_node = boxed_store.get_node().clone();
+        dead_node.node_type = None;
+        dead_node.last_ping = 0;
+        let redis_result: Result<(), ::redis::RedisError> = dead_store.connection.hset("nodes", dead_node.uuid.hyphenated().to_string(), serde_json::to_string(&dead_node).unwrap());
+        if redis_result.is_err() {
+            return Err(format!("{:?}", redis_result.err().unwrap()));
+        }
+
+        let mut store: StoreRef = Box::new(RedisStore::connect()?);
+        store.set_node_type(test_node_type.uuid)?;
+        assert_eq!(store.reap_dead_nodes()?, 1);
+        let waiting_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, test_job.uuid);
+        assert_eq!(waiting_jobs[0].executing_node, None);
+        assert_eq!(waiting_jobs[0].status, JobStatus::Queued);
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![]);
+        assert_eq!(store.get_other_node(dead_node.uuid)?, None);
+        assert_eq!(store.reap_dead_nodes()?, 0);
+        Ok(())
+    }
+
     #[test]
     fn can_ping() -> Result<(), String> {
         let mut store = RedisStore::connect()?;
@@ -662,4 +1769,24 @@ mod tests {
         Ok(())
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn expires_closed_job_logs() -> Result<(), String> {
+        let mut store = RedisStore::connect()?;
+        store.clean();
+        let job_uuid = Uuid::new_v4();
+        store.append_job_logs(job_uuid, "done\n")?;
+        let keys = job_logs_keys(job_uuid);
+        let ttl: i64 = store.connection.ttl(&*keys[1]).unwrap();
+        assert_eq!(ttl, -1);
+        store.close_job_logs(job_uuid)?;
+        for key in keys.iter() {
+            let ttl: i64 = store.connection.ttl(&**key).unwrap();
+            assert!(ttl > 0 && ttl <= (JOB_LOGS_RETENTION_MS / 1000) as i64);
+        }
+        // more output keeps them again
+        store.append_job_logs(job_uuid, "again\n")?;
+        let ttl: i64 = store.connection.ttl(&*keys[0]).unwrap();
+        assert_eq!(ttl, -1);
+        Ok(())
+    }
+}
@@ -0,0 +1,944 @@
+use crate::store::*;
+use crate::exec::node::Node;
+use uuid::Uuid;
+use serde_json::Value;
+use crate::util::time::{ epoch, epoch_us };
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::thread;
+use std::time::Duration;
+use serde::{ de, Serialize };
+use log::*;
+
+// how often a blocked dequeue polls for new jobs
+const DEQUEUE_POLL_MS: u64 = 250;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+impl SqlValue {
+    fn text(&self) -> Result<&str, String> {
+        match self {
+            SqlValue::Text(s) => Ok(s),
+            _ => Err(format!("sql consistency error: expected text column, got {:?}", self)),
+        }
+    }
+
+    fn integer(&self) -> Result<i64, String> {
+        match self {
+            SqlValue::Integer(i) => Ok(*i),
+            _ => Err(format!("sql consistency error: expected integer column, got {:?}", self)),
+        }
+    }
+}
+
+impl From<Uuid> for SqlValue {
+    fn from(uuid: Uuid) -> SqlValue {
+        SqlValue::Text(uuid.hyphenated().to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> SqlValue {
+        SqlValue::Text(value)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> SqlValue {
+        SqlValue::Text(value.to_string())
+    }
+}
+
+impl From<u64> for SqlValue {
+    fn from(value: u64) -> SqlValue {
+        SqlValue::Integer(value as i64)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(value: Option<T>) -> SqlValue {
+        value.map(|value| value.into()).unwrap_or(SqlValue::Null)
+    }
+}
+
+// a single connection to a SQL database. queries are written with postgres style `$1` placeholders.
+pub trait SqlConnection: Send + Sized + 'static {
+    fn open() -> Result<Self, String>;
+    // opens a new connection to the same database
+    fn reopen(&self) -> Result<Self, String>;
+    // starts a transaction that holds a write lock where the database does not support row locks
+    fn begin(&mut self) -> Result<(), String>;
+    fn commit(&mut self) -> Result<(), String>;
+    fn rollback(&mut self) -> Result<(), String>;
+    // row lock clause appended to select statements that claim rows for update
+    fn skip_locked(&self) -> &'static str;
+    // takes a lock held until the current transaction ends, so only one node migrates the schema at a time
+    fn lock_schema(&mut self) -> Result<(), String>;
+    fn execute(&mut self, query: &str, params: &[SqlValue]) -> Result<u64, String>;
+    fn query(&mut self, query: &str, params: &[SqlValue]) -> Result<Vec<Vec<SqlValue>>, String>;
+}
+
+// applied in order, each exactly once, tracked by index in `schema_migrations`. each is a list of single statements
+pub(super) const MIGRATIONS: &[&[&str]] = &[
+    &[
+        "CREATE TABLE node_types (uuid TEXT PRIMARY KEY, data TEXT NOT NULL)",
+        "CREATE TABLE job_types (uuid TEXT PRIMARY KEY, data TEXT NOT NULL)",
+        "CREATE TABLE schedule_items (uuid TEXT PRIMARY KEY, data TEXT NOT NULL)",
+        "CREATE TABLE nodes (uuid TEXT PRIMARY KEY, last_ping BIGINT NOT NULL, data TEXT NOT NULL)",
+        "CREATE TABLE jobs (
+            uuid TEXT PRIMARY KEY,
+            node_type_uuid TEXT NOT NULL,
+            job_type_uuid TEXT NOT NULL,
+            state TEXT NOT NULL,
+            queue_position BIGINT NOT NULL,
+            executing_node TEXT,
+            enqueued_at BIGINT,
+            started_at BIGINT,
+            ended_at BIGINT,
+            data TEXT NOT NULL
+        )",
+        "CREATE INDEX jobs_queue ON jobs (node_type_uuid, state, queue_position)",
+        "CREATE INDEX jobs_executing_node ON jobs (executing_node)",
+        "CREATE INDEX jobs_job_type ON jobs (job_type_uuid, ended_at)",
+    ],
+    &[
+        "CREATE TABLE unique_locks (job_type_uuid TEXT PRIMARY KEY, job_uuid TEXT NOT NULL, node_uuid TEXT)",
+        "CREATE INDEX unique_locks_node ON unique_locks (node_uuid)",
+    ],
+    &[
+        "ALTER TABLE jobs ADD COLUMN run_at BIGINT",
+        "CREATE INDEX jobs_run_at ON jobs (node_type_uuid, state, run_at)",
+    ],
+    &[
+        "ALTER TABLE jobs ADD COLUMN cancelled_at BIGINT",
+    ],
+    &[
+        "ALTER TABLE jobs ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal'",
+        "CREATE INDEX jobs_priority_queue ON jobs (node_type_uuid, state, priority, queue_position)",
+    ],
+    &[
+        "CREATE TABLE concurrency_leases (job_type_uuid TEXT NOT NULL, slot BIGINT NOT NULL, job_uuid TEXT NOT NULL, node_uuid TEXT NOT NULL, PRIMARY KEY (job_type_uuid, slot))",
+        "CREATE INDEX concurrency_leases_job ON concurrency_leases (job_uuid)",
+        "CREATE INDEX concurrency_leases_node ON concurrency_leases (node_uuid)",
+    ],
+    &[
+        "CREATE TABLE rate_limit_buckets (job_type_uuid TEXT PRIMARY KEY, data TEXT NOT NULL)",
+    ],
+    &[
+        "CREATE TABLE job_logs (job_uuid TEXT PRIMARY KEY, data TEXT NOT NULL)",
+    ],
+    &[
+        // logs are only kept for a while, so the old ones are dropped rather than converted
+        "DROP TABLE job_logs",
+        "CREATE TABLE job_logs (job_uuid TEXT PRIMARY KEY, output TEXT NOT NULL, end_offset BIGINT NOT NULL, closed BIGINT NOT NULL, expires_at BIGINT)",
+        "CREATE INDEX job_logs_expires_at ON job_logs (expires_at)",
+    ],
+];
+
+pub fn migrate<C: SqlConnection>(connection: &mut C) -> Result<(), String> {
+    connection.begin()?;
+    let result = (|| {
+        connection.lock_schema()?;
+        connection.execute("CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY, applied_at BIGINT NOT NULL)", &[])?;
+        let rows = connection.query("SELECT version FROM schema_migrations", &[])?;
+        let mut applied: Vec<i64> = vec![];
+        for row in rows {
+            applied.push(row[0].integer()?);
+        }
+        for (version, migration) in MIGRATIONS.iter().enumerate() {
+            if applied.contains(&(version as i64)) {
+                continue;
+            }
+            info!("Applying SQL schema migration {}", version);
+            for statement in migration.iter() {
+                connection.execute(statement, &[])?;
+            }
+            connection.execute("INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)", &[SqlValue::Integer(version as i64), epoch().into()])?;
+        }
+        Ok(())
+    })();
+    return finish_transaction(connection, result);
+}
+
+fn finish_transaction<C: SqlConnection, T>(connection: &mut C, result: Result<T, String>) -> Result<T, String> {
+    if result.is_err() {
+        connection.rollback()?;
+        return result;
+    }
+    connection.commit()?;
+    return result;
+}
+
+fn parse<T: de::DeserializeOwned>(value: &SqlValue) -> Result<T, String> {
+    let raw: Result<T, serde_json::Error> = serde_json::from_str(value.text()?);
+    if raw.is_err() {
+        return Err(format!("{:?}", raw.err().unwrap()));
+    }
+    return Ok(raw.unwrap());
+}
+
+fn serialize<T: Serialize>(value: &T) -> SqlValue {
+    SqlValue::Text(serde_json::to_string(value).unwrap())
+}
+
+pub struct SqlStore<C: SqlConnection> {
+    connection: C,
+    node: Node,
+    ping_interval: u32,
+    job_types: HashMap<Uuid, JobType>,
+    dequeue_count: u64, // position in the weighted priority cycle
+}
+
+impl<C: SqlConnection> SqlStore<C> {
+    pub fn with_connection(mut connection: C) -> Result<SqlStore<C>, String> {
+        migrate(&mut connection)?;
+        let new_node = Node { uuid: Uuid::new_v4(), last_ping: epoch(), node_type_uuid: None, node_type: None };
+        let mut store = SqlStore { connection: connection, node: new_node, ping_interval: 5000, job_types: HashMap::new(), dequeue_count: 0 };
+        store.save_node()?;
+        return Ok(store);
+    }
+
+    fn node_type_uuid(&self) -> Uuid {
+        return self.node.node_type_uuid.unwrap();
+    }
+
+    fn save_node(&mut self) -> Result<(), String> {
+        let node = Node { node_type: None, ..self.node.clone() };
+        self.connection.execute(
+            "INSERT INTO nodes (uuid, last_ping, data) VALUES ($1, $2, $3) ON CONFLICT (uuid) DO UPDATE SET last_ping = excluded.last_ping, data = excluded.data",
+            &[node.uuid.into(), node.last_ping.into(), serialize(&node)],
+        )?;
+        return Ok(());
+    }
+
+    fn upsert(&mut self, table: &str, uuid: Uuid, data: SqlValue) -> Result<(), String> {
+        let query = format!("INSERT INTO {} (uuid, data) VALUES ($1, $2) ON CONFLICT (uuid) DO UPDATE SET data = excluded.data", table);
+        self.connection.execute(&*query, &[uuid.into(), data])?;
+        return Ok(());
+    }
+
+    fn get_all<T: de::DeserializeOwned>(&mut self, table: &str) -> Result<Vec<T>, String> {
+        let rows = self.connection.query(&*format!("SELECT data FROM {}", table), &[])?;
+        return rows.iter().map(|row| parse(&row[0])).collect();
+    }
+
+    fn get_one<T: de::DeserializeOwned>(&mut self, table: &str, uuid: Uuid) -> Result<Option<T>, String> {
+        let rows = self.connection.query(&*format!("SELECT data FROM {} WHERE uuid = $1", table), &[uuid.into()])?;
+        return rows.first().map(|row| parse(&row[0])).transpose();
+    }
+
+    fn get_jobs(&mut self, state: &str, uuid: Option<Uuid>) -> Result<Vec<Job>, String> {
+        let mut query = "SELECT data FROM jobs WHERE node_type_uuid = $1 AND state = $2".to_string();
+        let mut params = vec![self.node_type_uuid().into(), state.into()];
+        if uuid.is_some() {
+            query.push_str(" AND uuid = $3");
+            params.push(uuid.into());
+        }
+        if state == "waiting" {
+            query.push_str(" ORDER BY CASE priority WHEN 'high' THEN 0 WHEN 'normal' THEN 1 ELSE 2 END, queue_position");
+        } else {
+            query.push_str(" ORDER BY queue_position");
+        }
+        let rows = self.connection.query(&*query, &params)?;
+        let mut jobs: Vec<Job> = vec![];
+        for row in rows {
+            let mut job: Job = parse(&row[0])?;
+            job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+            jobs.push(job);
+        }
+        return Ok(jobs);
+    }
+
+    fn update_job(&mut self, job: &Job, state: &str) -> Result<(), String> {
+        self.connection.execute(
+            "UPDATE jobs SET state = $2, executing_node = $3, started_at = $4, ended_at = $5, data = $6 WHERE uuid = $1",
+            &[job.uuid.into(), state.into(), job.executing_node.into(), job.started_at.into(), job.ended_at.into(), serialize(job)],
+        )?;
+        return Ok(());
+    }
+
+    // takes the first waiting job whose job type's concurrency limit allows it, moving it in progress on our node
+    fn take_waiting_job(&mut self) -> Result<Option<Job>, String> {
+        let order = Priority::dequeue_order(self.dequeue_count);
+        self.connection.begin()?;
+        let result = (|| {
+            let query = format!("SELECT data FROM jobs WHERE node_type_uuid = $1 AND state = 'waiting' AND priority = $2 ORDER BY queue_position LIMIT 1{}", self.connection.skip_locked());
+            loop {
+                let mut rows = vec![];
+                for priority in order.iter() {
+                    rows = self.connection.query(&*query, &[self.node_type_uuid().into(), priority.as_str().into()])?;
+                    if !rows.is_empty() {
+                        break;
+                    }
+                }
+                if rows.is_empty() {
+                    return Ok(None);
+                }
+                let mut job: Job = parse(&rows[0][0])?;
+                if !self.try_lease(&job)? {
+                    // held back until one of its job type's running jobs finishes
+                    job.status = JobStatus::Scheduled;
+                    self.connection.execute("UPDATE jobs SET state = 'throttled', data = $2 WHERE uuid = $1", &[job.uuid.into(), serialize(&job)])?;
+                    continue;
+                }
+                job.transition(JobStatus::Running)?;
+                job.started_at = Some(epoch());
+                job.executing_node = Some(self.node.uuid);
+                self.update_job(&job, "in_progress")?;
+                self.connection.execute("UPDATE unique_locks SET node_uuid = $3 WHERE job_type_uuid = $1 AND job_uuid = $2", &[job.job_type_uuid.into(), job.uuid.into(), self.node.uuid.into()])?;
+                return Ok(Some(job));
+            }
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    // moves an in progress job to the delayed jobs, until its `run_at`
+    fn delay_in_progress(&mut self, job: &Job) -> Result<(), String> {
+        self.connection.begin()?;
+        let result = (|| {
+            self.update_job(job, "delayed")?;
+            // a cancellation requested while the job ran does not carry over to its next run
+            self.connection.execute("UPDATE jobs SET run_at = $2, cancelled_at = NULL WHERE uuid = $1", &[job.uuid.into(), job.run_at.into()])?;
+            self.release_lease(job)?;
+            // the unique lock stays with the job while it is delayed, but is no longer owned by this node
+            self.connection.execute("UPDATE unique_locks SET node_uuid = NULL WHERE job_uuid = $1", &[job.uuid.into()])?;
+            Ok(())
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    // cancels the job as it is now, or returns `None` if it moved to another state meanwhile
+    fn try_cancel_job(&mut self, uuid: Uuid) -> Result<Option<CancelResult>, String> {
+        self.connection.begin()?;
+        let result = (|| {
+            let rows = self.connection.query("SELECT state, data FROM jobs WHERE uuid = $1", &[uuid.into()])?;
+            if rows.is_empty() {
+                return Ok(Some(CancelResult::NotFound));
+            }
+            let state = rows[0][0].text()?.to_string();
+            if state == "in_progress" {
+                // running jobs are killed by their node, which polls for cancellation
+                self.connection.execute("UPDATE jobs SET cancelled_at = $2 WHERE uuid = $1", &[uuid.into(), epoch().into()])?;
+                return Ok(Some(CancelResult::Cancelling));
+            }
+            if state != "waiting" && state != "delayed" && state != "deferred" && state != "throttled" {
+                return Ok(Some(CancelResult::NotFound));
+            }
+            let mut job: Job = parse(&rows[0][1])?;
+            job.transition(JobStatus::Cancelled)?;
+            job.ended_at = Some(epoch());
+            job.errors = Some(cancelled_errors());
+            // the job may have been picked up since we read it, in which case it is left to its node
+            let updated = self.connection.execute(
+                "UPDATE jobs SET state = 'finished', ended_at = $3, data = $4 WHERE uuid = $1 AND state = $2",
+                &[uuid.into(), state.into(), job.ended_at.into(), serialize(&job)],
+            )?;
+            if updated == 0 {
+                // only a job that is now running is left to its node, otherwise it is looked for again
+                let marked = self.connection.execute(
+                    "UPDATE jobs SET cancelled_at = $2 WHERE uuid = $1 AND state = 'in_progress'",
+                    &[uuid.into(), epoch().into()],
+                )?;
+                if marked == 0 {
+                    return Ok(None);
+                }
+                return Ok(Some(CancelResult::Cancelling));
+            }
+            self.release_unique_lock(&job)?;
+            Ok(Some(CancelResult::Cancelled))
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    // hands the unique lock held by the job to the next deferred job of its type, if there is one
+    fn release_unique_lock(&mut self, job: &Job) -> Result<(), String> {
+        let held = self.connection.query("SELECT job_uuid FROM unique_locks WHERE job_type_uuid = $1 AND job_uuid = $2", &[job.job_type_uuid.into(), job.uuid.into()])?;
+        if held.is_empty() {
+            return Ok(());
+        }
+        let query = format!("SELECT data FROM jobs WHERE job_type_uuid = $1 AND state = 'deferred' ORDER BY queue_position LIMIT 1{}", self.connection.skip_locked());
+        let deferred = self.connection.query(&*query, &[job.job_type_uuid.into()])?;
+        if deferred.is_empty() {
+            self.connection.execute("DELETE FROM unique_locks WHERE job_type_uuid = $1", &[job.job_type_uuid.into()])?;
+            return Ok(());
+        }
+        let mut deferred: Job = parse(&deferred[0][0])?;
+        let state = if is_handed_off_delayed(&deferred) { "delayed" } else { "waiting" };
+        if state == "waiting" {
+            deferred.status = JobStatus::Queued;
+        }
+        self.connection.execute("UPDATE unique_locks SET job_uuid = $2, node_uuid = NULL WHERE job_type_uuid = $1", &[job.job_type_uuid.into(), deferred.uuid.into()])?;
+        self.connection.execute("UPDATE jobs SET state = $2, queue_position = $3, data = $4 WHERE uuid = $1", &[deferred.uuid.into(), state.into(), epoch_us().into(), serialize(&deferred)])?;
+        return Ok(());
+    }
+
+    // read from the store rather than the job type cache, so a changed limit applies to every node straight away
+    fn get_max_concurrency(&mut self, job_type_uuid: Uuid) -> Result<Option<u32>, String> {
+        return Ok(self.get_job_type(job_type_uuid)?.and_then(|job_type| job_type.max_concurrency));
+    }
+
+    // takes a free concurrency slot of the job's job type, unless they are all taken
+    fn try_lease(&mut self, job: &Job) -> Result<bool, String> {
+        let max_concurrency = self.get_max_concurrency(job.job_type_uuid)?;
+        if max_concurrency.is_none() {
+            return Ok(true);
+        }
+        for slot in 0..max_concurrency.unwrap() {
+            let acquired = self.connection.execute(
+                "INSERT INTO concurrency_leases (job_type_uuid, slot, job_uuid, node_uuid) VALUES ($1, $2, $3, $4) ON CONFLICT (job_type_uuid, slot) DO NOTHING",
+                &[job.job_type_uuid.into(), (slot as u64).into(), job.uuid.into(), self.node.uuid.into()],
+            )?;
+            if acquired > 0 {
+                return Ok(true);
+            }
+        }
+        return Ok(false);
+    }
+
+    // how many more of the job type's jobs may run, or None if it has no max_concurrency
+    fn concurrency_room(&mut self, job_type_uuid: Uuid) -> Result<Option<u64>, String> {
+        let max_concurrency = self.get_max_concurrency(job_type_uuid)?;
+        if max_concurrency.is_none() {
+            return Ok(None);
+        }
+        let max_concurrency = max_concurrency.unwrap() as u64;
+        let rows = self.connection.query("SELECT COUNT(*) FROM concurrency_leases WHERE job_type_uuid = $1 AND slot < $2", &[job_type_uuid.into(), max_concurrency.into()])?;
+        return Ok(Some(max_concurrency.saturating_sub(rows[0][0].integer()? as u64)));
+    }
+
+    // queues throttled jobs of the given job type (or of our node type) again, as far as their job types have room for them
+    fn requeue_throttled(&mut self, job_type_uuid: Option<Uuid>) -> Result<u64, String> {
+        let rows = if job_type_uuid.is_some() {
+            let query = format!("SELECT data FROM jobs WHERE job_type_uuid = $1 AND state = 'throttled' ORDER BY queue_position{}", self.connection.skip_locked());
+            self.connection.query(&*query, &[job_type_uuid.into()])?
+        } else {
+            let query = format!("SELECT data FROM jobs WHERE node_type_uuid = $1 AND state = 'throttled' ORDER BY queue_position{}", self.connection.skip_locked());
+            self.connection.query(&*query, &[self.node_type_uuid().into()])?
+        };
+        let mut rooms: HashMap<Uuid, Option<u64>> = HashMap::new();
+        let mut requeued: u64 = 0;
+        for row in rows {
+            let mut job: Job = parse(&row[0])?;
+            if let Entry::Vacant(entry) = rooms.entry(job.job_type_uuid) {
+                entry.insert(self.concurrency_room(job.job_type_uuid)?);
+            }
+            let room = rooms[&job.job_type_uuid];
+            if room == Some(0) {
+                continue;
+            }
+            rooms.insert(job.job_type_uuid, room.map(|room| room - 1));
+            job.status = JobStatus::Queued;
+            // keeps its original queue position, so it is picked up before anything enqueued after it
+            self.connection.execute("UPDATE jobs SET state = 'waiting', data = $2 WHERE uuid = $1", &[job.uuid.into(), serialize(&job)])?;
+            requeued += 1;
+        }
+        return Ok(requeued);
+    }
+
+    // releases the job's concurrency lease, if it holds one, queueing the first job held back by it
+    fn release_lease(&mut self, job: &Job) -> Result<(), String> {
+        let released = self.connection.execute("DELETE FROM concurrency_leases WHERE job_uuid = $1", &[job.uuid.into()])?;
+        if released > 0 {
+            self.requeue_throttled(Some(job.job_type_uuid))?;
+        }
+        return Ok(());
+    }
+
+    fn get_cached_job_type(&mut self, uuid: Uuid) -> Result<JobType, String> {
+        let cached = self.job_types.get(&uuid);
+        if cached.is_some() {
+            return Ok(cached.unwrap().clone());
+        }
+        let retrieved = self.get_job_type(uuid)?;
+        if retrieved.is_none() {
+            return Err(format!("invalid job type: '{}'", uuid.hyphenated()));
+        }
+        self.job_types.insert(uuid, retrieved.as_ref().unwrap().clone());
+        return Ok(retrieved.unwrap());
+    }
+}
+
+impl<C: SqlConnection> Store for SqlStore<C> {
+    fn connect() -> Result<SqlStore<C>, String> {
+        return SqlStore::with_connection(C::open()?);
+    }
+
+    fn get_node_types(&mut self) -> Result<Vec<NodeType>, String> {
+        return self.get_all("node_types");
+    }
+
+    fn get_node_type(&mut self, node_type_uuid: Uuid) -> Result<Option<NodeType>, String> {
+        return self.get_one("node_types", node_type_uuid);
+    }
+
+    fn new_node_type(&mut self, node_type: &NodeType) -> Result<(), String> {
+        return self.upsert("node_types", node_type.uuid, serialize(node_type));
+    }
+
+    fn set_node_type(&mut self, node_type_uuid: Uuid) -> Result<Option<()>, String> {
+        if self.set_node_type_soft(node_type_uuid)?.is_none() {
+            return Ok(None);
+        }
+        self.save_node()?;
+        return Ok(Some(()));
+    }
+
+    fn set_node_type_soft(&mut self, node_type_uuid: Uuid) -> Result<Option<()>, String> {
+        let node_type = self.get_node_type(node_type_uuid)?;
+        if node_type.is_none() {
+            return Ok(None);
+        }
+        self.node.node_type_uuid = Some(node_type_uuid);
+        self.node.node_type = node_type;
+        return Ok(Some(()));
+    }
+
+    fn get_job_types(&mut self) -> Result<Vec<JobType>, String> {
+        return self.get_all("job_types");
+    }
+
+    fn get_job_type(&mut self, uuid: Uuid) -> Result<Option<JobType>, String> {
+        return self.get_one("job_types", uuid);
+    }
+
+    fn new_job_type(&mut self, job_type: &JobType) -> Result<(), String> {
+        return self.upsert("job_types", job_type.uuid, serialize(job_type));
+    }
+
+    fn get_job_schedule(&mut self) -> Result<Vec<ScheduleItem>, String> {
+        return self.get_all("schedule_items");
+    }
+
+    fn get_job_schedule_item(&mut self, uuid: Uuid) -> Result<Option<ScheduleItem>, String> {
+        return self.get_one("schedule_items", uuid);
+    }
+
+    fn delete_job_schedule_item(&mut self, uuid: Uuid) -> Result<(), String> {
+        self.connection.execute("DELETE FROM schedule_items WHERE uuid = $1", &[uuid.into()])?;
+        return Ok(());
+    }
+
+    fn new_job_schedule_item(&mut self, schedule_item: &ScheduleItem) -> Result<(), String> {
+        return self.upsert("schedule_items", schedule_item.uuid, serialize(schedule_item));
+    }
+
+    fn update_job_schedule_item(&mut self, old_schedule_item: &ScheduleItem, new_schedule_item: &ScheduleItem) -> Result<bool, String> {
+        let updated = self.connection.execute(
+            "UPDATE schedule_items SET data = $3 WHERE uuid = $1 AND data = $2",
+            &[old_schedule_item.uuid.into(), serialize(old_schedule_item), serialize(new_schedule_item)],
+        )?;
+        return Ok(updated > 0);
+    }
+
+    fn claim_job_scheduled(&mut self, schedule_item: &ScheduleItem) -> Result<Option<ScheduleItem>, String> {
+        let mut new_schedule_item = schedule_item.clone();
+        new_schedule_item.last_scheduled_by = Some(self.node.uuid);
+        new_schedule_item.last_scheduled_at = Some(epoch());
+        let updated = self.update_job_schedule_item(schedule_item, &new_schedule_item)?;
+        if updated {
+            return Ok(Some(new_schedule_item)); // we claimed it
+        } else {
+            return Ok(None); // someone else modified/claimed it
+        }
+    }
+
+    fn enqueue_job(&mut self, mut job: Job) -> Result<EnqueueResult, String> {
+        check_new_job(&job)?;
+        job.enqueued_at = Some(epoch());
+        let job_type = match job.job_type.as_ref() {
+            Some(job_type) => job_type.clone(),
+            None => self.get_cached_job_type(job.job_type_uuid)?,
+        };
+        let node_types = self.get_node_types()?;
+        let node_type_uuid = job_node_type_uuid(&node_types, &job_type)?;
+        self.connection.begin()?;
+        let result = (|| {
+            let mut state = "waiting";
+            let mut result = EnqueueResult::Enqueued;
+            if job_type.unique {
+                loop {
+                    let acquired = self.connection.execute(
+                        "INSERT INTO unique_locks (job_type_uuid, job_uuid) VALUES ($1, $2) ON CONFLICT (job_type_uuid) DO NOTHING",
+                        &[job_type.uuid.into(), job.uuid.into()],
+                    )?;
+                    if acquired > 0 {
+                        break;
+                    }
+                    let existing = self.connection.query("SELECT job_uuid FROM unique_locks WHERE job_type_uuid = $1", &[job_type.uuid.into()])?;
+                    if existing.is_empty() {
+                        continue; // released since we tried to acquire it
+                    }
+                    let existing_uuid = Uuid::parse_str(existing[0][0].text()?);
+                    if existing_uuid.is_err() {
+                        return Err(format!("sql consistency error: invalid unique lock for job type '{}'", job_type.uuid.hyphenated()));
+                    }
+                    result = EnqueueResult::unique_conflict(job_type.unique_policy, existing_uuid.unwrap());
+                    if result != EnqueueResult::Deferred {
+                        return Ok(result);
+                    }
+                    state = "deferred";
+                    job.transition(JobStatus::Scheduled)?;
+                    break;
+                }
+            }
+            if state == "waiting" && is_delayed(&job) {
+                state = "delayed";
+                result = EnqueueResult::Delayed;
+                job.transition(JobStatus::Scheduled)?;
+            }
+            self.connection.execute(
+                "INSERT INTO jobs (uuid, node_type_uuid, job_type_uuid, state, queue_position, enqueued_at, run_at, priority, data) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                &[job.uuid.into(), node_type_uuid.into(), job.job_type_uuid.into(), state.into(), epoch_us().into(), job.enqueued_at.into(), job.run_at.into(), job.priority.as_str().into(), serialize(&job)],
+            )?;
+            Ok(result)
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    fn dequeue_job(&mut self) -> Result<Job, String> {
+        loop {
+            let dequeued = self.try_dequeue_job()?;
+            if dequeued.is_some() {
+                return Ok(dequeued.unwrap());
+            }
+            thread::sleep(Duration::from_millis(DEQUEUE_POLL_MS));
+        }
+    }
+
+    fn try_dequeue_job(&mut self) -> Result<Option<Job>, String> {
+        let dequeued = self.take_waiting_job()?;
+        if dequeued.is_none() {
+            return Ok(None);
+        }
+        self.dequeue_count += 1;
+        let mut job = dequeued.unwrap();
+        job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+        return Ok(Some(job));
+    }
+
+    fn finish_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String> {
+        job.transition(JobStatus::from_outcome(&results, &errors))?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        self.connection.begin()?;
+        let result = (|| {
+            self.update_job(&job, "finished")?;
+            self.release_lease(&job)?;
+            self.release_unique_lock(&job)
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    fn retry_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>, retry_at: u64) -> Result<(), String> {
+        job.transition(JobStatus::Retrying)?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        job.attempt += 1;
+        job.run_at = Some(retry_at);
+        return self.delay_in_progress(&job);
+    }
+
+    fn delay_job(&mut self, mut job: Job, run_at: u64) -> Result<(), String> {
+        job.transition(JobStatus::Scheduled)?;
+        job.started_at = None;
+        job.executing_node = None;
+        job.run_at = Some(run_at);
+        return self.delay_in_progress(&job);
+    }
+
+    fn kill_job(&mut self, mut job: Job, results: Option<Value>, errors: Option<Value>) -> Result<(), String> {
+        job.transition(JobStatus::Dead)?;
+        job.ended_at = Some(epoch());
+        job.results = results;
+        job.errors = errors;
+        self.connection.begin()?;
+        let result = (|| {
+            self.update_job(&job, "dead")?;
+            self.release_lease(&job)?;
+            self.release_unique_lock(&job)
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    fn promote_delayed_jobs(&mut self) -> Result<u64, String> {
+        self.connection.begin()?;
+        let result = (|| {
+            let query = format!("SELECT data FROM jobs WHERE node_type_uuid = $1 AND state = 'delayed' AND run_at <= $2 ORDER BY run_at{}", self.connection.skip_locked());
+            let rows = self.connection.query(&*query, &[self.node_type_uuid().into(), epoch().into()])?;
+            let queue_position = epoch_us();
+            for (i, row) in rows.iter().enumerate() {
+                let mut job: Job = parse(&row[0])?;
+                job.status = JobStatus::Queued;
+                self.connection.execute("UPDATE jobs SET state = 'waiting', queue_position = $2, run_at = NULL, data = $3 WHERE uuid = $1", &[job.uuid.into(), (queue_position + i as u64).into(), serialize(&job)])?;
+            }
+            // throttled jobs are usually requeued when a lease is released, this catches any left behind, e.g. by a cancelled job
+            Ok(rows.len() as u64 + self.requeue_throttled(None)?)
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    fn get_all_jobs_delayed(&mut self) -> Result<Vec<Job>, String> {
+        let rows = self.connection.query("SELECT data FROM jobs WHERE node_type_uuid = $1 AND state = 'delayed' ORDER BY run_at", &[self.node_type_uuid().into()])?;
+        let mut jobs: Vec<Job> = vec![];
+        for row in rows {
+            let mut job: Job = parse(&row[0])?;
+            job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+            jobs.push(job);
+        }
+        return Ok(jobs);
+    }
+
+    fn cancel_delayed_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        self.connection.begin()?;
+        let result = (|| {
+            let query = format!("SELECT data FROM jobs WHERE node_type_uuid = $1 AND state = 'delayed' AND uuid = $2{}", self.connection.skip_locked());
+            let rows = self.connection.query(&*query, &[self.node_type_uuid().into(), uuid.into()])?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+            let mut job: Job = parse(&rows[0][0])?;
+            job.transition(JobStatus::Cancelled)?;
+            self.connection.execute("DELETE FROM jobs WHERE uuid = $1", &[uuid.into()])?;
+            self.release_unique_lock(&job)?;
+            Ok(Some(job))
+        })();
+        let job = finish_transaction(&mut self.connection, result)?;
+        if job.is_none() {
+            return Ok(None);
+        }
+        let mut job = job.unwrap();
+        job.job_type = Some(self.get_cached_job_type(job.job_type_uuid)?);
+        return Ok(Some(job));
+    }
+
+    fn get_all_jobs_dead(&mut self) -> Result<Vec<Job>, String> {
+        return self.get_jobs("dead", None);
+    }
+
+    fn get_dead_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        return Ok(self.get_jobs("dead", Some(uuid))?.pop());
+    }
+
+    fn purge_dead_jobs(&mut self, uuid: Option<Uuid>) -> Result<u64, String> {
+        let node_type_uuid = self.node_type_uuid();
+        self.connection.begin()?;
+        let result = (|| {
+            if uuid.is_some() {
+                self.connection.execute(
+                    "DELETE FROM job_logs WHERE job_uuid IN (SELECT uuid FROM jobs WHERE node_type_uuid = $1 AND state = 'dead' AND uuid = $2)",
+                    &[node_type_uuid.into(), uuid.into()],
+                )?;
+                return self.connection.execute("DELETE FROM jobs WHERE node_type_uuid = $1 AND state = 'dead' AND uuid = $2", &[node_type_uuid.into(), uuid.into()]);
+            }
+            self.connection.execute("DELETE FROM job_logs WHERE job_uuid IN (SELECT uuid FROM jobs WHERE node_type_uuid = $1 AND state = 'dead')", &[node_type_uuid.into()])?;
+            return self.connection.execute("DELETE FROM jobs WHERE node_type_uuid = $1 AND state = 'dead'", &[node_type_uuid.into()]);
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    fn get_all_jobs_waiting(&mut self) -> Result<Vec<Job>, String> {
+        return self.get_jobs("waiting", None);
+    }
+
+    fn reroute_jobs_waiting(&mut self) -> Result<u64, String> {
+        let node_type_uuid = self.node_type_uuid();
+        let node_types = self.get_node_types()?;
+        let job_types = self.get_job_types()?;
+        self.connection.begin()?;
+        let result = (|| {
+            let mut rerouted: u64 = 0;
+            for job_type in job_types {
+                let target_node_type_uuid = job_node_type_uuid(&node_types, &job_type);
+                if target_node_type_uuid.is_err() {
+                    continue; // left in place until its node type exists
+                }
+                let target_node_type_uuid = target_node_type_uuid.unwrap();
+                if target_node_type_uuid == node_type_uuid {
+                    continue;
+                }
+                // keeps their queue position, so they are ordered by when they were enqueued in their new queue
+                rerouted += self.connection.execute(
+                    "UPDATE jobs SET node_type_uuid = $3 WHERE node_type_uuid = $1 AND job_type_uuid = $2 AND state = 'waiting'",
+                    &[node_type_uuid.into(), job_type.uuid.into(), target_node_type_uuid.into()],
+                )?;
+            }
+            Ok(rerouted)
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    fn get_all_jobs_in_progress(&mut self) -> Result<Vec<Job>, String> {
+        return self.get_jobs("in_progress", None);
+    }
+
+    fn get_all_jobs_finished(&mut self) -> Result<Vec<Job>, String> {
+        return self.get_jobs("finished", None);
+    }
+
+    fn get_finished_job(&mut self, uuid: Uuid) -> Result<Option<Job>, String> {
+        return Ok(self.get_jobs("finished", Some(uuid))?.pop());
+    }
+
+    fn ping(&mut self) -> Result<(), String> {
+        self.node.last_ping = epoch();
+        return self.save_node();
+    }
+
+    fn get_ping_interval_ms(&self) -> u32 {
+        return self.ping_interval;
+    }
+
+    fn get_node(&mut self) -> &mut Node {
+        return &mut self.node;
+    }
+
+    fn get_nodes(&mut self) -> Result<Vec<Node>, String> {
+        let rows = self.connection.query("SELECT data FROM nodes WHERE last_ping >= $1", &[(epoch() - NODE_EXPIRY_MS).into()])?;
+        return rows.iter().map(|row| parse(&row[0])).collect();
+    }
+
+    fn get_other_node(&mut self, uuid: Uuid) -> Result<Option<Node>, String> {
+        let rows = self.connection.query("SELECT data FROM nodes WHERE uuid = $1 AND last_ping >= $2", &[uuid.into(), (epoch() - NODE_EXPIRY_MS).into()])?;
+        return rows.first().map(|row| parse(&row[0])).transpose();
+    }
+
+    fn reap_dead_nodes(&mut self) -> Result<u64, String> {
+        self.connection.begin()?;
+        let result = (|| {
+            let dead_nodes = self.connection.query("SELECT uuid FROM nodes WHERE last_ping < $1 AND uuid != $2", &[(epoch() - NODE_EXPIRY_MS).into(), self.node.uuid.into()])?;
+            let mut recovered: u64 = 0;
+            for dead_node in dead_nodes {
+                let query = format!("SELECT data FROM jobs WHERE executing_node = $1 AND state = 'in_progress'{}", self.connection.skip_locked());
+                let rows = self.connection.query(&*query, &[dead_node[0].clone()])?;
+                for row in rows {
+                    let mut job: Job = parse(&row[0])?;
+                    job.transition(JobStatus::Queued)?;
+                    job.started_at = None;
+                    job.executing_node = None;
+                    // keeps its original queue position, so it is picked up again before anything enqueued after it
+                    self.update_job(&job, "waiting")?;
+                    // unique locks stay with recovered jobs, but are no longer owned by the dead node
+                    self.connection.execute("UPDATE unique_locks SET node_uuid = NULL WHERE job_uuid = $1", &[job.uuid.into()])?;
+                    recovered += 1;
+                }
+                self.connection.execute("DELETE FROM unique_locks WHERE node_uuid = $1", &[dead_node[0].clone()])?;
+                let leases = self.connection.query("SELECT DISTINCT job_type_uuid FROM concurrency_leases WHERE node_uuid = $1", &[dead_node[0].clone()])?;
+                self.connection.execute("DELETE FROM concurrency_leases WHERE node_uuid = $1", &[dead_node[0].clone()])?;
+                for lease in leases {
+                    let job_type_uuid = Uuid::parse_str(lease[0].text()?);
+                    if job_type_uuid.is_err() {
+                        return Err(format!("sql consistency error: invalid concurrency lease job type '{}'", lease[0].text()?));
+                    }
+                    self.requeue_throttled(Some(job_type_uuid.unwrap()))?;
+                }
+                self.connection.execute("DELETE FROM nodes WHERE uuid = $1", &[dead_node[0].clone()])?;
+            }
+            Ok(recovered)
+        })();
+        return finish_transaction(&mut self.connection, result);
+    }
+
+    fn replicate(&self) -> Result<StoreRef, String> {
+        return Ok(Box::new(SqlStore {
+            connection: self.connection.reopen()?,
+            node: self.node.clone(),
+            ping_interval: self.ping_interval,
+            job_types: self.job_types.clone(),
+            dequeue_count: 0,
+        }));
+    }
+
+    fn cancel_job(&mut self, uuid: Uuid) -> Result<CancelResult, String> {
+        for _ in 0..CANCEL_ATTEMPTS {
+            let cancelled = self.try_cancel_job(uuid)?;
+            if cancelled.is_some() {
+                return Ok(cancelled.unwrap());
+            }
+        }
+        return Ok(CancelResult::NotFound);
+    }
+
+    fn is_job_cancelled(&mut self, uuid: Uuid) -> Result<bool, String> {
+        let rows = self.connection.query("SELECT uuid FROM jobs WHERE uuid = $1 AND cancelled_at IS NOT NULL", &[uuid.into()])?;
+        return Ok(!rows.is_empty());
+    }
+
+    fn get_rate_limit_bucket(&mut self, job_type_uuid: Uuid) -> Result<Option<RateLimitBucket>, String> {
+        let rows = self.connection.query("SELECT data FROM rate_limit_buckets WHERE job_type_uuid = $1", &[job_type_uuid.into()])?;
+        return rows.first().map(|row| parse(&row[0])).transpose();
+    }
+
+    fn update_rate_limit_bucket(&mut self, job_type_uuid: Uuid, old_bucket: Option<&RateLimitBucket>, new_bucket: &RateLimitBucket) -> Result<bool, String> {
+        let updated = match old_bucket {
+            None => self.connection.execute(
+                "INSERT INTO rate_limit_buckets (job_type_uuid, data) VALUES ($1, $2) ON CONFLICT (job_type_uuid) DO NOTHING",
+                &[job_type_uuid.into(), serialize(new_bucket)],
+            )?,
+            Some(old_bucket) => self.connection.execute(
+                "UPDATE rate_limit_buckets SET data = $3 WHERE job_type_uuid = $1 AND data = $2",
+                &[job_type_uuid.into(), serialize(old_bucket), serialize(new_bucket)],
+            )?,
+        };
+        return Ok(updated > 0);
+    }
+
+    fn append_job_logs(&mut self, job_uuid: Uuid, output: &str) -> Result<(), String> {
+        self.connection.execute(
+            "INSERT INTO job_logs (job_uuid, output, end_offset, closed) VALUES ($1, $2, $3, 0)
+            ON CONFLICT (job_uuid) DO UPDATE SET output = job_logs.output || excluded.output, end_offset = job_logs.end_offset + excluded.end_offset, closed = 0, expires_at = NULL",
+            &[job_uuid.into(), output.into(), (output.len() as u64).into()],
+        )?;
+        // output is appended in place and only cut back once well past the capacity, which reads then apply exactly.
+        // lengths are in characters, which keeps at least as many bytes
+        self.connection.execute(
+            &*format!("UPDATE job_logs SET output = substr(output, length(output) - {} + 1) WHERE job_uuid = $1 AND length(output) > {}", JOB_LOGS_CAPACITY, 2 * JOB_LOGS_CAPACITY),
+            &[job_uuid.into()],
+        )?;
+        return Ok(());
+    }
+
+    fn close_job_logs(&mut self, job_uuid: Uuid) -> Result<(), String> {
+        let now = epoch();
+        self.connection.execute("UPDATE job_logs SET closed = 1, expires_at = $2 WHERE job_uuid = $1", &[job_uuid.into(), (now + JOB_LOGS_RETENTION_MS).into()])?;
+        // drops the logs of other jobs whose retention has passed
+        self.connection.execute("DELETE FROM job_logs WHERE expires_at <= $1", &[now.into()])?;
+        return Ok(());
+    }
+
+    fn get_job_logs(&mut self, job_uuid: Uuid) -> Result<Option<JobLogs>, String> {
+        let rows = self.connection.query(
+            "SELECT output, end_offset, closed FROM job_logs WHERE job_uuid = $1 AND (expires_at IS NULL OR expires_at > $2)",
+            &[job_uuid.into(), epoch().into()],
+        )?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let mut logs = JobLogs { output: rows[0][0].text()?.to_string(), end: rows[0][1].integer()? as u64, closed: rows[0][2].integer()? != 0 };
+        logs.trim();
+        return Ok(Some(logs));
+    }
+
+    fn set_job_progress(&mut self, job_uuid: Uuid, progress: &JobProgress) -> Result<bool, String> {
+        let job = self.get_jobs("in_progress", Some(job_uuid))?.pop();
+        if job.is_none() {
+            return Ok(false);
+        }
+        let job = Job { progress: Some(progress.clone()), ..job.unwrap() };
+        // the job may have finished or been requeued since it was read
+        let updated = self.connection.execute("UPDATE jobs SET data = $2 WHERE uuid = $1 AND state = 'in_progress'", &[job_uuid.into(), serialize(&job)])?;
+        return Ok(updated > 0);
+    }
+
+    fn clean(&mut self) {
+        for table in &["node_types", "job_types", "schedule_items", "nodes", "jobs", "unique_locks", "concurrency_leases", "rate_limit_buckets", "job_logs"] {
+            let _ = self.connection.execute(&*format!("DELETE FROM {}", table), &[]);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::store::sql::*;
+use crate::util::config;
+use rusqlite::types::{ ToSqlOutput, ValueRef };
+use rusqlite::{ Connection, ToSql, params_from_iter };
+use regex::Regex;
+use std::time::Duration;
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            SqlValue::Null => Ok(ToSqlOutput::Borrowed(ValueRef::Null)),
+            SqlValue::Integer(i) => Ok(ToSqlOutput::Borrowed(ValueRef::Integer(*i))),
+            SqlValue::Text(s) => Ok(ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes()))),
+        }
+    }
+}
+
+pub struct SqliteConnection {
+    path: String,
+    connection: Connection,
+}
+
+impl SqliteConnection {
+    pub fn open_path(path: &str) -> Result<SqliteConnection, String> {
+        let connection = Connection::open(path);
+        if connection.is_err() {
+            return Err(format!("{:?}", connection.err().unwrap()));
+        }
+        let connection = connection.unwrap();
+        // multiple connections are opened per node (one per thread), which all need to wait on each other's writes
+        let configured = connection.busy_timeout(Duration::from_secs(30))
+            .and_then(|_| connection.pragma_update(None, "journal_mode", "WAL"));
+        if configured.is_err() {
+            return Err(format!("{:?}", configured.err().unwrap()));
+        }
+        return Ok(SqliteConnection { path: path.to_string(), connection: connection });
+    }
+
+    fn translate(query: &str) -> String {
+        lazy_static! {
+            static ref PLACEHOLDER_REGEX: Regex = Regex::new("\\$([0-9]+)").unwrap();
+        }
+        return PLACEHOLDER_REGEX.replace_all(query, "?$1").to_string();
+    }
+}
+
+impl SqlConnection for SqliteConnection {
+    fn open() -> Result<SqliteConnection, String> {
+        return SqliteConnection::open_path(&*config::SQLITE_PATH);
+    }
+
+    fn reopen(&self) -> Result<SqliteConnection, String> {
+        return SqliteConnection::open_path(&*self.path);
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        // sqlite has no row locks, so take the database write lock up front
+        return self.connection.execute_batch("BEGIN IMMEDIATE").map_err(|e| format!("{:?}", e));
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        return self.connection.execute_batch("COMMIT").map_err(|e| format!("{:?}", e));
+    }
+
+    fn rollback(&mut self) -> Result<(), String> {
+        return self.connection.execute_batch("ROLLBACK").map_err(|e| format!("{:?}", e));
+    }
+
+    fn skip_locked(&self) -> &'static str {
+        return "";
+    }
+
+    fn lock_schema(&mut self) -> Result<(), String> {
+        // already held, as `begin` takes the database write lock
+        return Ok(());
+    }
+
+    fn execute(&mut self, query: &str, params: &[SqlValue]) -> Result<u64, String> {
+        let result = self.connection.execute(&*SqliteConnection::translate(query), params_from_iter(params.iter()));
+        if result.is_err() {
+            return Err(format!("{:?}", result.err().unwrap()));
+        }
+        return Ok(result.unwrap() as u64);
+    }
+
+    fn query(&mut self, query: &str, params: &[SqlValue]) -> Result<Vec<Vec<SqlValue>>, String> {
+        let statement = self.connection.prepare(&*SqliteConnection::translate(query));
+        if statement.is_err() {
+            return Err(format!("{:?}", statement.err().unwrap()));
+        }
+        let mut statement = statement.unwrap();
+        let column_count = statement.column_count();
+        let rows = statement.query_map(params_from_iter(params.iter()), |row| {
+            let mut output: Vec<SqlValue> = vec![];
+            for i in 0..column_count {
+                output.push(match row.get_ref(i)? {
+                    ValueRef::Integer(i) => SqlValue::Integer(i),
+                    ValueRef::Text(s) => SqlValue::Text(String::from_utf8_lossy(s).to_string()),
+                    _ => SqlValue::Null,
+                });
+            }
+            Ok(output)
+        });
+        if rows.is_err() {
+            return Err(format!("{:?}", rows.err().unwrap()));
+        }
+        let rows: rusqlite::Result<Vec<Vec<SqlValue>>> = rows.unwrap().collect();
+        return rows.map_err(|e| format!("{:?}", e));
+    }
+}
+
+pub type SqliteStore = SqlStore<SqliteConnection>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::*;
+    use crate::store::tests::*;
+    use std::env;
+    use std::fs;
+    use std::thread;
+    use uuid::Uuid;
+    use serde_json::Value;
+
+    // a database in the temp directory, removed with its WAL files when dropped, so it is declared before its connections
+    struct TemporaryDatabase {
+        path: String,
+    }
+
+    impl TemporaryDatabase {
+        fn new() -> TemporaryDatabase {
+            let path = env::temp_dir().join(format!("synchrony_test_{}.db", Uuid::new_v4().hyphenated())).to_string_lossy().to_string();
+            return TemporaryDatabase { path: path };
+        }
+
+        fn connect(&self) -> Result<StoreRef, String> {
+            return Ok(Box::new(SqliteStore::with_connection(SqliteConnection::open_path(&*self.path)?)?));
+        }
+    }
+
+    impl Drop for TemporaryDatabase {
+        fn drop(&mut self) {
+            for suffix in &["", "-wal", "-shm"] {
+                let _ = fs::remove_file(format!("{}{}", self.path, suffix));
+            }
+        }
+    }
+
+    #[test]
+    fn can_migrate_repeatedly() -> Result<(), String> {
+        let database = TemporaryDatabase::new();
+        let _store = database.connect()?;
+        let mut connection = SqliteConnection::open_path(&*database.path)?;
+        migrate(&mut connection)?;
+        let versions = connection.query("SELECT version FROM schema_migrations", &[])?;
+        assert_eq!(versions.len(), MIGRATIONS.len());
+        Ok(())
+    }
+
+    #[test]
+    fn can_migrate_concurrently() -> Result<(), String> {
+        let database = TemporaryDatabase::new();
+        let migrating: Vec<_> = (0..4).map(|_| {
+            let path = database.path.clone();
+            thread::spawn(move || migrate(&mut SqliteConnection::open_path(&*path)?))
+        }).collect();
+        for migrated in migrating {
+            migrated.join().unwrap()?;
+        }
+        let versions = SqliteConnection::open_path(&*database.path)?.query("SELECT version FROM schema_migrations", &[])?;
+        assert_eq!(versions.len(), MIGRATIONS.len());
+        Ok(())
+    }
+
+    #[test]
+    fn can_enqueue_dequeue_finish_jobs() -> Result<(), String> {
+        let database = TemporaryDatabase::new();
+        let mut store = database.connect()?;
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let first_job = make_job(&mut store, &test_job_type)?;
+        let second_job = make_job(&mut store, &test_job_type)?;
+        assert_eq!(store.get_all_jobs_waiting()?.iter().map(|job| job.uuid).collect::<Vec<Uuid>>(), vec![first_job.uuid, second_job.uuid]);
+        let dequeued_job = store.dequeue_job()?;
+        assert_eq!(dequeued_job.uuid, first_job.uuid);
+        assert_eq!(dequeued_job.executing_node, Some(store.get_node().uuid));
+        assert_eq!(dequeued_job.job_type, Some(test_job_type));
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![dequeued_job.clone()]);
+        store.finish_job(dequeued_job.clone(), Some(Value::Bool(true)), None)?;
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![]);
+        let finished_job = store.get_finished_job(first_job.uuid)?.unwrap();
+        assert_eq!(finished_job.results, Some(Value::Bool(true)));
+        assert_eq!(store.get_all_jobs_finished()?, vec![finished_job]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_claim_job_schedule_once() -> Result<(), String> {
+        let database = TemporaryDatabase::new();
+        let mut store = database.connect()?;
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_schedule_item = make_schedule_item(&mut store, test_job_type.uuid, None, None)?;
+        let mut other_store = store.replicate()?;
+        let claimed = store.claim_job_scheduled(&test_schedule_item)?;
+        assert!(claimed.is_some());
+        assert_eq!(other_store.claim_job_scheduled(&test_schedule_item)?, None);
+        assert_eq!(other_store.get_job_schedule_item(test_schedule_item.uuid)?, claimed);
+        Ok(())
+    }
+
+    #[test]
+    fn can_reap_dead_nodes() -> Result<(), String> {
+        let database = TemporaryDatabase::new();
+        let mut store = database.connect()?;
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+        store.dequeue_job()?;
+        let mut connection = SqliteConnection::open_path(&*database.path)?;
+        connection.execute("UPDATE nodes SET last_ping = 0 WHERE uuid = $1", &[store.get_node().uuid.into()])?;
+
+        let mut other_store = database.connect()?;
+        other_store.set_node_type(test_node_type.uuid)?;
+        assert_eq!(other_store.reap_dead_nodes()?, 1);
+        let waiting_jobs = other_store.get_all_jobs_waiting()?;
+        assert_eq!(waiting_jobs.len(), 1);
+        assert_eq!(waiting_jobs[0].uuid, test_job.uuid);
+        assert_eq!(waiting_jobs[0].executing_node, None);
+        assert_eq!(waiting_jobs[0].status, JobStatus::Queued);
+        assert_eq!(other_store.get_all_jobs_in_progress()?, vec![]);
+        assert_eq!(other_store.get_other_node(store.get_node().uuid)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn drops_job_logs_after_retention() -> Result<(), String> {
+        let database = TemporaryDatabase::new();
+        let mut store = database.connect()?;
+        let job_uuid = Uuid::new_v4();
+        let other_job_uuid = Uuid::new_v4();
+        store.append_job_logs(job_uuid, "done\n")?;
+        store.close_job_logs(job_uuid)?;
+        assert!(store.get_job_logs(job_uuid)?.unwrap().closed);
+        let mut connection = SqliteConnection::open_path(&*database.path)?;
+        connection.execute("UPDATE job_logs SET expires_at = 0 WHERE job_uuid = $1", &[job_uuid.into()])?;
+        assert_eq!(store.get_job_logs(job_uuid)?, None);
+        // closing other logs deletes the expired ones
+        store.append_job_logs(other_job_uuid, "")?;
+        store.close_job_logs(other_job_uuid)?;
+        assert_eq!(connection.query("SELECT job_uuid FROM job_logs", &[])?, vec![vec![other_job_uuid.into()]]);
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
 pub mod ping_thread;
+pub mod reaper_thread;
 pub mod scheduler_thread;
 pub mod worker_thread;
\ No newline at end of file
@@ -0,0 +1,24 @@
+use std::thread;
+use std::time::Duration;
+use crate::StoreRef;
+use log::*;
+
+fn run_loop(store: &mut StoreRef) {
+    let reap_result = store.reap_dead_nodes();
+    match reap_result {
+        Err(e) => { error!("Error reaping dead nodes from redis server: {}", e); },
+        Ok(0) => {},
+        Ok(recovered) => { warn!("Recovered {} in-flight job(s) from dead nodes", recovered); },
+    };
+}
+
+pub fn start_thread(mut store: StoreRef) {
+    thread::spawn(move || {
+        let interval = store.get_ping_interval_ms();
+        loop {
+            thread::sleep(Duration::from_millis(interval as u64));
+
+            run_loop(&mut store);
+        }
+    });
+}
@@ -1,50 +1,99 @@
 
 use std::thread;
 use crate::StoreRef;
+use crate::store::EnqueueResult;
 use log::*;
 use crate::util::time::epoch;
-use crate::exec::job::Job;
+use crate::exec::job::{ Job, JobStatus };
 use uuid::Uuid;
 
+fn promote_delayed_jobs(store: &mut StoreRef) {
+    let promoted = store.promote_delayed_jobs();
+    if promoted.is_err() {
+        error!("Error promoting delayed jobs from redis server: {}", promoted.err().unwrap());
+        return;
+    }
+    let promoted = promoted.unwrap();
+    if promoted > 0 {
+        info!("Promoted {} delayed jobs", promoted);
+    }
+}
+
 fn run_loop(store: &mut StoreRef) {
+    promote_delayed_jobs(store);
     let job_schedule = store.get_job_schedule();
     if job_schedule.is_err() {
         error!("Error getting job schedule from redis server: {}", job_schedule.err().unwrap());
         return;
     }
     let eval_time = epoch();
+    // runs due more than two scheduler passes ago were missed, i.e. no node was running the scheduler at the time
+    let misfire_grace_ms = store.get_ping_interval_ms() as u64 * 2;
     for schedule_item in job_schedule.unwrap() {
-        let current_chunk = eval_time / schedule_item.interval;
-        if schedule_item.last_scheduled_at.is_none() || current_chunk > (schedule_item.last_scheduled_at.unwrap() / schedule_item.interval) {
-            let claim_result = store.claim_job_scheduled(&schedule_item);
-            match claim_result {
-                Err(e) => { error!("Error claiming job schedule from redis server: {}", e); },
-                Ok(None) => {},
-                Ok(Some(_)) => {
-                    let job_type = store.get_job_type(schedule_item.job_type_uuid);
-                    if job_type.is_err() || job_type.as_ref().unwrap().is_none() {
-                        error!("Error getting job type from redis server: {}", job_type.err().unwrap());
-                        return;
-                    }
+        if !schedule_item.enabled {
+            continue;
+        }
+        if schedule_item.cron.is_some() && schedule_item.last_scheduled_at.is_none() {
+            // cron schedules that have never run start counting from the first time they are seen, rather than firing immediately
+            if let Err(e) = store.claim_job_scheduled(&schedule_item) {
+                error!("Error claiming job schedule from redis server: {}", e);
+            }
+            continue;
+        }
+        let jobs_due = schedule_item.jobs_due(eval_time, misfire_grace_ms);
+        if jobs_due.is_err() {
+            error!("Invalid schedule item '{}': {}", schedule_item.uuid.hyphenated(), jobs_due.err().unwrap());
+            continue;
+        }
+        let jobs_due = jobs_due.unwrap();
+        if jobs_due.is_none() {
+            continue;
+        }
+        let claim_result = store.claim_job_scheduled(&schedule_item);
+        match claim_result {
+            Err(e) => { error!("Error claiming job schedule from redis server: {}", e); },
+            Ok(None) => {},
+            Ok(Some(_)) if jobs_due == Some(0) => {
+                info!("Skipped missed runs of schedule item '{}'", schedule_item.uuid.hyphenated());
+            },
+            Ok(Some(_)) => {
+                let job_type = store.get_job_type(schedule_item.job_type_uuid);
+                if job_type.is_err() || job_type.as_ref().unwrap().is_none() {
+                    error!("Error getting job type from redis server: {}", job_type.err().unwrap());
+                    return;
+                }
+                let job_type = job_type.unwrap().unwrap();
+                for _ in 0..jobs_due.unwrap() {
                     let enqueue_result = store.enqueue_job(Job {
                         uuid: Uuid::new_v4(),
+                        status: JobStatus::Queued,
                         job_type_uuid: schedule_item.job_type_uuid,
-                        job_type: Some(job_type.unwrap().unwrap()),
-                        arguments: schedule_item.job_arguments,
+                        job_type: Some(job_type.clone()),
+                        arguments: schedule_item.job_arguments.clone(),
+                        priority: job_type.priority,
                         executing_node: None,
                         enqueued_at: None,
+                        run_at: None,
                         started_at: None,
                         ended_at: None,
                         results: None,
                         errors: None,
+                        attempt: 0,
+                        progress: None,
                     });
-                    if enqueue_result.is_err() {
-                        error!("Error enqueuing job from redis server: {}", enqueue_result.err().unwrap());
-                        return;
-                    }
-                },
-            };
-        }
+                    match enqueue_result {
+                        Err(e) => {
+                            error!("Error enqueuing job from redis server: {}", e);
+                            return;
+                        },
+                        Ok(EnqueueResult::Rejected(existing_uuid)) | Ok(EnqueueResult::Coalesced(existing_uuid)) => {
+                            info!("Skipped scheduling unique job type '{}', job '{}' is already queued or running", schedule_item.job_type_uuid.hyphenated(), existing_uuid.hyphenated());
+                        },
+                        Ok(_) => {},
+                    };
+                }
+            },
+        };
     }
 }
 
@@ -65,6 +114,8 @@ mod tests {
     use super::*;
     use crate::store::tests::*;
     use crate::store::init_store_untyped;
+    use crate::scheduler::MisfirePolicy;
+    use std::time::Duration;
 
     #[test]
     fn can_schedule_first_time() -> Result<(), String> {
@@ -118,4 +169,62 @@ mod tests {
         assert_eq!(queued_jobs[0].job_type_uuid, test_job_type.uuid);
         Ok(())
     }
+
+    #[test]
+    fn will_not_schedule_when_paused() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let mut test_schedule_item = make_schedule_item(&mut store, test_job_type.uuid, None, None)?;
+        test_schedule_item.enabled = false;
+        store.new_job_schedule_item(&test_schedule_item)?;
+        run_loop(&mut store);
+        assert_eq!(store.get_all_jobs_waiting()?.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn can_fire_all_missed_runs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let node_uuid = store.get_node().uuid;
+        let mut test_schedule_item = make_schedule_item(&mut store, test_job_type.uuid, Some(node_uuid), Some(epoch() - 60000))?;
+        test_schedule_item.misfire_policy = MisfirePolicy::FireAll;
+        test_schedule_item.misfire_limit = Some(3);
+        store.new_job_schedule_item(&test_schedule_item)?;
+        run_loop(&mut store);
+        assert_eq!(store.get_all_jobs_waiting()?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn can_schedule_cron() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let test_job_type = make_job_type(&mut store)?;
+        let mut test_schedule_item = make_schedule_item(&mut store, test_job_type.uuid, None, None)?;
+        test_schedule_item.interval = 0;
+        test_schedule_item.cron = Some("* * * * * *".to_string());
+        test_schedule_item.timezone = Some("Europe/Berlin".to_string());
+        store.new_job_schedule_item(&test_schedule_item)?;
+
+        // the first pass only starts the schedule
+        run_loop(&mut store);
+        assert_eq!(store.get_all_jobs_waiting()?.len(), 0);
+        assert!(store.get_job_schedule_item(test_schedule_item.uuid)?.unwrap().last_scheduled_at.is_some());
+
+        thread::sleep(Duration::from_millis(1100));
+        run_loop(&mut store);
+        let queued_jobs = store.get_all_jobs_waiting()?;
+        assert_eq!(queued_jobs.len(), 1);
+        assert_eq!(queued_jobs[0].job_type_uuid, test_job_type.uuid);
+        Ok(())
+    }
 }
@@ -5,6 +5,12 @@ use log::*;
 use crate::exec::executors::*;
 use crate::exec::executor::*;
 use serde_json::Value;
+use std::time::Duration;
+
+// how often a worker thread running jobs asynchronously polls them
+const ASYNC_POLL_MS: u64 = 10;
+// how often a worker thread running jobs asynchronously checks for new jobs while it has none running
+const ASYNC_IDLE_POLL_MS: u64 = 100;
 
 fn run_loop(store: &mut StoreRef) {
     let dequeued_item = store.dequeue_job();
@@ -18,10 +24,40 @@ fn run_loop(store: &mut StoreRef) {
     run_job(store, job);
 }
 
+// polls the running jobs, finishing those with results, then starts new jobs until `max_jobs` are running, returning them
+fn run_async_loop(store: &mut StoreRef, running: Vec<RunningJob>, max_jobs: usize) -> Vec<RunningJob> {
+    let mut running: Vec<RunningJob> = running.into_iter().filter_map(|running| poll_job(store, running)).collect();
+    while running.len() < max_jobs {
+        let dequeued_item = store.try_dequeue_job();
+        if dequeued_item.is_err() {
+            error!("Error getting job schedule from redis server: {}", dequeued_item.err().unwrap());
+            break;
+        }
+        let job = match dequeued_item.unwrap() {
+            Some(job) => job,
+            None => break,
+        };
+        let job_type = job.job_type.as_ref().unwrap();
+        info!("Starting job '{}' of type '{}' / '{}'", job.uuid.hyphenated(), job_type.name, job.job_type_uuid.hyphenated());
+        if let Some(started) = start_job(store, job) {
+            running.push(started);
+        }
+    }
+    return running;
+}
+
 pub fn start_thread(mut store: StoreRef) {
+    let jobs_per_thread = store.get_node().node_type.as_ref().unwrap().jobs_per_thread.unwrap_or(1) as usize;
     thread::spawn(move || {
+        if jobs_per_thread <= 1 {
+            loop {
+                run_loop(&mut store);
+            }
+        }
+        let mut running = vec![];
         loop {
-            run_loop(&mut store);
+            running = run_async_loop(&mut store, running, jobs_per_thread);
+            thread::sleep(Duration::from_millis(if running.is_empty() { ASYNC_IDLE_POLL_MS } else { ASYNC_POLL_MS }));
         }
     });
 }
@@ -31,7 +67,12 @@ mod tests {
     use super::*;
     use crate::store::tests::*;
     use crate::store::init_store_untyped;
-    use serde_json::{ Map, Number };
+    use serde_json::{ Map, Number, json };
+    use crate::exec::job_type::{ RetryPolicy, RetryOutcome, RateLimit, OutputLimit };
+    use crate::store::CancelResult;
+    use crate::exec::job::JobStatus;
+    use std::time::{ Duration, Instant };
+    use crate::util::time::epoch;
 
     #[test]
     fn can_execute_job() -> Result<(), String> {
@@ -48,12 +89,214 @@ mod tests {
         test_job.started_at = finished_jobs[0].started_at;
         test_job.executing_node = finished_jobs[0].executing_node;
         test_job.ended_at = finished_jobs[0].ended_at;
+        test_job.status = JobStatus::Succeeded;
         let mut output = Map::new();
         output.insert("stdout".to_string(), Value::String("test\n".to_string()));
         output.insert("stderr".to_string(), Value::String("".to_string()));
         output.insert("exit_code".to_string(), Value::Number(Number::from(0)));
         test_job.results = Some(Value::Object(output));
         assert_eq!(finished_jobs[0], test_job);
+        let logs = store.get_job_logs(test_job.uuid)?.unwrap();
+        assert_eq!(logs.since(0), "test\n");
+        assert!(logs.closed);
+        Ok(())
+    }
+
+    #[test]
+    fn can_retry_failed_job() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.metadata.insert("command".to_string(), Value::String("exit 3".to_string()));
+        test_job_type.retry = Some(RetryPolicy { max_attempts: 2, base_delay_ms: 0, max_delay_ms: 0, jitter: 0.0, retry_on: vec![RetryOutcome::NonZeroExit] });
+        store.new_job_type(&test_job_type)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        run_loop(&mut store);
+        assert_eq!(store.get_all_jobs_finished()?, vec![]);
+        assert_eq!(store.promote_delayed_jobs()?, 1);
+        run_loop(&mut store);
+        assert_eq!(store.get_all_jobs_finished()?, vec![]);
+        let dead_job = store.get_dead_job(test_job.uuid)?.unwrap();
+        assert_eq!(dead_job.attempt, 1);
+        assert_eq!(dead_job.results.unwrap()["exit_code"], Value::Number(Number::from(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn can_fail_job_with_invalid_executor() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.executor = "missing".to_string();
+        test_job_type.retry = Some(RetryPolicy { max_attempts: 2, base_delay_ms: 0, max_delay_ms: 0, jitter: 0.0, retry_on: vec![RetryOutcome::Error] });
+        store.new_job_type(&test_job_type)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        run_loop(&mut store);
+        assert_eq!(store.get_all_jobs_finished()?, vec![]);
+        assert_eq!(store.promote_delayed_jobs()?, 1);
+        run_loop(&mut store);
+        let dead_job = store.get_dead_job(test_job.uuid)?.unwrap();
+        assert_eq!(dead_job.status, JobStatus::Dead);
+        assert_eq!(dead_job.errors, Some(json!({ "kind": "invalid_executor", "executor": "missing" })));
+
+        let mut other_job_type = make_job_type(&mut store)?;
+        other_job_type.executor = "missing".to_string();
+        store.new_job_type(&other_job_type)?;
+        let test_job = make_job(&mut store, &other_job_type)?;
+        run_loop(&mut store);
+        assert_eq!(store.get_finished_job(test_job.uuid)?.unwrap().status, JobStatus::Failed);
+        Ok(())
+    }
+
+    #[test]
+    fn can_limit_job_output() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.metadata.insert("command".to_string(), Value::String("seq 1 1000".to_string()));
+        test_job_type.output_limit = Some(OutputLimit { head_bytes: 4, tail_bytes: 9 });
+        store.new_job_type(&test_job_type)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        run_loop(&mut store);
+        let results = store.get_finished_job(test_job.uuid)?.unwrap().results.unwrap();
+        assert_eq!(results["stdout"], Value::String("1\n2\n\n[... 3880 bytes truncated ...]\n999\n1000\n".to_string()));
+        assert_eq!(results["stdout_bytes"], Value::Number(Number::from(3893)));
+        assert_eq!(results["truncated"], Value::Bool(true));
+        assert_eq!(results["stderr"], Value::String("".to_string()));
+        // the job's logs are kept in full
+        assert_eq!(store.get_job_logs(test_job.uuid)?.unwrap().end, 3893);
+        Ok(())
+    }
+
+    #[test]
+    fn can_report_job_progress() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.metadata.insert("command".to_string(), Value::String("echo '{\"percent\": 50, \"message\": \"half\"}' > \"$SYNCHRONY_PROGRESS_FILE\"".to_string()));
+        store.new_job_type(&test_job_type)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        run_loop(&mut store);
+        // the last progress reported is kept with the finished job
+        let progress = store.get_finished_job(test_job.uuid)?.unwrap().progress.unwrap();
+        assert_eq!(progress.percent, Some(50.0));
+        assert_eq!(progress.message, Some("half".to_string()));
+        assert!(progress.updated_at > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn can_rate_limit_jobs() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.rate_limit = Some(RateLimit { limit: 1, period_ms: 60000 });
+        store.new_job_type(&test_job_type)?;
+        let first_job = make_job(&mut store, &test_job_type)?;
+        let second_job = make_job(&mut store, &test_job_type)?;
+
+        run_loop(&mut store);
+        run_loop(&mut store);
+        let finished_jobs = store.get_all_jobs_finished()?;
+        assert_eq!(finished_jobs.len(), 1);
+        assert_eq!(finished_jobs[0].uuid, first_job.uuid);
+        // delayed until the bucket has refilled, without counting an attempt
+        let delayed_jobs = store.get_all_jobs_delayed()?;
+        assert_eq!(delayed_jobs.len(), 1);
+        assert_eq!(delayed_jobs[0].uuid, second_job.uuid);
+        assert_eq!(delayed_jobs[0].status, JobStatus::Scheduled);
+        assert_eq!(delayed_jobs[0].attempt, 0);
+        assert_eq!(delayed_jobs[0].executing_node, None);
+        assert!(delayed_jobs[0].run_at.unwrap() > epoch() + 50000);
+        assert_eq!(store.get_all_jobs_in_progress()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_cancel_running_job() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.metadata.insert("command".to_string(), Value::String("sleep 10".to_string()));
+        test_job_type.retry = Some(RetryPolicy { max_attempts: 2, base_delay_ms: 0, max_delay_ms: 0, jitter: 0.0, retry_on: vec![RetryOutcome::Error] });
+        store.new_job_type(&test_job_type)?;
+        let test_job = make_job(&mut store, &test_job_type)?;
+
+        let started_at = Instant::now();
+        let mut worker_store = store.replicate()?;
+        let worker = thread::spawn(move || run_loop(&mut worker_store));
+        while store.get_all_jobs_in_progress()?.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(store.cancel_job(test_job.uuid)?, CancelResult::Cancelling);
+        worker.join().unwrap();
+        assert!(started_at.elapsed() < Duration::from_millis(5000));
+        // cancelled jobs are finished, not retried
+        let finished_job = store.get_finished_job(test_job.uuid)?.unwrap();
+        assert_eq!(finished_job.errors.unwrap()["kind"], Value::String("cancelled".to_string()));
+        assert_eq!(store.get_all_jobs_delayed()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn will_not_retry_other_outcomes() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.metadata.insert("command".to_string(), Value::String("exit 3".to_string()));
+        test_job_type.retry = Some(RetryPolicy { max_attempts: 2, base_delay_ms: 0, max_delay_ms: 0, jitter: 0.0, retry_on: vec![RetryOutcome::Timeout] });
+        store.new_job_type(&test_job_type)?;
+        make_job(&mut store, &test_job_type)?;
+        run_loop(&mut store);
+        assert_eq!(store.get_all_jobs_finished()?.len(), 1);
+        assert_eq!(store.get_all_jobs_delayed()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_run_jobs_asynchronously() -> Result<(), String> {
+        let mut store = init_store_untyped();
+        store.clean();
+        let test_node_type = make_node_type(&mut store)?;
+        store.set_node_type(test_node_type.uuid)?;
+        let mut test_job_type = make_job_type(&mut store)?;
+        test_job_type.metadata.insert("command".to_string(), Value::String("sleep 1; echo test".to_string()));
+        store.new_job_type(&test_job_type)?;
+        for _ in 0..4 {
+            make_job(&mut store, &test_job_type)?;
+        }
+
+        let started_at = Instant::now();
+        let mut running = run_async_loop(&mut store, vec![], 3);
+        assert_eq!(running.len(), 3);
+        assert_eq!(store.get_all_jobs_waiting()?.len(), 1);
+        // the first three run alongside each other, the fourth once one of them finishes
+        while !running.is_empty() {
+            running = run_async_loop(&mut store, running, 3);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(started_at.elapsed() < Duration::from_millis(3500));
+        let finished_jobs = store.get_all_jobs_finished()?;
+        assert_eq!(finished_jobs.len(), 4);
+        assert!(finished_jobs.iter().all(|job| job.status == JobStatus::Succeeded));
         Ok(())
     }
 }